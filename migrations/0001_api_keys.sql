@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS api_keys (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    key_hash VARCHAR(66) NOT NULL,
+    owner VARCHAR(255) NOT NULL,
+    scopes VARCHAR(1024) NOT NULL DEFAULT '',
+    expires_at BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    is_revoked BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at BIGINT UNSIGNED NOT NULL DEFAULT 0
+);
+
+CREATE UNIQUE INDEX idx_api_keys_key_hash ON api_keys(key_hash);
+CREATE INDEX idx_api_keys_owner ON api_keys(owner);
@@ -0,0 +1,4 @@
+-- records which API key owner uploaded a bundle, existing rows have no owner
+ALTER TABLE bundles ADD COLUMN owner VARCHAR(255) NOT NULL DEFAULT '';
+
+CREATE INDEX idx_bundles_owner_id ON bundles(owner, id);
@@ -0,0 +1,17 @@
+-- per key storage quotas, 0 means unlimited
+ALTER TABLE api_keys ADD COLUMN quota_bytes_per_day BIGINT UNSIGNED NOT NULL DEFAULT 0;
+ALTER TABLE api_keys ADD COLUMN quota_total_bytes BIGINT UNSIGNED NOT NULL DEFAULT 0;
+ALTER TABLE api_keys ADD COLUMN quota_objects BIGINT UNSIGNED NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS usage_ledger (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    key_id VARCHAR(66) NOT NULL,
+    owner VARCHAR(255) NOT NULL,
+    event VARCHAR(32) NOT NULL,
+    optimistic_hash VARCHAR(66) NOT NULL,
+    bytes BIGINT UNSIGNED NOT NULL,
+    created_at BIGINT UNSIGNED NOT NULL
+);
+
+CREATE INDEX idx_usage_ledger_key_event_created ON usage_ledger(key_id, event, created_at);
+CREATE INDEX idx_usage_ledger_optimistic_hash ON usage_ledger(optimistic_hash, event);
@@ -0,0 +1,5 @@
+-- private objects live in S3_PRIVATE_BUCKET_NAME and are only served through
+-- presigned URLs, they are kept out of the public settlement queue
+ALTER TABLE bundles ADD COLUMN is_private BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX idx_bundles_settlement_queue ON bundles(is_settled, is_private, id);
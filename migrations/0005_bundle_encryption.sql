@@ -0,0 +1,8 @@
+-- envelope metadata of encrypted objects, empty for plaintext ones. For
+-- server-side encryption wrapped_key is the data key wrapped by the
+-- encryption_key_id KEK
+ALTER TABLE bundles ADD COLUMN encryption VARCHAR(16) NOT NULL DEFAULT '';
+ALTER TABLE bundles ADD COLUMN encryption_algorithm VARCHAR(64) NOT NULL DEFAULT '';
+ALTER TABLE bundles ADD COLUMN encryption_key_id VARCHAR(255) NOT NULL DEFAULT '';
+ALTER TABLE bundles ADD COLUMN wrapped_key VARCHAR(2048) NOT NULL DEFAULT '';
+ALTER TABLE bundles ADD COLUMN encryption_nonce VARCHAR(255) NOT NULL DEFAULT '';
@@ -0,0 +1,5 @@
+-- compression applied before storage and settlement, with the sizes before and
+-- after it. Rows uploaded before this migration have both sizes at 0
+ALTER TABLE bundles ADD COLUMN compression VARCHAR(16) NOT NULL DEFAULT '';
+ALTER TABLE bundles ADD COLUMN original_size BIGINT UNSIGNED NOT NULL DEFAULT 0;
+ALTER TABLE bundles ADD COLUMN compressed_size BIGINT UNSIGNED NOT NULL DEFAULT 0;
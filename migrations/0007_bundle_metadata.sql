@@ -0,0 +1,13 @@
+-- upload filename, and the x-load-meta-* attributes and tags of each bundle
+ALTER TABLE bundles ADD COLUMN filename VARCHAR(255) NOT NULL DEFAULT '';
+
+CREATE TABLE IF NOT EXISTS bundle_tags (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    optimistic_hash VARCHAR(66) NOT NULL,
+    kind VARCHAR(8) NOT NULL,
+    name VARCHAR(128) NOT NULL,
+    value VARCHAR(1024) NOT NULL DEFAULT ''
+);
+
+CREATE INDEX idx_bundle_tags_optimistic_hash ON bundle_tags(optimistic_hash);
+CREATE INDEX idx_bundle_tags_kind_name_value ON bundle_tags(kind, name, value(255));
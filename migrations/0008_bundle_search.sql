@@ -0,0 +1,7 @@
+-- upload time of each bundle (unix seconds) and the keyset indexes used by
+-- GET /bundles, rows created before this migration keep created_at = 0
+ALTER TABLE bundles ADD COLUMN created_at BIGINT UNSIGNED NOT NULL DEFAULT 0;
+
+CREATE INDEX idx_bundles_owner_created_at ON bundles(owner, created_at, id);
+CREATE INDEX idx_bundles_owner_data_size ON bundles(owner, data_size, id);
+CREATE INDEX idx_bundles_created_at ON bundles(created_at, id);
@@ -0,0 +1,8 @@
+-- settlement time and last update of each bundle (unix seconds), set by
+-- insert_bundle and update_bundle_settled_status
+ALTER TABLE bundles ADD COLUMN settled_at BIGINT UNSIGNED NOT NULL DEFAULT 0;
+ALTER TABLE bundles ADD COLUMN updated_at BIGINT UNSIGNED NOT NULL DEFAULT 0;
+
+UPDATE bundles SET updated_at = created_at;
+
+CREATE INDEX idx_bundles_settled_at ON bundles(is_settled, settled_at);
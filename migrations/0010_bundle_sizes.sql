@@ -0,0 +1,2 @@
+-- object sizes near 2GB overflow a signed INT, sizes are u64 end to end
+ALTER TABLE bundles MODIFY COLUMN data_size BIGINT UNSIGNED;
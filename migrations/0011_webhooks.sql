@@ -0,0 +1,31 @@
+-- webhooks registered per API key, or per upload through ?callback_url=, and
+-- the log of their delivery attempts
+CREATE TABLE IF NOT EXISTS webhooks (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    owner VARCHAR(255) NOT NULL,
+    key_id VARCHAR(66) NOT NULL DEFAULT '',
+    url VARCHAR(2048) NOT NULL,
+    events VARCHAR(255) NOT NULL DEFAULT '',
+    optimistic_hash VARCHAR(66) NOT NULL DEFAULT '',
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at BIGINT UNSIGNED NOT NULL DEFAULT 0
+);
+
+CREATE INDEX idx_webhooks_owner ON webhooks(owner, optimistic_hash, is_active);
+CREATE INDEX idx_webhooks_key_id ON webhooks(key_id, optimistic_hash, is_active);
+CREATE INDEX idx_webhooks_optimistic_hash ON webhooks(optimistic_hash, is_active);
+
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    webhook_id INT NOT NULL,
+    delivery_id VARCHAR(66) NOT NULL,
+    event VARCHAR(32) NOT NULL,
+    optimistic_hash VARCHAR(66) NOT NULL,
+    attempt INT UNSIGNED NOT NULL,
+    status_code INT UNSIGNED NOT NULL DEFAULT 0,
+    success BOOLEAN NOT NULL DEFAULT FALSE,
+    error VARCHAR(1024) NOT NULL DEFAULT '',
+    created_at BIGINT UNSIGNED NOT NULL
+);
+
+CREATE INDEX idx_webhook_deliveries_webhook_id ON webhook_deliveries(webhook_id, id);
@@ -0,0 +1,14 @@
+-- settlement progress of a bundle: its 4MB chunks, their txids once
+-- propagated and the txid of the finalized bundle
+CREATE TABLE IF NOT EXISTS bundle_chunks (
+    optimistic_hash VARCHAR(66) PRIMARY KEY,
+    status VARCHAR(16) NOT NULL DEFAULT 'propagating',
+    chunks_count INT UNSIGNED NOT NULL DEFAULT 0,
+    chunks_propagated INT UNSIGNED NOT NULL DEFAULT 0,
+    -- JSON array
+    chunk_txids MEDIUMTEXT NOT NULL,
+    finalize_txid VARCHAR(66) NOT NULL DEFAULT '',
+    error VARCHAR(1024) NOT NULL DEFAULT '',
+    started_at BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    updated_at BIGINT UNSIGNED NOT NULL DEFAULT 0
+);
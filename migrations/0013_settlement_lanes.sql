@@ -0,0 +1,6 @@
+-- 1 for uploads of the internal key and keys with the `priority` scope, they
+-- settle in the priority lane
+ALTER TABLE bundles ADD COLUMN priority TINYINT UNSIGNED NOT NULL DEFAULT 0;
+
+-- the oldest unsettled bundle of every owner
+CREATE INDEX idx_bundles_settlement_owner ON bundles(is_settled, is_private, owner, id);
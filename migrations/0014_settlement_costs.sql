@@ -0,0 +1,17 @@
+-- estimated and actual cost of every settlement, wei amounts don't fit a
+-- BIGINT
+CREATE TABLE IF NOT EXISTS settlement_costs (
+    optimistic_hash VARCHAR(66) PRIMARY KEY,
+    owner VARCHAR(255) NOT NULL DEFAULT '',
+    data_size BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    chunks_count INT UNSIGNED NOT NULL DEFAULT 0,
+    estimated_gas BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    estimated_cost_wei DECIMAL(39, 0) NOT NULL DEFAULT 0,
+    gas_used BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    cost_wei DECIMAL(39, 0) NOT NULL DEFAULT 0,
+    status VARCHAR(16) NOT NULL DEFAULT 'estimated',
+    created_at BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    updated_at BIGINT UNSIGNED NOT NULL DEFAULT 0
+);
+
+CREATE INDEX idx_settlement_costs_owner ON settlement_costs(owner, status);
@@ -0,0 +1,8 @@
+-- "ephemeral" (kept until expires_at, never settled), "standard" (optimistic,
+-- settled by the orchestrator) or "permanent-now" (settled during the upload)
+ALTER TABLE bundles ADD COLUMN storage_class VARCHAR(16) NOT NULL DEFAULT 'standard';
+ALTER TABLE bundles ADD COLUMN expires_at BIGINT UNSIGNED NOT NULL DEFAULT 0;
+ALTER TABLE bundles ADD COLUMN evicted_at BIGINT UNSIGNED NOT NULL DEFAULT 0;
+
+-- expired ephemeral bundles that are still stored
+CREATE INDEX idx_bundles_expiry ON bundles(storage_class, evicted_at, expires_at);
@@ -0,0 +1,8 @@
+-- the random nonce each owner's webhook signing secret is derived from, a new
+-- one rotates the secret. owners without a row keep the secret derived from
+-- WEBHOOK_SIGNING_SECRET alone
+CREATE TABLE IF NOT EXISTS webhook_secrets (
+    owner VARCHAR(255) PRIMARY KEY,
+    nonce VARCHAR(64) NOT NULL,
+    rotated_at BIGINT UNSIGNED NOT NULL DEFAULT 0
+);
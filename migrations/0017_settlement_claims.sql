@@ -0,0 +1,5 @@
+-- a settlement claims its bundle before propagating it, so the queue and an
+-- inline permanent-now settlement never settle the same bundle twice.
+-- settling_at is refreshed while the settlement runs
+ALTER TABLE bundles ADD COLUMN settling_at BIGINT UNSIGNED NOT NULL DEFAULT 0;
+ALTER TABLE bundles ADD COLUMN settle_claim VARCHAR(36) NOT NULL DEFAULT '';
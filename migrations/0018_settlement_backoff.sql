@@ -0,0 +1,4 @@
+-- a bundle whose settlement failed, or that has to wait for other bundles,
+-- is retried with a backoff so the next bundle of its owner gets a turn
+ALTER TABLE bundles ADD COLUMN settle_attempts INT UNSIGNED NOT NULL DEFAULT 0;
+ALTER TABLE bundles ADD COLUMN next_attempt_at BIGINT UNSIGNED NOT NULL DEFAULT 0;
@@ -4,9 +4,31 @@ CREATE TABLE IF NOT EXISTS bundles (
     id INT AUTO_INCREMENT PRIMARY KEY,
     optimistic_hash VARCHAR(66),
     bundle_txid VARCHAR(66),
-    data_size INT,
+    data_size BIGINT UNSIGNED,
     is_settled BOOLEAN,
-    content_type VARCHAR(255)
+    content_type VARCHAR(255),
+    owner VARCHAR(255) NOT NULL DEFAULT '',
+    is_private BOOLEAN NOT NULL DEFAULT FALSE,
+    encryption VARCHAR(16) NOT NULL DEFAULT '',
+    encryption_algorithm VARCHAR(64) NOT NULL DEFAULT '',
+    encryption_key_id VARCHAR(255) NOT NULL DEFAULT '',
+    wrapped_key VARCHAR(2048) NOT NULL DEFAULT '',
+    encryption_nonce VARCHAR(255) NOT NULL DEFAULT '',
+    compression VARCHAR(16) NOT NULL DEFAULT '',
+    original_size BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    compressed_size BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    filename VARCHAR(255) NOT NULL DEFAULT '',
+    created_at BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    settled_at BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    updated_at BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    priority TINYINT UNSIGNED NOT NULL DEFAULT 0,
+    storage_class VARCHAR(16) NOT NULL DEFAULT 'standard',
+    expires_at BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    evicted_at BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    settling_at BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    settle_claim VARCHAR(36) NOT NULL DEFAULT '',
+    settle_attempts INT UNSIGNED NOT NULL DEFAULT 0,
+    next_attempt_at BIGINT UNSIGNED NOT NULL DEFAULT 0
 );
 
 CREATE INDEX idx_bundles_id ON bundles(id);
@@ -16,3 +38,132 @@ CREATE INDEX idx_bundles_data_size ON bundles(data_size);
 CREATE INDEX idx_bundles_is_settled ON bundles(is_settled);
 CREATE INDEX idx_bundles_content_type ON bundles(content_type);
 CREATE INDEX idx_is_settled_data_size ON bundles(is_settled, data_size);
+CREATE INDEX idx_bundles_settlement_queue ON bundles(is_settled, is_private, id);
+CREATE INDEX idx_bundles_owner_id ON bundles(owner, id);
+CREATE INDEX idx_bundles_owner_created_at ON bundles(owner, created_at, id);
+CREATE INDEX idx_bundles_owner_data_size ON bundles(owner, data_size, id);
+CREATE INDEX idx_bundles_created_at ON bundles(created_at, id);
+CREATE INDEX idx_bundles_settled_at ON bundles(is_settled, settled_at);
+CREATE INDEX idx_bundles_settlement_owner ON bundles(is_settled, is_private, owner, id);
+CREATE INDEX idx_bundles_expiry ON bundles(storage_class, evicted_at, expires_at);
+
+DROP TABLE IF EXISTS api_keys;
+
+CREATE TABLE IF NOT EXISTS api_keys (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    key_hash VARCHAR(66) NOT NULL,
+    owner VARCHAR(255) NOT NULL,
+    scopes VARCHAR(1024) NOT NULL DEFAULT '',
+    expires_at BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    is_revoked BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    quota_bytes_per_day BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    quota_total_bytes BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    quota_objects BIGINT UNSIGNED NOT NULL DEFAULT 0
+);
+
+CREATE UNIQUE INDEX idx_api_keys_key_hash ON api_keys(key_hash);
+CREATE INDEX idx_api_keys_owner ON api_keys(owner);
+
+DROP TABLE IF EXISTS usage_ledger;
+
+CREATE TABLE IF NOT EXISTS usage_ledger (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    key_id VARCHAR(66) NOT NULL,
+    owner VARCHAR(255) NOT NULL,
+    event VARCHAR(32) NOT NULL,
+    optimistic_hash VARCHAR(66) NOT NULL,
+    bytes BIGINT UNSIGNED NOT NULL,
+    created_at BIGINT UNSIGNED NOT NULL
+);
+
+CREATE INDEX idx_usage_ledger_key_event_created ON usage_ledger(key_id, event, created_at);
+CREATE INDEX idx_usage_ledger_optimistic_hash ON usage_ledger(optimistic_hash, event);
+
+DROP TABLE IF EXISTS bundle_tags;
+
+CREATE TABLE IF NOT EXISTS bundle_tags (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    optimistic_hash VARCHAR(66) NOT NULL,
+    kind VARCHAR(8) NOT NULL,
+    name VARCHAR(128) NOT NULL,
+    value VARCHAR(1024) NOT NULL DEFAULT ''
+);
+
+CREATE INDEX idx_bundle_tags_optimistic_hash ON bundle_tags(optimistic_hash);
+CREATE INDEX idx_bundle_tags_kind_name_value ON bundle_tags(kind, name, value(255));
+
+DROP TABLE IF EXISTS webhooks;
+
+CREATE TABLE IF NOT EXISTS webhooks (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    owner VARCHAR(255) NOT NULL,
+    key_id VARCHAR(66) NOT NULL DEFAULT '',
+    url VARCHAR(2048) NOT NULL,
+    events VARCHAR(255) NOT NULL DEFAULT '',
+    optimistic_hash VARCHAR(66) NOT NULL DEFAULT '',
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at BIGINT UNSIGNED NOT NULL DEFAULT 0
+);
+
+CREATE INDEX idx_webhooks_owner ON webhooks(owner, optimistic_hash, is_active);
+CREATE INDEX idx_webhooks_key_id ON webhooks(key_id, optimistic_hash, is_active);
+CREATE INDEX idx_webhooks_optimistic_hash ON webhooks(optimistic_hash, is_active);
+
+DROP TABLE IF EXISTS webhook_deliveries;
+
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+    webhook_id INT NOT NULL,
+    delivery_id VARCHAR(66) NOT NULL,
+    event VARCHAR(32) NOT NULL,
+    optimistic_hash VARCHAR(66) NOT NULL,
+    attempt INT UNSIGNED NOT NULL,
+    status_code INT UNSIGNED NOT NULL DEFAULT 0,
+    success BOOLEAN NOT NULL DEFAULT FALSE,
+    error VARCHAR(1024) NOT NULL DEFAULT '',
+    created_at BIGINT UNSIGNED NOT NULL
+);
+
+CREATE INDEX idx_webhook_deliveries_webhook_id ON webhook_deliveries(webhook_id, id);
+
+DROP TABLE IF EXISTS webhook_secrets;
+
+CREATE TABLE IF NOT EXISTS webhook_secrets (
+    owner VARCHAR(255) PRIMARY KEY,
+    nonce VARCHAR(64) NOT NULL,
+    rotated_at BIGINT UNSIGNED NOT NULL DEFAULT 0
+);
+
+DROP TABLE IF EXISTS bundle_chunks;
+
+CREATE TABLE IF NOT EXISTS bundle_chunks (
+    optimistic_hash VARCHAR(66) PRIMARY KEY,
+    status VARCHAR(16) NOT NULL DEFAULT 'propagating',
+    chunks_count INT UNSIGNED NOT NULL DEFAULT 0,
+    chunks_propagated INT UNSIGNED NOT NULL DEFAULT 0,
+    -- JSON array
+    chunk_txids MEDIUMTEXT NOT NULL,
+    finalize_txid VARCHAR(66) NOT NULL DEFAULT '',
+    error VARCHAR(1024) NOT NULL DEFAULT '',
+    started_at BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    updated_at BIGINT UNSIGNED NOT NULL DEFAULT 0
+);
+
+DROP TABLE IF EXISTS settlement_costs;
+
+CREATE TABLE IF NOT EXISTS settlement_costs (
+    optimistic_hash VARCHAR(66) PRIMARY KEY,
+    owner VARCHAR(255) NOT NULL DEFAULT '',
+    data_size BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    chunks_count INT UNSIGNED NOT NULL DEFAULT 0,
+    estimated_gas BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    estimated_cost_wei DECIMAL(39, 0) NOT NULL DEFAULT 0,
+    gas_used BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    cost_wei DECIMAL(39, 0) NOT NULL DEFAULT 0,
+    status VARCHAR(16) NOT NULL DEFAULT 'estimated',
+    created_at BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    updated_at BIGINT UNSIGNED NOT NULL DEFAULT 0
+);
+
+CREATE INDEX idx_settlement_costs_owner ON settlement_costs(owner, status);
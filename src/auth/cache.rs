@@ -0,0 +1,173 @@
+use crate::auth::provider::{ApiKeyIdentity, AuthProvider, unix_now};
+use crate::utils::hash::keccak_hex;
+use anyhow::Error;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+const MAX_CACHE_ENTRIES: usize = 100_000;
+// unknown tokens are cheap to make up, they are capped apart so they can't
+// push valid keys out of the cache
+const MAX_CACHED_MISSES: usize = 10_000;
+
+type CacheEntries<T> = HashMap<String, (Instant, T)>;
+
+// caches both positive and negative verification results for `ttl`, so a
+// revoked key may keep working on a replica for at most `ttl`
+pub struct CachedAuthProvider {
+    inner: Arc<dyn AuthProvider>,
+    ttl: Duration,
+    entries: RwLock<CacheEntries<ApiKeyIdentity>>,
+    misses: RwLock<CacheEntries<()>>,
+}
+
+impl CachedAuthProvider {
+    pub fn new(inner: Arc<dyn AuthProvider>, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            entries: RwLock::new(HashMap::new()),
+            misses: RwLock::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<Option<ApiKeyIdentity>> {
+        if let Some((inserted_at, identity)) = self.entries.read().unwrap().get(key)
+            && inserted_at.elapsed() < self.ttl
+        {
+            if identity.is_expired(unix_now()) {
+                return Some(None);
+            }
+            return Some(Some(identity.clone()));
+        }
+        match self.misses.read().unwrap().get(key) {
+            Some((inserted_at, ())) if inserted_at.elapsed() < self.ttl => Some(None),
+            _ => None,
+        }
+    }
+
+    fn insert(&self, key: String, identity: Option<ApiKeyIdentity>) {
+        match identity {
+            Some(identity) => insert_bounded(
+                &mut self.entries.write().unwrap(),
+                MAX_CACHE_ENTRIES,
+                self.ttl,
+                key,
+                identity,
+            ),
+            None => insert_bounded(
+                &mut self.misses.write().unwrap(),
+                MAX_CACHED_MISSES,
+                self.ttl,
+                key,
+                (),
+            ),
+        }
+    }
+}
+
+// at capacity the stale entries go first, then the oldest ones
+fn insert_bounded<T>(
+    entries: &mut CacheEntries<T>,
+    capacity: usize,
+    ttl: Duration,
+    key: String,
+    value: T,
+) {
+    if entries.len() >= capacity && !entries.contains_key(&key) {
+        entries.retain(|_, (inserted_at, _)| inserted_at.elapsed() < ttl);
+        if entries.len() >= capacity
+            && let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, (inserted_at, _))| *inserted_at)
+                .map(|(key, _)| key.clone())
+        {
+            entries.remove(&oldest);
+        }
+    }
+    entries.insert(key, (Instant::now(), value));
+}
+
+#[async_trait]
+impl AuthProvider for CachedAuthProvider {
+    async fn verify(&self, token: &str) -> Result<Option<ApiKeyIdentity>, Error> {
+        let key = keccak_hex(token.as_bytes());
+        if let Some(identity) = self.get(&key) {
+            return Ok(identity);
+        }
+
+        // backend errors are not cached so the next request retries
+        let identity = self.inner.verify(token).await?;
+        self.insert(key, identity.clone());
+        Ok(identity)
+    }
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::auth::cache::{CachedAuthProvider, insert_bounded};
+    use crate::auth::provider::{ApiKeyIdentity, AuthProvider, SCOPE_READ, StorageQuota};
+    use anyhow::Error;
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    struct CountingProvider {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl AuthProvider for CountingProvider {
+        async fn verify(&self, token: &str) -> Result<Option<ApiKeyIdentity>, Error> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if token == "valid" {
+                Ok(Some(ApiKeyIdentity {
+                    key_id: "1".to_string(),
+                    owner: "team".to_string(),
+                    scopes: vec![SCOPE_READ.to_string()],
+                    expires_at: 0,
+                    quota: StorageQuota::default(),
+                    is_internal: false,
+                }))
+            } else {
+                Ok(None)
+            }
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_cached_auth_provider() {
+        let inner = Arc::new(CountingProvider {
+            calls: AtomicUsize::new(0),
+        });
+        let cache = CachedAuthProvider::new(inner.clone(), Duration::from_millis(50));
+
+        assert!(cache.verify("valid").await.unwrap().is_some());
+        assert!(cache.verify("valid").await.unwrap().is_some());
+        assert!(cache.verify("invalid").await.unwrap().is_none());
+        assert!(cache.verify("invalid").await.unwrap().is_none());
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(cache.verify("valid").await.unwrap().is_some());
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 3);
+
+        // a full cache drops its oldest entry, not every entry
+        let mut entries = HashMap::new();
+        for key in ["a", "b", "c"] {
+            insert_bounded(
+                &mut entries,
+                2,
+                Duration::from_secs(60),
+                key.to_string(),
+                (),
+            );
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        assert_eq!(entries.len(), 2);
+        assert!(!entries.contains_key("a"));
+    }
+}
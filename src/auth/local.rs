@@ -0,0 +1,44 @@
+use crate::auth::is_reserved_owner;
+use crate::auth::provider::{ApiKeyIdentity, AuthProvider, StorageQuota, parse_scopes, unix_now};
+use crate::orchestrator::db::get_api_key_by_hash;
+use crate::utils::hash::keccak_hex;
+use anyhow::Error;
+use async_trait::async_trait;
+
+// API keys issued by load0 itself, only the keccak hash of a key is stored
+pub struct LocalAuthProvider;
+
+#[async_trait]
+impl AuthProvider for LocalAuthProvider {
+    async fn verify(&self, token: &str) -> Result<Option<ApiKeyIdentity>, Error> {
+        let key_hash = keccak_hex(token.as_bytes());
+        let api_key = match get_api_key_by_hash(&key_hash).await? {
+            Some(api_key) => api_key,
+            None => return Ok(None),
+        };
+
+        // keys can't be minted for the owner of the internal key
+        if api_key.is_revoked || is_reserved_owner(&api_key.owner) {
+            return Ok(None);
+        }
+
+        let identity = ApiKeyIdentity {
+            key_id: api_key.id.to_string(),
+            owner: api_key.owner,
+            scopes: parse_scopes(&api_key.scopes),
+            expires_at: api_key.expires_at,
+            quota: StorageQuota {
+                bytes_per_day: api_key.quota_bytes_per_day,
+                total_bytes: api_key.quota_total_bytes,
+                objects: api_key.quota_objects,
+            },
+            is_internal: false,
+        };
+
+        if identity.is_expired(unix_now()) {
+            return Ok(None);
+        }
+
+        Ok(Some(identity))
+    }
+}
@@ -0,0 +1,206 @@
+pub mod cache;
+pub mod local;
+pub mod provider;
+pub mod remote;
+
+use crate::auth::cache::CachedAuthProvider;
+use crate::auth::local::LocalAuthProvider;
+use crate::auth::provider::{ApiKeyIdentity, AuthProvider, SCOPE_ALL, SCOPE_READ, StorageQuota};
+use crate::auth::remote::RemoteAuthProvider;
+use crate::server::rate_limiter::LOAD_HEADER_NAME;
+use crate::r#static::INTERNAL_KEY;
+use crate::utils::get_env::{env_var_to_vec, get_env_var};
+use anyhow::Error;
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use reqwest::Client;
+use std::sync::Arc;
+use std::time::Duration;
+
+// an unset BYPASS_INTERNAL_KEY must not turn an empty header into the internal tier
+pub fn is_internal_key(token: &str) -> bool {
+    let internal_key = &*INTERNAL_KEY;
+    !internal_key.is_empty() && token == internal_key
+}
+
+pub const INTERNAL_OWNER: &str = "internal";
+
+// resolves the X-Load-Authorization header of a request to an identity, the
+// internal key maps to an `internal` owner with every scope and is_internal
+pub async fn identify(
+    auth: &dyn AuthProvider,
+    headers: &HeaderMap,
+) -> Result<Option<ApiKeyIdentity>, Error> {
+    let token = match headers.get(LOAD_HEADER_NAME).and_then(|h| h.to_str().ok()) {
+        Some(token) if !token.is_empty() => token,
+        _ => return Ok(None),
+    };
+
+    if is_internal_key(token) {
+        return Ok(Some(ApiKeyIdentity {
+            key_id: INTERNAL_OWNER.to_string(),
+            owner: INTERNAL_OWNER.to_string(),
+            scopes: vec![SCOPE_ALL.to_string()],
+            expires_at: 0,
+            quota: StorageQuota::default(),
+            is_internal: true,
+        }));
+    }
+
+    // whatever a provider returns, the internal owner is reserved
+    let identity = auth.verify(token).await?;
+    Ok(identity.filter(|identity| !is_reserved_owner(&identity.owner)))
+}
+
+// whether the request may read the owner only data of a bundle of `owner`,
+// i.e. it carries a read key of that owner or the internal key
+pub async fn is_bundle_reader(
+    auth: &dyn AuthProvider,
+    headers: &HeaderMap,
+    owner: &str,
+) -> Result<bool, Error> {
+    Ok(match identify(auth, headers).await? {
+        Some(identity) => {
+            identity.has_scope(SCOPE_READ)
+                && (identity.is_internal || (!owner.is_empty() && identity.owner == owner))
+        }
+        None => false,
+    })
+}
+
+pub fn is_reserved_owner(owner: &str) -> bool {
+    owner == INTERNAL_OWNER
+}
+
+// owners end up in SQL strings and object listings, keep them to a safe charset
+pub fn is_valid_owner(owner: &str) -> bool {
+    !owner.is_empty()
+        && owner.len() <= 255
+        && owner
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@' | ':'))
+}
+
+// tries each provider in order and returns the first identity found
+pub struct ChainedAuthProvider {
+    providers: Vec<Arc<dyn AuthProvider>>,
+}
+
+impl ChainedAuthProvider {
+    pub fn new(providers: Vec<Arc<dyn AuthProvider>>) -> Self {
+        Self { providers }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for ChainedAuthProvider {
+    async fn verify(&self, token: &str) -> Result<Option<ApiKeyIdentity>, Error> {
+        let mut last_error = None;
+        for provider in &self.providers {
+            match provider.verify(token).await {
+                Ok(Some(identity)) => return Ok(Some(identity)),
+                Ok(None) => continue,
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        match last_error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+}
+
+fn get_auth_cache_ttl() -> Duration {
+    let secs = std::env::var("AUTH_CACHE_TTL_SECS")
+        .ok()
+        .and_then(|val| val.parse::<u64>().ok())
+        .unwrap_or(60);
+    Duration::from_secs(secs)
+}
+
+// AUTH_PROVIDERS is a comma separated list of `local` and `remote`
+pub fn init_auth_provider(http_client: Client) -> Arc<dyn AuthProvider> {
+    let mut names = env_var_to_vec("AUTH_PROVIDERS");
+    if names.is_empty() {
+        names = vec!["local".to_string(), "remote".to_string()];
+    }
+
+    let mut providers: Vec<Arc<dyn AuthProvider>> = Vec::new();
+    for name in names {
+        match name.as_str() {
+            "local" => providers.push(Arc::new(LocalAuthProvider)),
+            "remote" => providers.push(Arc::new(RemoteAuthProvider::new(
+                http_client.clone(),
+                get_env_var("AUTH_API_URL").ok(),
+            ))),
+            other => println!("Unknown auth provider in AUTH_PROVIDERS: {}", other),
+        }
+    }
+
+    Arc::new(CachedAuthProvider::new(
+        Arc::new(ChainedAuthProvider::new(providers)),
+        get_auth_cache_ttl(),
+    ))
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::auth::is_bundle_reader;
+    use crate::auth::provider::{
+        ApiKeyIdentity, AuthProvider, SCOPE_READ, SCOPE_UPLOAD, StorageQuota,
+    };
+    use crate::server::rate_limiter::LOAD_HEADER_NAME;
+    use anyhow::Error;
+    use async_trait::async_trait;
+    use axum::http::HeaderMap;
+
+    struct StaticProvider;
+
+    #[async_trait]
+    impl AuthProvider for StaticProvider {
+        async fn verify(&self, token: &str) -> Result<Option<ApiKeyIdentity>, Error> {
+            let (owner, scope) = match token {
+                "team-read" => ("team", SCOPE_READ),
+                "team-upload" => ("team", SCOPE_UPLOAD),
+                "other-read" => ("other", SCOPE_READ),
+                _ => return Ok(None),
+            };
+            Ok(Some(ApiKeyIdentity {
+                key_id: token.to_string(),
+                owner: owner.to_string(),
+                scopes: vec![scope.to_string()],
+                expires_at: 0,
+                quota: StorageQuota::default(),
+                is_internal: false,
+            }))
+        }
+    }
+
+    fn headers(token: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(token) = token {
+            headers.insert(LOAD_HEADER_NAME, token.parse().unwrap());
+        }
+        headers
+    }
+
+    #[tokio::test]
+    pub async fn test_is_bundle_reader() {
+        let reader = |token, owner| async move {
+            is_bundle_reader(&StaticProvider, &headers(token), owner)
+                .await
+                .unwrap()
+        };
+
+        assert!(reader(Some("team-read"), "team").await);
+        // anonymous, another owner, a key without the read scope or an
+        // unknown one are all denied
+        assert!(!reader(None, "team").await);
+        assert!(!reader(Some("other-read"), "team").await);
+        assert!(!reader(Some("team-upload"), "team").await);
+        assert!(!reader(Some("unknown"), "team").await);
+        // bundles uploaded without a key have no owner to match
+        assert!(!reader(Some("team-read"), "").await);
+    }
+}
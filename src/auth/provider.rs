@@ -0,0 +1,70 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+pub const SCOPE_ALL: &str = "*";
+pub const SCOPE_READ: &str = "read";
+pub const SCOPE_UPLOAD: &str = "upload";
+// uploads settle in the priority lane, for paid keys
+pub const SCOPE_PRIORITY: &str = "priority";
+pub const KNOWN_SCOPES: [&str; 4] = [SCOPE_ALL, SCOPE_READ, SCOPE_UPLOAD, SCOPE_PRIORITY];
+
+// storage limits attached to an API key, 0 means unlimited
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct StorageQuota {
+    pub bytes_per_day: u64,
+    pub total_bytes: u64,
+    pub objects: u64,
+}
+
+impl StorageQuota {
+    pub fn is_unlimited(&self) -> bool {
+        self.bytes_per_day == 0 && self.total_bytes == 0 && self.objects == 0
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ApiKeyIdentity {
+    pub key_id: String,
+    pub owner: String,
+    pub scopes: Vec<String>,
+    // unix timestamp in seconds, 0 means the key never expires
+    pub expires_at: u64,
+    pub quota: StorageQuota,
+    // only the BYPASS_INTERNAL_KEY, see auth::identify. Privileges hang off
+    // this, never off the owner name
+    #[serde(default)]
+    pub is_internal: bool,
+}
+
+impl ApiKeyIdentity {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == SCOPE_ALL || s == scope)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at != 0 && self.expires_at <= now
+    }
+}
+
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    // Ok(None) means the token is unknown, revoked or expired; Err is reserved
+    // for backend failures so callers (and the cache) can tell them apart
+    async fn verify(&self, token: &str) -> Result<Option<ApiKeyIdentity>, Error>;
+}
+
+pub fn parse_scopes(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
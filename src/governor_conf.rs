@@ -1,17 +1,189 @@
 use crate::server::rate_limiter::XLoadAuthHeaderExtractor;
-use governor::middleware::{NoOpMiddleware, StateInformationMiddleware};
+use governor::middleware::StateInformationMiddleware;
+use serde::{Deserialize, Serialize};
 use tower_governor::governor::{GovernorConfig, GovernorConfigBuilder};
 
 pub fn get_governor_conf(
-    burst_per_minute: u32,
+    burst_size: u32,
+    replenish_secs: u64,
 ) -> GovernorConfig<XLoadAuthHeaderExtractor, StateInformationMiddleware> {
     let governor_conf = GovernorConfigBuilder::default()
-        .per_second(60)
+        .per_second(replenish_secs)
         .use_headers()
-        .burst_size(burst_per_minute)
+        .burst_size(burst_size)
         .key_extractor(XLoadAuthHeaderExtractor)
         .finish()
-        .unwrap();
+        .unwrap_or_else(|| {
+            panic!(
+                "Invalid rate limit: a burst of {} replenished every {}s",
+                burst_size, replenish_secs
+            )
+        });
 
     governor_conf
 }
+
+// limits applied to every request of a tier, a 0 burst or byte rate disables
+// that limit entirely
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TierLimits {
+    pub request_burst: u32,
+    pub request_replenish_secs: u64,
+    pub upload_burst: u32,
+    pub upload_replenish_secs: u64,
+    pub ingress_bytes_per_sec: u64,
+    pub egress_bytes_per_sec: u64,
+}
+
+impl TierLimits {
+    // a limited burst needs a replenish period, governor rejects a 0 one
+    pub fn validate(&self, tier: &str) -> Result<(), String> {
+        for (field, burst, replenish_secs) in [
+            ("request", self.request_burst, self.request_replenish_secs),
+            ("upload", self.upload_burst, self.upload_replenish_secs),
+        ] {
+            if burst > 0 && replenish_secs == 0 {
+                return Err(format!(
+                    "{}.{}_replenish_secs must be greater than 0 when {}.{}_burst is set",
+                    tier, field, tier, field
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub const fn unlimited() -> Self {
+        Self {
+            request_burst: 0,
+            request_replenish_secs: 60,
+            upload_burst: 0,
+            upload_replenish_secs: 60,
+            ingress_bytes_per_sec: 0,
+            egress_bytes_per_sec: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RateLimitTiers {
+    // no or unknown X-Load-Authorization header
+    pub anonymous: TierLimits,
+    // a valid API key
+    pub authenticated: TierLimits,
+    // the BYPASS_INTERNAL_KEY
+    pub internal: TierLimits,
+    // origin tokens, WHITELISTED_CIDRS and mTLS identities, see Whitelist
+    pub whitelisted: TierLimits,
+    // anonymous requests from a WHITELISTED_ORIGINS site, limited since the
+    // headers can be forged
+    pub browser: TierLimits,
+    // how many seconds of traffic a byte rate limit lets through in one burst
+    pub byte_burst_secs: u32,
+}
+
+impl RateLimitTiers {
+    pub fn validate(&self) -> Result<(), String> {
+        self.anonymous.validate("anonymous")?;
+        self.authenticated.validate("authenticated")?;
+        self.internal.validate("internal")?;
+        self.whitelisted.validate("whitelisted")?;
+        self.browser.validate("browser")
+    }
+}
+
+impl Default for RateLimitTiers {
+    fn default() -> Self {
+        Self {
+            anonymous: TierLimits {
+                request_burst: 6,
+                upload_burst: 6,
+                ..TierLimits::unlimited()
+            },
+            authenticated: TierLimits {
+                request_burst: 60,
+                upload_burst: 60,
+                ..TierLimits::unlimited()
+            },
+            internal: TierLimits {
+                request_burst: 999999,
+                upload_burst: 999999,
+                ..TierLimits::unlimited()
+            },
+            whitelisted: TierLimits::unlimited(),
+            browser: TierLimits {
+                request_burst: 60,
+                upload_burst: 60,
+                ..TierLimits::unlimited()
+            },
+            byte_burst_secs: 10,
+        }
+    }
+}
+
+// RATE_LIMIT_CONFIG points to a JSON file with any of the RateLimitTiers
+// fields, tiers missing from the file keep their defaults
+pub fn get_rate_limit_tiers() -> RateLimitTiers {
+    let path = match std::env::var("RATE_LIMIT_CONFIG") {
+        Ok(path) if !path.trim().is_empty() => path,
+        _ => return RateLimitTiers::default(),
+    };
+
+    let raw = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Unable to read RATE_LIMIT_CONFIG {}: {}", path, e));
+    let tiers: RateLimitTiers = serde_json::from_str(&raw)
+        .unwrap_or_else(|e| panic!("Invalid RATE_LIMIT_CONFIG {}: {}", path, e));
+    if let Err(e) = tiers.validate() {
+        panic!("Invalid RATE_LIMIT_CONFIG {}: {}", path, e);
+    }
+    tiers
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::governor_conf::{RateLimitTiers, TierLimits};
+
+    #[test]
+    pub fn test_rate_limit_tiers_from_json() {
+        let tiers: RateLimitTiers = serde_json::from_str(
+            r#"{
+                "anonymous": {
+                    "request_burst": 10,
+                    "request_replenish_secs": 30,
+                    "upload_burst": 1,
+                    "upload_replenish_secs": 300,
+                    "ingress_bytes_per_sec": 1048576,
+                    "egress_bytes_per_sec": 2097152
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(tiers.anonymous.request_burst, 10);
+        assert_eq!(tiers.anonymous.upload_replenish_secs, 300);
+        assert_eq!(tiers.anonymous.egress_bytes_per_sec, 2097152);
+        assert_eq!(tiers.authenticated, RateLimitTiers::default().authenticated);
+        assert_eq!(tiers.whitelisted, TierLimits::unlimited());
+        assert_ne!(tiers.browser, TierLimits::unlimited());
+        assert_eq!(tiers.byte_burst_secs, 10);
+        assert!(tiers.validate().is_ok());
+        assert!(RateLimitTiers::default().validate().is_ok());
+
+        // a burst that never replenishes names the bad field
+        let tiers: RateLimitTiers = serde_json::from_str(
+            r#"{"browser": {"request_burst": 10, "request_replenish_secs": 60, "upload_burst": 5, "upload_replenish_secs": 0, "ingress_bytes_per_sec": 0, "egress_bytes_per_sec": 0}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            tiers.validate().unwrap_err(),
+            "browser.upload_replenish_secs must be greater than 0 when browser.upload_burst is set"
+        );
+
+        // a disabled limit needs no replenish period
+        let tiers: RateLimitTiers = serde_json::from_str(
+            r#"{"whitelisted": {"request_burst": 0, "request_replenish_secs": 0, "upload_burst": 0, "upload_replenish_secs": 0, "ingress_bytes_per_sec": 0, "egress_bytes_per_sec": 0}}"#,
+        )
+        .unwrap();
+        assert!(tiers.validate().is_ok());
+    }
+}
@@ -1,26 +1,39 @@
-use axum::{Router, routing::get, routing::post};
+use axum::{Router, routing::delete, routing::get, routing::post};
 use std::net::SocketAddr;
 use std::time::Duration;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::limit::RequestBodyLimitLayer;
 use tower_http::timeout::TimeoutLayer;
 
+use crate::auth::provider::{SCOPE_READ, unix_now};
+use crate::auth::{identify, init_auth_provider};
 use crate::booter::Booter;
-use crate::governor_conf::get_governor_conf;
+use crate::governor_conf::{TierLimits, get_governor_conf, get_rate_limit_tiers};
 use crate::orchestrator::cron::update;
-use crate::orchestrator::db::get_unsettled_bundles;
+use crate::orchestrator::eviction::evict_expired;
+use crate::orchestrator::stats::{StatsCache, get_stats_cache_ttl};
+use crate::server::bandwidth::{BandwidthLimits, bandwidth_limit};
 use crate::server::handlers::{
-    bundles_stats_handler, download_object_handler, get_bundle_by_load_txid_handler,
-    get_bundle_by_op_hash_handler, server_status_handler, upload_binary_handler,
+    bundle_settlement_handler, bundles_stats_handler, create_api_key_handler,
+    create_origin_token_handler, create_webhook_handler, delete_webhook_handler,
+    download_object_handler, estimate_handler, events_sse_handler, events_ws_handler,
+    get_bundle_by_load_txid_handler, get_bundle_by_op_hash_handler, list_bundles_handler,
+    list_webhooks_handler, resolve_manifest_index_handler, resolve_manifest_path_handler,
+    revoke_api_key_handler, rotate_webhook_secret_handler, server_status_handler,
+    sign_bundle_handler, upload_binary_handler, usage_handler, webhook_deliveries_handler,
 };
-use crate::server::rate_limiter::{
-    LOAD_HEADER_NAME, XLoadAuthHeaderExtractor, is_whitelisted, whitelisted_urls,
+use crate::server::limit_headers::{RateLimitPolicy, rate_limit_headers, tier_header};
+use crate::server::rate_limiter::{RateLimitKey, XLoadAuthHeaderExtractor};
+use crate::server::shared_limiter::{
+    RateLimitStore, SharedRateLimit, init_rate_limit_store, shared_rate_limit,
 };
 use crate::server::types::AppState;
-use crate::r#static::INTERNAL_KEY;
-use crate::utils::auth::is_access_token_valid;
-use crate::utils::constants::SERVER_REQUEST_BODY_LIMIT;
+use crate::server::whitelist::Whitelist;
+use crate::r#static::{MAX_UPLOAD_BYTES, TRUSTED_PROXIES};
+use crate::utils::constants::{SETTLEMENT_BUSY_DELAY_SECS, SETTLEMENT_IDLE_DELAY_SECS};
+use crate::utils::encryption::KeyRing;
 use crate::utils::get_env::get_env_var;
+use axum::extract::ConnectInfo;
 use axum::handler::HandlerWithoutStateExt;
 use axum::http::Request;
 use reqwest::Client;
@@ -30,6 +43,7 @@ use tower_governor::GovernorLayer;
 use tower_governor::governor::GovernorConfigBuilder;
 use url::Url;
 
+pub mod auth;
 mod booter;
 pub mod core;
 mod governor_conf;
@@ -43,15 +57,25 @@ async fn init_app_state() -> Result<AppState, anyhow::Error> {
     let supabase_url = get_env_var("SUPABASE_URL").unwrap();
     let api_key = get_env_var("SUPABASE_API_KEY").unwrap();
     let bucket_name = get_env_var("S3_BUCKET_NAME").unwrap();
+    let private_bucket_name = std::env::var("S3_PRIVATE_BUCKET_NAME").unwrap_or_default();
 
     // Create HTTP client
     let http_client = Client::new();
+    let auth = init_auth_provider(http_client.clone());
+    let rate_limit_store = init_rate_limit_store();
+    let key_ring = Arc::new(KeyRing::from_env()?);
+    let stats_cache = Arc::new(StatsCache::new(get_stats_cache_ttl()));
 
     Ok(AppState {
         http_client,
         supabase_url,
         bucket_name,
+        private_bucket_name,
         api_key,
+        auth,
+        rate_limit_store,
+        key_ring,
+        stats_cache,
     })
 }
 
@@ -67,20 +91,128 @@ fn retrieval_routes() -> Router<Arc<AppState>> {
         .route("/download/{optimistic_hash}", get(download_object_handler))
         // to maintain same route as gateway.load.rs
         .route("/resolve/{optimistic_hash}", get(download_object_handler))
+        .route(
+            "/resolve/{optimistic_hash}/",
+            get(resolve_manifest_index_handler),
+        )
+        .route(
+            "/resolve/{optimistic_hash}/{*path}",
+            get(resolve_manifest_path_handler),
+        )
         .route(
             "/bundle/optimistic/{op_hash}",
             get(get_bundle_by_op_hash_handler),
         )
+        .route(
+            "/bundle/optimistic/{op_hash}/settlement",
+            get(bundle_settlement_handler),
+        )
         .route(
             "/bundle/load/{bundle_txid}",
             get(get_bundle_by_load_txid_handler),
         )
+        .route("/bundle/{optimistic_hash}/sign", post(sign_bundle_handler))
+        .route("/bundles", get(list_bundles_handler))
+        .route("/usage", get(usage_handler))
+        .route("/estimate", get(estimate_handler))
+        .route("/events", get(events_sse_handler))
+        .route("/events/ws", get(events_ws_handler))
+        .route(
+            "/webhooks",
+            get(list_webhooks_handler).post(create_webhook_handler),
+        )
+        .route(
+            "/webhooks/secret/rotate",
+            post(rotate_webhook_secret_handler),
+        )
+        .route("/webhooks/{id}", delete(delete_webhook_handler))
+        .route("/webhooks/{id}/deliveries", get(webhook_deliveries_handler))
 }
 
-fn retrieval_route_with_burst(burst_size_per_min: u32) -> Router<Arc<AppState>> {
-    retrieval_routes().layer(GovernorLayer {
-        config: Arc::new(get_governor_conf(burst_size_per_min)),
-    })
+fn with_burst(
+    router: Router<Arc<AppState>>,
+    burst_size: u32,
+    replenish_secs: u64,
+    tier: &str,
+    bucket: &str,
+    shared_store: Option<Arc<dyn RateLimitStore>>,
+) -> Router<Arc<AppState>> {
+    if burst_size == 0 {
+        return router;
+    }
+
+    // with a shared store the limit is enforced across every replica
+    let router = match shared_store {
+        Some(store) => router.layer(axum::middleware::from_fn_with_state(
+            SharedRateLimit {
+                store,
+                namespace: format!("{}:{}", tier, bucket),
+                burst_size,
+                replenish_secs,
+            },
+            shared_rate_limit,
+        )),
+        None => router.layer(GovernorLayer {
+            config: Arc::new(get_governor_conf(burst_size, replenish_secs)),
+        }),
+    };
+
+    router.layer(axum::middleware::from_fn_with_state(
+        RateLimitPolicy {
+            tier: tier.to_string(),
+            burst_size,
+            replenish_secs,
+        },
+        rate_limit_headers,
+    ))
+}
+
+// every tier gets its own governor state, uploads are counted separately from
+// retrieval requests so a burst of downloads doesn't block uploading
+fn tier_router(
+    tier: &str,
+    limits: &TierLimits,
+    byte_burst_secs: u32,
+    shared_store: Option<Arc<dyn RateLimitStore>>,
+) -> (Router<Arc<AppState>>, BandwidthLimits) {
+    let requests = with_burst(
+        retrieval_routes().route("/stats", get(bundles_stats_handler)),
+        limits.request_burst,
+        limits.request_replenish_secs,
+        tier,
+        "requests",
+        shared_store.clone(),
+    );
+    let uploads = with_burst(
+        Router::new().route("/upload", post(upload_binary_handler)),
+        limits.upload_burst,
+        limits.upload_replenish_secs,
+        tier,
+        "uploads",
+        shared_store,
+    );
+
+    let bandwidth = BandwidthLimits::new(
+        limits.ingress_bytes_per_sec,
+        limits.egress_bytes_per_sec,
+        byte_burst_secs,
+    );
+
+    let router = requests
+        .merge(uploads)
+        .layer(axum::middleware::map_response_with_state(
+            tier.to_string(),
+            tier_header,
+        ));
+    if bandwidth.is_unlimited() {
+        return (router, bandwidth);
+    }
+
+    let router = router.layer(axum::middleware::from_fn_with_state(
+        bandwidth.clone(),
+        bandwidth_limit,
+    ));
+    (router, bandwidth)
 }
 
 fn get_router(state: Arc<AppState>) -> Router {
@@ -90,60 +222,123 @@ fn get_router(state: Arc<AppState>) -> Router {
         .allow_headers(Any);
 
     let timeout = TimeoutLayer::new(Duration::from_secs(3600));
-    let request_body_limit = RequestBodyLimitLayer::new(SERVER_REQUEST_BODY_LIMIT);
-
-    let unprotected_router = retrieval_route_with_burst(6);
-
-    let protected_router = retrieval_route_with_burst(60);
-
-    let internal_router = retrieval_route_with_burst(999999);
+    let request_body_limit =
+        RequestBodyLimitLayer::new(usize::try_from(*MAX_UPLOAD_BYTES).unwrap_or(usize::MAX));
+
+    let tiers = get_rate_limit_tiers();
+    println!("RATE LIMIT TIERS: {:?}", tiers);
+
+    let store = state.rate_limit_store.clone();
+    let (unprotected_router, unprotected_bandwidth) = tier_router(
+        "anonymous",
+        &tiers.anonymous,
+        tiers.byte_burst_secs,
+        store.clone(),
+    );
+    let (protected_router, protected_bandwidth) = tier_router(
+        "authenticated",
+        &tiers.authenticated,
+        tiers.byte_burst_secs,
+        store.clone(),
+    );
+    let (internal_router, internal_bandwidth) = tier_router(
+        "internal",
+        &tiers.internal,
+        tiers.byte_burst_secs,
+        store.clone(),
+    );
+    let (whitelisted_router, whitelisted_bandwidth) = tier_router(
+        "whitelisted",
+        &tiers.whitelisted,
+        tiers.byte_burst_secs,
+        store.clone(),
+    );
+    let (browser_router, browser_bandwidth) =
+        tier_router("browser", &tiers.browser, tiers.byte_burst_secs, store);
+
+    // keyed byte limiters keep one entry per client, drop the idle ones
+    let bandwidth_limits = [
+        unprotected_bandwidth,
+        protected_bandwidth,
+        internal_bandwidth,
+        whitelisted_bandwidth,
+        browser_bandwidth,
+    ];
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            bandwidth_limits.iter().for_each(|l| l.retain_recent());
+        }
+    });
 
     let dispatch_state = state.clone();
 
-    let whitelisted_urls = Arc::new(whitelisted_urls());
+    let whitelist = Arc::new(Whitelist::from_env());
 
     let dispatch = tower::service_fn(move |req: Request<axum::body::Body>| {
-        let internal_key = &*INTERNAL_KEY;
         let headers = req.headers();
-        let whitelisted_urls = whitelisted_urls.clone();
-
-        let req_header = headers.get(LOAD_HEADER_NAME);
-        let host_header = headers
-            .get("host")
-            .map(|e| e.to_str().unwrap_or(""))
-            .unwrap_or("");
-        let host_url = {
-            if host_header.starts_with("http://") || host_header.starts_with("https://") {
-                host_header.to_string()
-            } else {
-                format!("http://{}", host_header)
-            }
-        };
-
-        let is_url_whitelisted = is_whitelisted(Some(host_url), &whitelisted_urls);
 
-        let tier_router = {
-            if is_url_whitelisted {
-                retrieval_routes()
+        let peer = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let is_request_whitelisted =
+            whitelist.is_request_whitelisted(peer, headers, &TRUSTED_PROXIES, unix_now());
+        let is_browser_request = whitelist.is_browser_request(headers);
+
+        let dispatch_state = dispatch_state.clone();
+        // requests without a valid key from a known site get the browser tier
+        let anonymous_router = if is_browser_request {
+            browser_router.clone()
+        } else {
+            unprotected_router.clone()
+        };
+        let protected_router = protected_router.clone();
+        let internal_router = internal_router.clone();
+        let whitelisted_router = whitelisted_router.clone();
+
+        // token verification is async now, so the tier is picked inside the
+        // returned future instead of blocking the runtime in the closure
+        async move {
+            let (tier_router, rate_limit_key) = if is_request_whitelisted {
+                (whitelisted_router, None)
             } else {
-                match req_header.and_then(|h| h.to_str().ok()) {
-                    Some(value) if value == internal_key => internal_router.clone(),
-                    Some(value) if is_access_token_valid(value) => protected_router.clone(),
-                    _ => unprotected_router.clone(),
+                // the same identity the handlers see, keys without the read
+                // scope or of the reserved owner stay anonymous
+                match identify(dispatch_state.auth.as_ref(), req.headers()).await {
+                    Ok(Some(identity)) if identity.is_internal => {
+                        (internal_router, Some(RateLimitKey::internal()))
+                    }
+                    Ok(Some(identity)) if identity.has_scope(SCOPE_READ) => (
+                        protected_router,
+                        Some(RateLimitKey::for_key(&identity.key_id)),
+                    ),
+                    Ok(_) => (anonymous_router, None),
+                    Err(e) => {
+                        println!("Error verifying access token: {:?}", e);
+                        (anonymous_router, None)
+                    }
                 }
+            };
+            // everything else is limited per client IP
+            let mut req = req;
+            if let Some(rate_limit_key) = rate_limit_key {
+                req.extensions_mut().insert(rate_limit_key);
             }
-        };
 
-        let tier_router = tier_router.with_state(dispatch_state.clone());
+            let tier_router = tier_router.with_state(dispatch_state);
 
-        // forward the *same* request into the chosen sub‑router
-        async move { tier_router.oneshot(req).await }
+            // forward the *same* request into the chosen sub‑router
+            tier_router.oneshot(req).await
+        }
     });
 
     let router = Router::new()
         .route("/", get(server_status_handler))
-        .route("/stats", get(bundles_stats_handler))
-        .route("/upload", post(upload_binary_handler))
+        .route("/admin/keys", post(create_api_key_handler))
+        .route("/admin/keys/{key_id}/revoke", post(revoke_api_key_handler))
+        .route("/admin/origin-tokens", post(create_origin_token_handler))
         .fallback_service(dispatch)
         .layer(timeout)
         .layer(cors)
@@ -161,52 +356,33 @@ async fn main() -> Result<(), anyhow::Error> {
     let app_state = init_app_state().await?;
 
     let state = Arc::new(app_state);
-    // Spawn a background task for updates with backpressure control
+    // bundles are settled one at a time, picked by the settlement queue. The
+    // failed ones, permanent-now uploads included, are retried from it
     tokio::spawn(async move {
-        // Create a semaphore to limit concurrent operations
-        let semaphore = Arc::new(tokio::sync::Semaphore::new(2)); // Limit to 2 concurrent operations
-
-        // loop {
-        //     let unsettled_count = match get_unsettled_bundles().await {
-        //         Ok(bundles) => bundles.len(),
-        //         Err(_) => 0,
-        //     };
-
-        //     if unsettled_count == 0 {
-        //         println!("No unsettled bundles, sleeping for 120s");
-        //         tokio::time::sleep(tokio::time::Duration::from_secs(120)).await;
-        //         continue;
-        //     }
-
-        //     let permit = match tokio::time::timeout(
-        //         Duration::from_secs(5),
-        //         semaphore.clone().acquire_owned(),
-        //     )
-        //     .await
-        //     {
-        //         Ok(Ok(permit)) => permit,
-        //         Ok(Err(_)) => {
-        //             println!("Semaphore closed, retrying in 60s");
-        //             tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
-        //             continue;
-        //         }
-        //         Err(_) => {
-        //             println!("Timeout acquiring permit, system under load, sleeping for 60s");
-        //             tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
-        //             continue;
-        //         }
-        //     };
-
-        //     tokio::spawn(async move {
-        //         let _permit = permit;
-
-        //         if let Err(e) = update().await {
-        //             println!("Error in update: {:?}", e);
-        //         }
-        //     });
-
-        //     tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-        // }
+        loop {
+            let delay = match update().await {
+                Ok(true) => SETTLEMENT_BUSY_DELAY_SECS,
+                Ok(false) => SETTLEMENT_IDLE_DELAY_SECS,
+                Err(e) => {
+                    println!("Error in update: {:?}", e);
+                    SETTLEMENT_BUSY_DELAY_SECS
+                }
+            };
+            tokio::time::sleep(Duration::from_secs(delay)).await;
+        }
+    });
+
+    // expired ephemeral objects are deleted from the bucket
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            match evict_expired().await {
+                Ok(0) => {}
+                Ok(evicted) => println!("Evicted {} expired bundles", evicted),
+                Err(e) => println!("Error evicting expired bundles: {:?}", e),
+            }
+        }
     });
 
     let booter = Booter::new(None).await;
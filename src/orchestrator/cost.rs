@@ -0,0 +1,218 @@
+use crate::auth::provider::unix_now;
+use crate::orchestrator::db::{Bundle, SettlementCost, upsert_settlement_cost};
+use crate::orchestrator::progress::chunks_count;
+use crate::orchestrator::stats::StatsCache;
+use crate::r#static::{LOAD_RPC_URL, SETTLEMENT_GAS_PRICE_WEI};
+use crate::utils::constants::FOUR_MB;
+use anyhow::{Error, anyhow};
+use serde::{Serialize, Serializer};
+use serde_json::{Value, json};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+// every chunk, and the finalize bundle, is one Load Network transaction whose
+// calldata is the 0xbabe1 envelope
+const TX_BASE_GAS: u64 = 21_000;
+const CALLDATA_GAS_PER_BYTE: u64 = 16;
+// signature, owner and tags of an envelope
+const ENVELOPE_OVERHEAD_BYTES: u64 = 1024;
+// the finalize bundle references every chunk by its 0x txid
+const FINALIZE_BYTES_PER_CHUNK: u64 = 66;
+
+pub const COST_STATUS_ESTIMATED: &str = "estimated";
+pub const COST_STATUS_RECORDED: &str = "recorded";
+// the receipts couldn't be loaded, only the estimate is known
+pub const COST_STATUS_UNAVAILABLE: &str = "unavailable";
+
+const RECEIPT_ATTEMPTS: u32 = 5;
+const RECEIPT_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+static GAS_PRICE_CACHE: LazyLock<StatsCache<u128>> =
+    LazyLock::new(|| StatsCache::new(Duration::from_secs(60)));
+
+static RPC_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap()
+});
+
+// wei amounts overflow JSON numbers, they are sent as decimal strings
+pub fn serialize_wei<S: Serializer>(wei: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(wei)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SettlementEstimate {
+    pub data_size: u64,
+    pub chunks_count: u32,
+    pub gas: u64,
+    #[serde(serialize_with = "serialize_wei")]
+    pub gas_price_wei: u128,
+    #[serde(serialize_with = "serialize_wei")]
+    pub cost_wei: u128,
+}
+
+fn calldata_gas(bytes: u64) -> u64 {
+    TX_BASE_GAS + (bytes + ENVELOPE_OVERHEAD_BYTES) * CALLDATA_GAS_PER_BYTE
+}
+
+// calldata is priced as non zero bytes, so this is an upper bound for data
+// with zero bytes in it
+pub fn estimate_settlement(data_size: u64, gas_price_wei: u128) -> SettlementEstimate {
+    let chunks = chunks_count(data_size);
+    let full_chunks = data_size / FOUR_MB as u64;
+    let last_chunk = data_size % FOUR_MB as u64;
+
+    let mut gas = full_chunks * calldata_gas(FOUR_MB as u64);
+    if last_chunk > 0 {
+        gas += calldata_gas(last_chunk);
+    }
+    gas += calldata_gas(chunks as u64 * FINALIZE_BYTES_PER_CHUNK);
+
+    SettlementEstimate {
+        data_size,
+        chunks_count: chunks,
+        gas,
+        gas_price_wei,
+        cost_wei: gas as u128 * gas_price_wei,
+    }
+}
+
+impl SettlementCost {
+    pub fn estimated(bundle: &Bundle, estimate: &SettlementEstimate, now: u64) -> Self {
+        Self {
+            optimistic_hash: bundle.optimistic_hash.clone(),
+            owner: bundle.owner.clone(),
+            data_size: estimate.data_size,
+            chunks_count: estimate.chunks_count,
+            estimated_gas: estimate.gas,
+            estimated_cost_wei: estimate.cost_wei,
+            gas_used: 0,
+            cost_wei: 0,
+            status: COST_STATUS_ESTIMATED.to_string(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+// SETTLEMENT_GAS_PRICE_WEI when set, otherwise eth_gasPrice, cached a minute
+pub async fn current_gas_price() -> Result<u128, Error> {
+    if *SETTLEMENT_GAS_PRICE_WEI > 0 {
+        return Ok(*SETTLEMENT_GAS_PRICE_WEI);
+    }
+    let price = GAS_PRICE_CACHE
+        .get_or_refresh(|| async {
+            let result = rpc_call("eth_gasPrice", json!([])).await?;
+            parse_quantity(&result).ok_or_else(|| anyhow!("invalid eth_gasPrice result"))
+        })
+        .await?;
+    Ok(*price)
+}
+
+// gas used and wei paid by settled transactions, None while a receipt is
+// missing
+pub async fn transactions_cost(txids: &[String]) -> Result<Option<(u64, u128)>, Error> {
+    let mut gas_used = 0u64;
+    let mut cost_wei = 0u128;
+    for txid in txids {
+        let receipt = rpc_call("eth_getTransactionReceipt", json!([txid])).await?;
+        match receipt_cost(&receipt) {
+            Some((gas, cost)) => {
+                gas_used += gas;
+                cost_wei += cost;
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(Some((gas_used, cost_wei)))
+}
+
+// receipts can lag behind finalize, they are polled a few times before the
+// cost is given up as unavailable
+pub async fn record_actual_cost(mut cost: SettlementCost, txids: Vec<String>) {
+    for attempt in 1..=RECEIPT_ATTEMPTS {
+        match transactions_cost(&txids).await {
+            Ok(Some((gas_used, cost_wei))) => {
+                cost.gas_used = gas_used;
+                cost.cost_wei = cost_wei;
+                cost.status = COST_STATUS_RECORDED.to_string();
+                break;
+            }
+            Ok(None) => println!("Settlement receipts not available yet, attempt {}", attempt),
+            Err(e) => println!("Error loading settlement receipts: {:?}", e),
+        }
+        if attempt == RECEIPT_ATTEMPTS {
+            cost.status = COST_STATUS_UNAVAILABLE.to_string();
+        } else {
+            tokio::time::sleep(RECEIPT_RETRY_DELAY).await;
+        }
+    }
+
+    cost.updated_at = unix_now();
+    if let Err(e) = upsert_settlement_cost(&cost).await {
+        println!("Error recording settlement cost: {:?}", e);
+    }
+}
+
+pub fn receipt_cost(receipt: &Value) -> Option<(u64, u128)> {
+    let gas_used = parse_quantity(receipt.get("gasUsed")?)?;
+    let gas_price = parse_quantity(receipt.get("effectiveGasPrice")?)?;
+    Some((u64::try_from(gas_used).ok()?, gas_used * gas_price))
+}
+
+// JSON-RPC quantities are 0x prefixed hex
+fn parse_quantity(value: &Value) -> Option<u128> {
+    let hex = value.as_str()?.strip_prefix("0x")?;
+    u128::from_str_radix(hex, 16).ok()
+}
+
+async fn rpc_call(method: &str, params: Value) -> Result<Value, Error> {
+    let response: Value = RPC_CLIENT
+        .post(LOAD_RPC_URL.as_str())
+        .json(&json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params}))
+        .send()
+        .await?
+        .json()
+        .await?;
+    if let Some(error) = response.get("error") {
+        return Err(anyhow!("{} failed: {}", method, error));
+    }
+    Ok(response.get("result").cloned().unwrap_or(Value::Null))
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::orchestrator::cost::{estimate_settlement, receipt_cost};
+    use serde_json::json;
+
+    #[test]
+    pub fn test_estimate_settlement() {
+        let four_mb = 4 * 1024 * 1024;
+        let single = estimate_settlement(1000, 2);
+        assert_eq!(single.chunks_count, 1);
+        assert_eq!(single.gas, (21_000 + 2024 * 16) + (21_000 + 1090 * 16));
+        assert_eq!(single.cost_wei, single.gas as u128 * 2);
+
+        let chunked = estimate_settlement(2 * four_mb + 1, 1);
+        assert_eq!(chunked.chunks_count, 3);
+        assert_eq!(
+            chunked.gas,
+            2 * (21_000 + (four_mb + 1024) * 16) + (21_000 + 1025 * 16) + (21_000 + 1222 * 16)
+        );
+
+        // 2GB at 100 gwei doesn't fit a u64 of wei
+        let huge = estimate_settlement(2 * 1024 * 1024 * 1024, 100_000_000_000);
+        assert!(huge.cost_wei > u64::MAX as u128);
+        let json = serde_json::to_value(&huge).unwrap();
+        assert_eq!(json["cost_wei"], huge.cost_wei.to_string());
+
+        let receipt = json!({"gasUsed": "0x5208", "effectiveGasPrice": "0x3b9aca00"});
+        assert_eq!(
+            receipt_cost(&receipt),
+            Some((21_000, 21_000 * 1_000_000_000))
+        );
+        assert_eq!(receipt_cost(&json!(null)), None);
+    }
+}
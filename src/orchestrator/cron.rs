@@ -1,63 +1,313 @@
+use crate::auth::provider::unix_now;
 use crate::core::bundler_superaccount::init_superaccount;
-use crate::orchestrator::db::{get_unsettled_bundles, update_bundle_settled_status};
-use crate::utils::constants::FOUR_MB;
+use crate::orchestrator::cost::{current_gas_price, estimate_settlement, record_actual_cost};
+use crate::orchestrator::db::{
+    Bundle, BundleChunks, SettlementCost, USAGE_EVENT_SETTLE, USAGE_EVENT_UPLOAD,
+    claim_bundle_settlement, defer_bundle_settlement, get_bundle_by_optimistic_hash,
+    get_bundle_tags, get_settlement_candidates, get_usage_entry, insert_usage_entry,
+    refresh_bundle_settlement_claim, update_bundle_settled_status, upsert_bundle_chunks,
+    upsert_settlement_cost,
+};
+use crate::orchestrator::progress::chunks_count;
+use crate::orchestrator::queue::{SettlementLane, SettlementQueue, retry_delay};
+use crate::orchestrator::storage_class::STORAGE_CLASS_EPHEMERAL;
+use crate::server::events::{
+    BundleEvent, EVENT_CHUNKS_PROPAGATED, EVENT_SETTLEMENT_STARTED, publish,
+};
+use crate::server::manifest::{MANIFEST_CONTENT_TYPE, PathManifest, SETTLED_MANIFEST_CONTENT_TYPE};
+use crate::server::metadata::ObjectMetadata;
+use crate::server::webhooks::{EVENT_SETTLED, EVENT_SETTLEMENT_FAILED, WebhookBundle, notify};
+use crate::utils::constants::{MAX_SETTLEMENT_CANDIDATES, SETTLE_CLAIM_REFRESH_SECS};
 use crate::utils::get_env::get_env_var;
 use anyhow::{Error, anyhow};
 use bundler::utils::core::large_bundle::LargeBundle;
+use bundler::utils::core::tags::Tag;
+use std::collections::BTreeMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
 
-pub async fn update() -> Result<(), Error> {
-    let funder_pk = get_env_var("SUPERACCOUNT_PK").unwrap();
-    let unsettled_bundles = get_unsettled_bundles().await.unwrap_or_default();
-    if unsettled_bundles.is_empty() {
+// lane turns and owner fairness carry over between runs
+static SETTLEMENT_QUEUE: LazyLock<Mutex<SettlementQueue>> =
+    LazyLock::new(|| Mutex::new(SettlementQueue::default()));
+
+// true when a bundle was picked, whether it settled or not
+pub async fn update() -> Result<bool, Error> {
+    let candidates = get_settlement_candidates(MAX_SETTLEMENT_CANDIDATES)
+        .await
+        .unwrap_or_default();
+    let Some(bundle) = SETTLEMENT_QUEUE
+        .lock()
+        .unwrap()
+        .pick(&candidates, unix_now())
+        .cloned()
+    else {
         println!("No unsettled bundles to process");
-        return Ok(());
-    }
+        return Ok(false);
+    };
+    println!(
+        "SETTLING BUNDLE {} ({} lane, {} candidates)",
+        bundle.optimistic_hash,
+        SettlementLane::of(bundle.priority, bundle.data_size).as_str(),
+        candidates.len()
+    );
+
+    settle(&bundle).await?;
+    Ok(true)
+}
 
-    println!("UNSETTLED BUNDLES: {:?}", unsettled_bundles);
-    let header_bundle = unsettled_bundles
-        .get(0)
-        .ok_or_else(|| anyhow!("Error getting unsettled bundles"))?;
+// settles one bundle, from the queue or inline for permanent-now uploads, and
+// notifies about it. None when it has nothing to settle yet, or another
+// settlement holds it
+pub async fn settle(header_bundle: &Bundle) -> Result<Option<String>, Error> {
+    let funder_pk = get_env_var("SUPERACCOUNT_PK")?;
 
+    if header_bundle.storage_class == STORAGE_CLASS_EPHEMERAL {
+        return Err(anyhow!("Ephemeral bundles are never settled"));
+    }
     if header_bundle.data_size == 0 {
         println!("Bundle has zero data size, skipping");
-        return Ok(());
+        return Ok(None);
+    }
+
+    let hash = &header_bundle.optimistic_hash;
+    let claim = uuid::Uuid::new_v4().to_string();
+    if !claim_bundle_settlement(hash, &claim, unix_now()).await? {
+        println!("Bundle {} is already being settled, skipping", hash);
+        return Ok(None);
     }
+    let heartbeat = tokio::spawn(keep_claim(hash.clone(), claim.clone()));
+    let settled = settle_bundle(header_bundle, funder_pk).await;
+    heartbeat.abort();
+    if !matches!(settled, Ok(Some(_))) {
+        let next_attempt_at = unix_now() + retry_delay(header_bundle.settle_attempts);
+        if let Err(e) = defer_bundle_settlement(hash, &claim, next_attempt_at).await {
+            println!("Error deferring settlement: {:?}", e);
+        }
+    }
+
+    let key_id = upload_key_id(&header_bundle.optimistic_hash).await;
+    let bundle_txid = match settled {
+        Ok(Some(bundle_txid)) => bundle_txid,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            publish(BundleEvent::failed(
+                &header_bundle.optimistic_hash,
+                &header_bundle.owner,
+                &e.to_string(),
+            ));
+            tokio::spawn(notify(
+                EVENT_SETTLEMENT_FAILED,
+                WebhookBundle::from(header_bundle),
+                key_id,
+                Some(e.to_string()),
+            ));
+            return Err(e);
+        }
+    };
+
+    if let Err(e) =
+        record_settlement_usage(&header_bundle.optimistic_hash, header_bundle.data_size).await
+    {
+        println!("Error recording settlement usage: {:?}", e);
+    }
+
+    let mut settled = WebhookBundle::from(header_bundle);
+    settled.bundle_txid = bundle_txid.clone();
+    settled.is_settled = true;
+    tokio::spawn(notify(EVENT_SETTLED, settled, key_id, None));
+
+    println!("Successfully updated bundle status");
+    Ok(Some(bundle_txid))
+}
+
+// a settlement of a multi-GB bundle can outlast any fixed timeout, its claim
+// stays fresh for as long as it runs
+async fn keep_claim(optimistic_hash: String, claim: String) {
+    let mut interval = tokio::time::interval(Duration::from_secs(SETTLE_CLAIM_REFRESH_SECS));
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if let Err(e) = refresh_bundle_settlement_claim(&optimistic_hash, &claim, unix_now()).await
+        {
+            println!("Error refreshing settlement claim: {:?}", e);
+        }
+    }
+}
+
+// settles one bundle and returns its txid, None when it has to wait
+async fn settle_bundle(header_bundle: &Bundle, funder_pk: String) -> Result<Option<String>, Error> {
     let header_bundle_obj = get_optimistic_bundle_data(&header_bundle.optimistic_hash).await?;
 
-    let header_bundle_data = header_bundle_obj.0.clone();
-    let header_bundle_size = header_bundle_obj.0.len() as f64;
-    let header_bundle_mime = header_bundle_obj.1;
+    // directory manifests settle once their files have, pointing at their txids
+    let (header_bundle_data, header_bundle_mime) =
+        if header_bundle.content_type == MANIFEST_CONTENT_TYPE {
+            match settled_manifest(&header_bundle_obj.0).await? {
+                Some(manifest) => (manifest, SETTLED_MANIFEST_CONTENT_TYPE.to_string()),
+                None => {
+                    println!("Manifest files are not settled yet, skipping");
+                    return Ok(None);
+                }
+            }
+        } else {
+            header_bundle_obj
+        };
+
+    // user metadata (and the compression) of encrypted objects stays off chain
+    let bundle_tags = if header_bundle.encryption.is_empty() {
+        let tags = get_bundle_tags(&header_bundle.optimistic_hash).await?;
+        ObjectMetadata::from_bundle_tags(&header_bundle.filename, tags)
+            .bundle_tags(&header_bundle.compression)
+    } else {
+        Vec::new()
+    };
 
     // let super_account = init_superaccount().await?;
 
-    let chunkers_count = (header_bundle_size as f64 / FOUR_MB as f64).ceil() as u32;
+    let chunkers_count = chunks_count(header_bundle_data.len() as u64);
     println!("Processing bundle with {} chunks", chunkers_count);
 
+    let gas_price = current_gas_price().await.unwrap_or_else(|e| {
+        println!("Error loading the gas price, estimating at 0: {:?}", e);
+        0
+    });
+    let estimate = estimate_settlement(header_bundle_data.len() as u64, gas_price);
+    println!(
+        "Estimated settlement cost: {} gas, {} wei",
+        estimate.gas, estimate.cost_wei
+    );
+    let cost = SettlementCost::estimated(header_bundle, &estimate, unix_now());
+    if let Err(e) = upsert_settlement_cost(&cost).await {
+        println!("Error recording settlement estimate: {:?}", e);
+    }
+
     let large_bundle_builder = LargeBundle::new()
         .data(header_bundle_data)
         .private_key(funder_pk)
         .content_type(header_bundle_mime)
+        .tags(
+            bundle_tags
+                .into_iter()
+                .map(|(name, value)| Tag::new(name, value))
+                .collect(),
+        )
         // .super_account(super_account)
         // .with_chunkers_count(chunkers_count)
         .chunk()
         .build()
         .map_err(|e| anyhow!("Error building large bundle: {:?}", e))?;
 
+    // propagate_chunks sends the chunks concurrently without reporting each
+    // one, progress is recorded and published before and after it
+    let hash = &header_bundle.optimistic_hash;
+    let owner = &header_bundle.owner;
+    let mut progress = BundleChunks::started(hash, chunkers_count, unix_now());
+    record_progress(&progress).await;
+    publish(BundleEvent::progress(
+        EVENT_SETTLEMENT_STARTED,
+        hash,
+        owner,
+        0,
+        chunkers_count,
+    ));
+
     // propagate chunks
-    let propagated = large_bundle_builder
-        .propagate_chunks()
-        .await
-        .map_err(|e| anyhow!("Error propagating chunks: {:?}", e))?;
+    let propagated = match large_bundle_builder.propagate_chunks().await {
+        Ok(propagated) => propagated,
+        Err(e) => {
+            let e = anyhow!("Error propagating chunks: {:?}", e);
+            return Err(record_failure(&mut progress, e).await);
+        }
+    };
+    let chunk_txids = propagated.chunks_receipts.clone();
+    progress.propagated(&chunk_txids, unix_now());
+    record_progress(&progress).await;
+    publish(BundleEvent::progress(
+        EVENT_CHUNKS_PROPAGATED,
+        hash,
+        owner,
+        progress.chunks_propagated,
+        chunkers_count,
+    ));
 
-    let large_bundle = propagated
-        .finalize()
-        .await
-        .map_err(|e| anyhow!("Error finalizing bundle: {:?}", e))?;
+    let large_bundle = match propagated.finalize().await {
+        Ok(large_bundle) => large_bundle,
+        Err(e) => {
+            let e = anyhow!("Error finalizing bundle: {:?}", e);
+            return Err(record_failure(&mut progress, e).await);
+        }
+    };
 
     update_bundle_settled_status(&header_bundle.optimistic_hash, true, &large_bundle).await?;
+    progress.finalized(&large_bundle, unix_now());
+    record_progress(&progress).await;
+    publish(BundleEvent::finalized(hash, owner, &large_bundle));
 
-    println!("Successfully updated bundle status");
-    Ok(())
+    let mut txids = chunk_txids;
+    txids.push(large_bundle.clone());
+    tokio::spawn(record_actual_cost(cost, txids));
+
+    Ok(Some(large_bundle))
+}
+
+// files are uploaded, and queued, before their manifest so they are normally
+// settled by the time it is. Empty files are never settled and are left out
+async fn settled_manifest(manifest: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+    let manifest: PathManifest = serde_json::from_slice(manifest)?;
+
+    let mut txids = BTreeMap::new();
+    for id in manifest.ids() {
+        let bundle = get_bundle_by_optimistic_hash(id).await?;
+        if bundle.data_size == 0 {
+            continue;
+        }
+        if !bundle.is_settled {
+            return Ok(None);
+        }
+        txids.insert(id.to_string(), bundle.bundle_txid);
+    }
+
+    Ok(Some(serde_json::to_vec(&manifest.to_settled(&txids))?))
+}
+
+// progress is best effort, it never fails a settlement
+async fn record_progress(progress: &BundleChunks) {
+    if let Err(e) = upsert_bundle_chunks(progress).await {
+        println!("Error recording settlement progress: {:?}", e);
+    }
+}
+
+async fn record_failure(progress: &mut BundleChunks, error: Error) -> Error {
+    progress.failed(&error.to_string(), unix_now());
+    record_progress(progress).await;
+    error
+}
+
+pub async fn upload_key_id(optimistic_hash: &str) -> Option<String> {
+    match get_usage_entry(optimistic_hash, USAGE_EVENT_UPLOAD).await {
+        Ok(entry) => entry.map(|entry| entry.key_id),
+        Err(e) => {
+            println!("Error loading upload usage entry: {:?}", e);
+            None
+        }
+    }
+}
+
+// settlement is accounted to the key that uploaded the bundle, if any
+async fn record_settlement_usage(optimistic_hash: &str, bytes: u64) -> Result<(), Error> {
+    let upload_entry = match get_usage_entry(optimistic_hash, USAGE_EVENT_UPLOAD).await? {
+        Some(entry) => entry,
+        None => return Ok(()),
+    };
+
+    insert_usage_entry(
+        &upload_entry.key_id,
+        &upload_entry.owner,
+        USAGE_EVENT_SETTLE,
+        optimistic_hash,
+        bytes,
+        unix_now(),
+    )
+    .await
 }
 
 async fn get_optimistic_bundle_data(optimistic_hash: &str) -> Result<(Vec<u8>, String), Error> {
@@ -1,16 +1,95 @@
+use crate::auth::provider::{StorageQuota, unix_now};
+use crate::orchestrator::cost::{COST_STATUS_RECORDED, serialize_wei};
+use crate::orchestrator::queue::settlement_lane_sql;
+use crate::orchestrator::search::BundleQuery;
+use crate::orchestrator::storage_class::{STORAGE_CLASS_EPHEMERAL, STORAGE_CLASS_PERMANENT_NOW};
+use crate::utils::constants::{PERMANENT_NOW_SETTLE_GRACE_SECS, SETTLE_CLAIM_TIMEOUT_SECS};
 use crate::utils::get_env::get_env_var;
 use anyhow::Error;
 use planetscale_driver::{Database, PSConnection, query};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Database)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Database)]
 pub struct Bundle {
     pub id: u32,
     pub optimistic_hash: String,
     pub bundle_txid: String,
-    pub data_size: u32,
+    pub data_size: u64,
     pub is_settled: bool,
     pub content_type: String,
+    pub owner: String,
+    pub is_private: bool,
+    // "" (plaintext), "client" or "server", see utils::encryption
+    pub encryption: String,
+    pub encryption_algorithm: String,
+    pub encryption_key_id: String,
+    // client envelopes are returned by /download, bundles served as JSON
+    // never carry them
+    #[serde(skip_serializing)]
+    pub wrapped_key: String,
+    #[serde(skip_serializing)]
+    pub encryption_nonce: String,
+    // "" (stored as uploaded), "zstd" or "gzip"
+    pub compression: String,
+    pub original_size: u64,
+    pub compressed_size: u64,
+    pub filename: String,
+    pub created_at: u64,
+    pub settled_at: u64,
+    pub updated_at: u64,
+    // 1 settles in the priority lane, see orchestrator::queue
+    pub priority: u8,
+    // see orchestrator::storage_class, expires_at is 0 unless ephemeral
+    pub storage_class: String,
+    pub expires_at: u64,
+    pub evicted_at: u64,
+    // see claim_bundle_settlement
+    pub settling_at: u64,
+    #[serde(skip_serializing)]
+    pub settle_claim: String,
+    // see orchestrator::queue::retry_delay
+    pub settle_attempts: u32,
+    pub next_attempt_at: u64,
+}
+
+// the columns of a bundle row set at upload time
+#[derive(Debug, Clone, Default)]
+pub struct NewBundle {
+    pub optimistic_hash: String,
+    pub bundle_txid: String,
+    pub data_size: u64,
+    pub is_settled: bool,
+    pub content_type: String,
+    pub owner: String,
+    pub is_private: bool,
+    pub encryption: BundleEncryption,
+    pub compression: String,
+    pub original_size: u64,
+    pub compressed_size: u64,
+    pub filename: String,
+    pub priority: u8,
+    pub storage_class: String,
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BundleEncryption {
+    pub mode: String,
+    pub algorithm: String,
+    pub key_id: String,
+    pub wrapped_key: String,
+    pub nonce: String,
+}
+
+// user metadata of a bundle, `meta` rows are x-load-meta-* attributes and `tag`
+// rows are tags (with an empty value)
+#[derive(Debug, Serialize, Deserialize, Database)]
+pub struct BundleTag {
+    pub id: u64,
+    pub optimistic_hash: String,
+    pub kind: String,
+    pub name: String,
+    pub value: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Database)]
@@ -20,9 +99,159 @@ pub struct BundleOptimisticHash {
 
 #[derive(Debug, Serialize, Deserialize, Database)]
 pub struct BundleStats {
-    pub bundles_count: u32,
-    pub settled_count: u32,
-    pub total_data_size: u128,
+    pub bundles_count: u64,
+    pub settled_count: u64,
+    pub total_data_size: u64,
+}
+
+// one row of a GROUP BY breakdown of the bundles table
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Database)]
+pub struct StatsGroup {
+    pub name: String,
+    pub count: u64,
+    pub bytes: u64,
+}
+
+// bundles per time bucket, `bucket` is the bucket start in unix seconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Database)]
+pub struct StatsSeriesRow {
+    pub bucket: u64,
+    pub count: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Database)]
+pub struct SettlementLatency {
+    pub latency: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Database)]
+pub struct ApiKey {
+    pub id: u32,
+    pub key_hash: String,
+    pub owner: String,
+    pub scopes: String,
+    pub expires_at: u64,
+    pub is_revoked: bool,
+    pub created_at: u64,
+    pub quota_bytes_per_day: u64,
+    pub quota_total_bytes: u64,
+    pub quota_objects: u64,
+}
+
+pub const USAGE_EVENT_UPLOAD: &str = "upload";
+pub const USAGE_EVENT_SETTLE: &str = "settle";
+
+#[derive(Debug, Serialize, Deserialize, Database)]
+pub struct UsageLedgerEntry {
+    pub id: u64,
+    pub key_id: String,
+    pub owner: String,
+    pub event: String,
+    pub optimistic_hash: String,
+    pub bytes: u64,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Database)]
+pub struct KeyUsage {
+    pub total_bytes: u64,
+    pub bytes_today: u64,
+    pub objects_count: u64,
+    pub settled_bytes: u64,
+    pub settled_count: u64,
+}
+
+// optimistic_hash is empty for the webhooks registered on a key, and set for
+// the callback_url of a single upload
+#[derive(Debug, Clone, Serialize, Deserialize, Database)]
+pub struct Webhook {
+    pub id: u32,
+    pub owner: String,
+    pub key_id: String,
+    pub url: String,
+    // comma separated
+    pub events: String,
+    pub optimistic_hash: String,
+    pub is_active: bool,
+    pub created_at: u64,
+}
+
+// one delivery attempt, status_code is 0 when the request itself failed
+#[derive(Debug, Clone, Serialize, Deserialize, Database)]
+pub struct WebhookDelivery {
+    pub id: u64,
+    pub webhook_id: u32,
+    pub delivery_id: String,
+    pub event: String,
+    pub optimistic_hash: String,
+    pub attempt: u32,
+    pub status_code: u32,
+    pub success: bool,
+    pub error: String,
+    pub created_at: u64,
+}
+
+// the nonce an owner's webhook signing secret is derived from
+#[derive(Debug, Clone, Serialize, Deserialize, Database)]
+pub struct WebhookSecret {
+    pub owner: String,
+    pub nonce: String,
+    pub rotated_at: u64,
+}
+
+// settlement progress of a bundle, chunk_txids is a JSON array
+#[derive(Debug, Clone, Serialize, Deserialize, Database)]
+pub struct BundleChunks {
+    pub optimistic_hash: String,
+    pub status: String,
+    pub chunks_count: u32,
+    pub chunks_propagated: u32,
+    pub chunk_txids: String,
+    pub finalize_txid: String,
+    pub error: String,
+    pub started_at: u64,
+    pub updated_at: u64,
+}
+
+// the estimate is recorded before a bundle is built, the actual cost once the
+// receipts of its transactions are available
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Database)]
+pub struct SettlementCost {
+    pub optimistic_hash: String,
+    pub owner: String,
+    pub data_size: u64,
+    pub chunks_count: u32,
+    pub estimated_gas: u64,
+    #[serde(serialize_with = "serialize_wei")]
+    pub estimated_cost_wei: u128,
+    pub gas_used: u64,
+    #[serde(serialize_with = "serialize_wei")]
+    pub cost_wei: u128,
+    // "estimated", "recorded" or "unavailable"
+    pub status: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+// gas_used and cost_wei only count the recorded settlements
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Database)]
+pub struct OwnerSettlementCosts {
+    pub bundles: u64,
+    pub recorded_bundles: u64,
+    pub bytes: u64,
+    pub estimated_gas: u64,
+    #[serde(serialize_with = "serialize_wei")]
+    pub estimated_cost_wei: u128,
+    pub gas_used: u64,
+    #[serde(serialize_with = "serialize_wei")]
+    pub cost_wei: u128,
+}
+
+// every string interpolated into a double quoted SQL literal goes through
+// this, hashes and ids included since many come from request paths
+pub fn escape_sql(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
 }
 
 pub async fn ps_client() -> Result<PSConnection, Error> {
@@ -33,17 +262,33 @@ pub async fn ps_client() -> Result<PSConnection, Error> {
     Ok(conn)
 }
 
-pub async fn insert_bundle(
-    optimistic_hash: &str,
-    bundle_txid: &str,
-    data_size: u32,
-    is_settled: bool,
-    content_type: &str,
-) -> Result<(), Error> {
+pub async fn insert_bundle(bundle: &NewBundle) -> Result<(), Error> {
     let conn = ps_client().await?;
+    let encryption = &bundle.encryption;
+    let now = unix_now();
     let query_str = format!(
-        "INSERT INTO bundles(optimistic_hash, bundle_txid, data_size, is_settled, content_type) VALUES(\"{}\", \"{}\", {}, {}, \"{}\")",
-        optimistic_hash, bundle_txid, data_size, is_settled as u8, content_type
+        "INSERT INTO bundles(optimistic_hash, bundle_txid, data_size, is_settled, content_type, owner, is_private, encryption, encryption_algorithm, encryption_key_id, wrapped_key, encryption_nonce, compression, original_size, compressed_size, filename, created_at, updated_at, priority, storage_class, expires_at) VALUES(\"{}\", \"{}\", {}, {}, \"{}\", \"{}\", {}, \"{}\", \"{}\", \"{}\", \"{}\", \"{}\", \"{}\", {}, {}, \"{}\", {}, {}, {}, \"{}\", {})",
+        escape_sql(&bundle.optimistic_hash),
+        escape_sql(&bundle.bundle_txid),
+        bundle.data_size,
+        bundle.is_settled as u8,
+        escape_sql(&bundle.content_type),
+        escape_sql(&bundle.owner),
+        bundle.is_private as u8,
+        escape_sql(&encryption.mode),
+        escape_sql(&encryption.algorithm),
+        escape_sql(&encryption.key_id),
+        escape_sql(&encryption.wrapped_key),
+        escape_sql(&encryption.nonce),
+        escape_sql(&bundle.compression),
+        bundle.original_size,
+        bundle.compressed_size,
+        escape_sql(&bundle.filename),
+        now,
+        now,
+        bundle.priority,
+        escape_sql(&bundle.storage_class),
+        bundle.expires_at
     );
     let res = query(&query_str).execute(&conn).await?;
     println!("Insert bundle operation successful: {:?}", res);
@@ -54,7 +299,7 @@ pub async fn get_bundle_by_txid(bundle_txid: &str) -> Result<Bundle, Error> {
     let conn = ps_client().await?;
     let query_str = format!(
         "SELECT * FROM bundles WHERE bundle_txid = \"{}\"",
-        bundle_txid
+        escape_sql(bundle_txid)
     );
     let result: Bundle = query(&query_str).fetch_one(&conn).await?;
     Ok(result)
@@ -64,12 +309,60 @@ pub async fn get_bundle_by_optimistic_hash(optimistic_hash: &str) -> Result<Bund
     let conn = ps_client().await?;
     let query_str = format!(
         "SELECT * FROM bundles WHERE optimistic_hash = \"{}\"",
-        optimistic_hash
+        escape_sql(optimistic_hash)
     );
     let result: Bundle = query(&query_str).fetch_one(&conn).await?;
     Ok(result)
 }
 
+// returns up to query.limit + 1 rows, see BundleQuery::to_sql
+pub async fn search_bundles(bundle_query: &BundleQuery) -> Result<Vec<Bundle>, Error> {
+    let conn = ps_client().await?;
+    let query_str = bundle_query.to_sql();
+    let results: Vec<Bundle> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results)
+}
+
+pub async fn insert_bundle_tags(
+    optimistic_hash: &str,
+    rows: &[(String, String, String)],
+) -> Result<(), Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let conn = ps_client().await?;
+    let values: Vec<String> = rows
+        .iter()
+        .map(|(kind, name, value)| {
+            format!(
+                "(\"{}\", \"{}\", \"{}\", \"{}\")",
+                escape_sql(optimistic_hash),
+                escape_sql(kind),
+                escape_sql(name),
+                escape_sql(value)
+            )
+        })
+        .collect();
+    let query_str = format!(
+        "INSERT INTO bundle_tags(optimistic_hash, kind, name, value) VALUES {}",
+        values.join(", ")
+    );
+    let res = query(&query_str).execute(&conn).await?;
+    println!("Insert bundle tags operation successful: {:?}", res);
+    Ok(())
+}
+
+pub async fn get_bundle_tags(optimistic_hash: &str) -> Result<Vec<BundleTag>, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "SELECT * FROM bundle_tags WHERE optimistic_hash = \"{}\" ORDER BY id ASC",
+        escape_sql(optimistic_hash)
+    );
+    let results: Vec<BundleTag> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results)
+}
+
 pub async fn get_settled_bundles() -> Result<Vec<Bundle>, Error> {
     let conn = ps_client().await?;
     let query_str = "SELECT * FROM bundles WHERE is_settled = TRUE";
@@ -79,22 +372,109 @@ pub async fn get_settled_bundles() -> Result<Vec<Bundle>, Error> {
 
 pub async fn get_unsettled_bundles() -> Result<Vec<Bundle>, Error> {
     let conn = ps_client().await?;
-    let query_str =
-        "SELECT * FROM bundles WHERE is_settled = FALSE AND data_size > 0 ORDER BY id ASC LIMIT 5";
+    let query_str = "SELECT * FROM bundles WHERE is_settled = FALSE AND is_private = FALSE AND data_size > 0 ORDER BY id ASC LIMIT 5";
 
     let results: Vec<Bundle> = query(&query_str).fetch_all(&conn).await?;
     Ok(results)
 }
 
+// the oldest unsettled bundle of every owner in every lane, the settlement
+// queue picks among them. Ephemeral bundles are never settled, permanent-now
+// ones are left to their upload first, claimed ones to their settlement, and
+// the ones waiting for a retry let the next bundle of their owner go
+pub async fn get_settlement_candidates(limit: u32) -> Result<Vec<Bundle>, Error> {
+    let conn = ps_client().await?;
+    let now = unix_now();
+    let grace_start = now.saturating_sub(PERMANENT_NOW_SETTLE_GRACE_SECS);
+    let claim_expiry = now.saturating_sub(SETTLE_CLAIM_TIMEOUT_SECS);
+    let query_str = format!(
+        "SELECT * FROM bundles WHERE id IN (SELECT MIN(id) FROM bundles WHERE is_settled = FALSE AND is_private = FALSE AND data_size > 0 AND storage_class != \"{}\" AND evicted_at = 0 AND NOT (storage_class = \"{}\" AND created_at > {}) AND settling_at <= {} AND next_attempt_at <= {} GROUP BY owner, {}) ORDER BY id ASC LIMIT {}",
+        STORAGE_CLASS_EPHEMERAL,
+        STORAGE_CLASS_PERMANENT_NOW,
+        grace_start,
+        claim_expiry,
+        now,
+        settlement_lane_sql(),
+        limit
+    );
+
+    let results: Vec<Bundle> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results)
+}
+
+// claims an unsettled bundle for one settlement, false when another one holds
+// it. `claim` is read back to tell whether this call won the update
+pub async fn claim_bundle_settlement(
+    optimistic_hash: &str,
+    claim: &str,
+    now: u64,
+) -> Result<bool, Error> {
+    let conn = ps_client().await?;
+    let hash = escape_sql(optimistic_hash);
+    let claim_expiry = now.saturating_sub(SETTLE_CLAIM_TIMEOUT_SECS);
+    let query_str = format!(
+        "UPDATE bundles SET settling_at = {now}, settle_claim = \"{}\" WHERE optimistic_hash = \"{hash}\" AND is_settled = FALSE AND settling_at <= {claim_expiry}",
+        escape_sql(claim)
+    );
+    query(&query_str).execute(&conn).await?;
+
+    let query_str = format!("SELECT * FROM bundles WHERE optimistic_hash = \"{hash}\" LIMIT 1");
+    let results: Vec<Bundle> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results
+        .into_iter()
+        .next()
+        .is_some_and(|bundle| !bundle.is_settled && bundle.settle_claim == claim))
+}
+
+pub async fn refresh_bundle_settlement_claim(
+    optimistic_hash: &str,
+    claim: &str,
+    now: u64,
+) -> Result<(), Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "UPDATE bundles SET settling_at = {} WHERE optimistic_hash = \"{}\" AND settle_claim = \"{}\"",
+        now,
+        escape_sql(optimistic_hash),
+        escape_sql(claim)
+    );
+    query(&query_str).execute(&conn).await?;
+    Ok(())
+}
+
+// a settlement that failed or has to wait releases its claim, the bundle is
+// tried again from `next_attempt_at`
+pub async fn defer_bundle_settlement(
+    optimistic_hash: &str,
+    claim: &str,
+    next_attempt_at: u64,
+) -> Result<(), Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "UPDATE bundles SET settling_at = 0, settle_attempts = settle_attempts + 1, next_attempt_at = {} WHERE optimistic_hash = \"{}\" AND settle_claim = \"{}\"",
+        next_attempt_at,
+        escape_sql(optimistic_hash),
+        escape_sql(claim)
+    );
+    query(&query_str).execute(&conn).await?;
+    Ok(())
+}
+
 pub async fn update_bundle_settled_status(
     optimistic_hash: &str,
     is_settled: bool,
     bundle_txid: &str,
 ) -> Result<(), Error> {
     let conn = ps_client().await?;
+    let now = unix_now();
+    let settled_at = if is_settled { now } else { 0 };
     let query_str = format!(
-        "UPDATE bundles SET is_settled = {}, bundle_txid = \"{}\" WHERE optimistic_hash = \"{}\"",
-        is_settled as u8, bundle_txid, optimistic_hash
+        "UPDATE bundles SET is_settled = {}, bundle_txid = \"{}\", settled_at = {}, updated_at = {} WHERE optimistic_hash = \"{}\"",
+        is_settled as u8,
+        escape_sql(bundle_txid),
+        settled_at,
+        now,
+        escape_sql(optimistic_hash)
     );
     let res = query(&query_str).execute(&conn).await?;
     println!(
@@ -104,14 +484,40 @@ pub async fn update_bundle_settled_status(
     Ok(())
 }
 
+// ephemeral bundles past their TTL whose objects are still stored
+pub async fn get_expired_bundles(now: u64, limit: u32) -> Result<Vec<Bundle>, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "SELECT * FROM bundles WHERE storage_class = \"{}\" AND evicted_at = 0 AND expires_at > 0 AND expires_at <= {} ORDER BY expires_at ASC LIMIT {}",
+        STORAGE_CLASS_EPHEMERAL, now, limit
+    );
+
+    let results: Vec<Bundle> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results)
+}
+
+pub async fn mark_bundle_evicted(optimistic_hash: &str, now: u64) -> Result<(), Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "UPDATE bundles SET evicted_at = {}, updated_at = {} WHERE optimistic_hash = \"{}\"",
+        now,
+        now,
+        escape_sql(optimistic_hash)
+    );
+    query(&query_str).execute(&conn).await?;
+    Ok(())
+}
+
 pub async fn update_bundle_content_type(
     optimistic_hash: &str,
     content_type: &str,
 ) -> Result<(), Error> {
     let conn = ps_client().await?;
     let query_str = format!(
-        "UPDATE bundles SET content_type = \"{}\" WHERE optimistic_hash = \"{}\"",
-        content_type, optimistic_hash
+        "UPDATE bundles SET content_type = \"{}\", updated_at = {} WHERE optimistic_hash = \"{}\"",
+        escape_sql(content_type),
+        unix_now(),
+        escape_sql(optimistic_hash)
     );
     let res = query(&query_str).execute(&conn).await?;
     println!("Update bundle content_type operation successful: {:?}", res);
@@ -123,19 +529,389 @@ pub async fn get_bundle_stats() -> Result<BundleStats, Error> {
     let query_str = "
         SELECT 
             COUNT(*) as bundles_count,
-            SUM(CASE WHEN is_settled = TRUE THEN 1 ELSE 0 END) as settled_count,
-            SUM(data_size) as total_data_size
+            CAST(COALESCE(SUM(CASE WHEN is_settled = TRUE THEN 1 ELSE 0 END), 0) AS UNSIGNED) as settled_count,
+            CAST(COALESCE(SUM(data_size), 0) AS UNSIGNED) as total_data_size
         FROM bundles";
     let result: BundleStats = query(&query_str).fetch_one(&conn).await?;
     Ok(result)
 }
 
+// `group_expr` is a trusted SQL expression, see orchestrator::stats
+pub async fn get_bundle_groups(group_expr: &str, limit: u32) -> Result<Vec<StatsGroup>, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "SELECT {} as name, COUNT(*) as count, CAST(COALESCE(SUM(data_size), 0) AS UNSIGNED) as bytes FROM bundles GROUP BY name ORDER BY count DESC LIMIT {}",
+        group_expr, limit
+    );
+    let results: Vec<StatsGroup> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results)
+}
+
+// `column` is created_at (uploads) or settled_at (settlements)
+pub async fn get_bundle_series(
+    column: &str,
+    step: u64,
+    since: u64,
+) -> Result<Vec<StatsSeriesRow>, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "SELECT CAST(FLOOR({column} / {step}) * {step} AS UNSIGNED) as bucket, COUNT(*) as count, CAST(COALESCE(SUM(data_size), 0) AS UNSIGNED) as bytes FROM bundles WHERE {column} >= {since} GROUP BY bucket ORDER BY bucket"
+    );
+    let results: Vec<StatsSeriesRow> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results)
+}
+
+// seconds from upload to settlement of the bundles settled since `since`,
+// rows from before the timestamp columns have created_at = 0 and are skipped
+pub async fn get_settlement_latencies(since: u64) -> Result<Vec<u64>, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "SELECT settled_at - created_at as latency FROM bundles WHERE is_settled = 1 AND settled_at >= {} AND created_at > 0 AND settled_at >= created_at",
+        since
+    );
+    let results: Vec<SettlementLatency> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results.into_iter().map(|row| row.latency).collect())
+}
+
 pub async fn get_bundles_by_content_type(content_type: &str) -> Result<Vec<Bundle>, Error> {
     let conn = ps_client().await?;
     let query_str = format!(
         "SELECT * FROM bundles WHERE content_type = \"{}\"",
-        content_type
+        escape_sql(content_type)
     );
     let results: Vec<Bundle> = query(&query_str).fetch_all(&conn).await?;
     Ok(results)
 }
+
+pub async fn insert_api_key(
+    key_hash: &str,
+    owner: &str,
+    scopes: &str,
+    expires_at: u64,
+    created_at: u64,
+    quota: &StorageQuota,
+) -> Result<ApiKey, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "INSERT INTO api_keys(key_hash, owner, scopes, expires_at, is_revoked, created_at, quota_bytes_per_day, quota_total_bytes, quota_objects) VALUES(\"{}\", \"{}\", \"{}\", {}, 0, {}, {}, {}, {})",
+        escape_sql(key_hash),
+        escape_sql(owner),
+        escape_sql(scopes),
+        expires_at,
+        created_at,
+        quota.bytes_per_day,
+        quota.total_bytes,
+        quota.objects
+    );
+    let res = query(&query_str).execute(&conn).await?;
+    println!("Insert api key operation successful: {:?}", res);
+
+    get_api_key_by_hash(key_hash)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Inserted api key not found"))
+}
+
+pub async fn get_api_key_by_hash(key_hash: &str) -> Result<Option<ApiKey>, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "SELECT * FROM api_keys WHERE key_hash = \"{}\" LIMIT 1",
+        escape_sql(key_hash)
+    );
+    let results: Vec<ApiKey> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results.into_iter().next())
+}
+
+pub async fn revoke_api_key(id: u32) -> Result<(), Error> {
+    let conn = ps_client().await?;
+    let query_str = format!("UPDATE api_keys SET is_revoked = 1 WHERE id = {}", id);
+    let res = query(&query_str).execute(&conn).await?;
+    println!("Revoke api key operation successful: {:?}", res);
+    Ok(())
+}
+
+pub async fn insert_usage_entry(
+    key_id: &str,
+    owner: &str,
+    event: &str,
+    optimistic_hash: &str,
+    bytes: u64,
+    created_at: u64,
+) -> Result<(), Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "INSERT INTO usage_ledger(key_id, owner, event, optimistic_hash, bytes, created_at) VALUES(\"{}\", \"{}\", \"{}\", \"{}\", {}, {})",
+        escape_sql(key_id),
+        escape_sql(owner),
+        escape_sql(event),
+        escape_sql(optimistic_hash),
+        bytes,
+        created_at
+    );
+    let res = query(&query_str).execute(&conn).await?;
+    println!("Insert usage entry operation successful: {:?}", res);
+    Ok(())
+}
+
+pub async fn get_usage_entry(
+    optimistic_hash: &str,
+    event: &str,
+) -> Result<Option<UsageLedgerEntry>, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "SELECT * FROM usage_ledger WHERE optimistic_hash = \"{}\" AND event = \"{}\" LIMIT 1",
+        escape_sql(optimistic_hash),
+        escape_sql(event)
+    );
+    let results: Vec<UsageLedgerEntry> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results.into_iter().next())
+}
+
+pub async fn get_key_usage(key_id: &str, day_start: u64) -> Result<KeyUsage, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "
+        SELECT
+            COALESCE(SUM(CASE WHEN event = \"{upload}\" THEN bytes ELSE 0 END), 0) as total_bytes,
+            COALESCE(SUM(CASE WHEN event = \"{upload}\" AND created_at >= {day_start} THEN bytes ELSE 0 END), 0) as bytes_today,
+            COALESCE(SUM(CASE WHEN event = \"{upload}\" THEN 1 ELSE 0 END), 0) as objects_count,
+            COALESCE(SUM(CASE WHEN event = \"{settle}\" THEN bytes ELSE 0 END), 0) as settled_bytes,
+            COALESCE(SUM(CASE WHEN event = \"{settle}\" THEN 1 ELSE 0 END), 0) as settled_count
+        FROM usage_ledger WHERE key_id = \"{key_id}\"",
+        upload = USAGE_EVENT_UPLOAD,
+        settle = USAGE_EVENT_SETTLE,
+        day_start = day_start,
+        key_id = escape_sql(key_id)
+    );
+    let result: KeyUsage = query(&query_str).fetch_one(&conn).await?;
+    Ok(result)
+}
+
+pub async fn insert_webhook(
+    owner: &str,
+    key_id: &str,
+    url: &str,
+    events: &str,
+    optimistic_hash: &str,
+    created_at: u64,
+) -> Result<Webhook, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "INSERT INTO webhooks(owner, key_id, url, events, optimistic_hash, is_active, created_at) VALUES(\"{}\", \"{}\", \"{}\", \"{}\", \"{}\", 1, {})",
+        escape_sql(owner),
+        escape_sql(key_id),
+        escape_sql(url),
+        escape_sql(events),
+        escape_sql(optimistic_hash),
+        created_at
+    );
+    let res = query(&query_str).execute(&conn).await?;
+    println!("Insert webhook operation successful: {:?}", res);
+
+    let query_str = format!(
+        "SELECT * FROM webhooks WHERE key_id = \"{}\" AND url = \"{}\" AND optimistic_hash = \"{}\" ORDER BY id DESC LIMIT 1",
+        escape_sql(key_id),
+        escape_sql(url),
+        escape_sql(optimistic_hash)
+    );
+    let results: Vec<Webhook> = query(&query_str).fetch_all(&conn).await?;
+    results
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Inserted webhook not found"))
+}
+
+// the key level webhooks of an owner
+pub async fn get_webhooks_by_owner(owner: &str) -> Result<Vec<Webhook>, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "SELECT * FROM webhooks WHERE owner = \"{}\" AND optimistic_hash = \"\" AND is_active = 1 ORDER BY id ASC",
+        escape_sql(owner)
+    );
+    let results: Vec<Webhook> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results)
+}
+
+pub async fn get_webhook(id: u32) -> Result<Option<Webhook>, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!("SELECT * FROM webhooks WHERE id = {} LIMIT 1", id);
+    let results: Vec<Webhook> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results.into_iter().next())
+}
+
+// the callbacks of an upload and the webhooks of the key that uploaded it
+pub async fn get_bundle_webhooks(
+    optimistic_hash: &str,
+    key_id: Option<&str>,
+) -> Result<Vec<Webhook>, Error> {
+    let conn = ps_client().await?;
+    let key_condition = match key_id {
+        Some(key_id) => format!(
+            " OR (optimistic_hash = \"\" AND key_id = \"{}\")",
+            escape_sql(key_id)
+        ),
+        None => String::new(),
+    };
+    let query_str = format!(
+        "SELECT * FROM webhooks WHERE is_active = 1 AND (optimistic_hash = \"{}\"{})",
+        escape_sql(optimistic_hash),
+        key_condition
+    );
+    let results: Vec<Webhook> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results)
+}
+
+pub async fn deactivate_webhook(id: u32) -> Result<(), Error> {
+    let conn = ps_client().await?;
+    let query_str = format!("UPDATE webhooks SET is_active = 0 WHERE id = {}", id);
+    let res = query(&query_str).execute(&conn).await?;
+    println!("Deactivate webhook operation successful: {:?}", res);
+    Ok(())
+}
+
+pub async fn get_webhook_secret(owner: &str) -> Result<Option<WebhookSecret>, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "SELECT * FROM webhook_secrets WHERE owner = \"{}\" LIMIT 1",
+        escape_sql(owner)
+    );
+    let results: Vec<WebhookSecret> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results.into_iter().next())
+}
+
+// keeps the existing nonce of the owner, if any
+pub async fn insert_webhook_secret(owner: &str, nonce: &str, rotated_at: u64) -> Result<(), Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "INSERT IGNORE INTO webhook_secrets(owner, nonce, rotated_at) VALUES(\"{}\", \"{}\", {})",
+        escape_sql(owner),
+        escape_sql(nonce),
+        rotated_at
+    );
+    let res = query(&query_str).execute(&conn).await?;
+    println!("Insert webhook secret operation successful: {:?}", res);
+    Ok(())
+}
+
+pub async fn upsert_webhook_secret(owner: &str, nonce: &str, rotated_at: u64) -> Result<(), Error> {
+    let conn = ps_client().await?;
+    let owner = escape_sql(owner);
+    let nonce = escape_sql(nonce);
+    let query_str = format!(
+        "INSERT INTO webhook_secrets(owner, nonce, rotated_at) VALUES(\"{owner}\", \"{nonce}\", {rotated_at}) ON DUPLICATE KEY UPDATE nonce = \"{nonce}\", rotated_at = {rotated_at}"
+    );
+    let res = query(&query_str).execute(&conn).await?;
+    println!("Upsert webhook secret operation successful: {:?}", res);
+    Ok(())
+}
+
+pub async fn insert_webhook_delivery(delivery: &WebhookDelivery) -> Result<(), Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "INSERT INTO webhook_deliveries(webhook_id, delivery_id, event, optimistic_hash, attempt, status_code, success, error, created_at) VALUES({}, \"{}\", \"{}\", \"{}\", {}, {}, {}, \"{}\", {})",
+        delivery.webhook_id,
+        escape_sql(&delivery.delivery_id),
+        escape_sql(&delivery.event),
+        escape_sql(&delivery.optimistic_hash),
+        delivery.attempt,
+        delivery.status_code,
+        delivery.success as u8,
+        escape_sql(&delivery.error),
+        delivery.created_at
+    );
+    query(&query_str).execute(&conn).await?;
+    Ok(())
+}
+
+pub async fn get_webhook_deliveries(
+    webhook_id: u32,
+    limit: u32,
+) -> Result<Vec<WebhookDelivery>, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "SELECT * FROM webhook_deliveries WHERE webhook_id = {} ORDER BY id DESC LIMIT {}",
+        webhook_id, limit
+    );
+    let results: Vec<WebhookDelivery> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results)
+}
+
+// a retried settlement starts over from the same row
+pub async fn upsert_bundle_chunks(chunks: &BundleChunks) -> Result<(), Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "INSERT INTO bundle_chunks(optimistic_hash, status, chunks_count, chunks_propagated, chunk_txids, finalize_txid, error, started_at, updated_at) VALUES(\"{hash}\", \"{status}\", {count}, {propagated}, \"{txids}\", \"{finalize_txid}\", \"{error}\", {started_at}, {updated_at}) ON DUPLICATE KEY UPDATE status = \"{status}\", chunks_count = {count}, chunks_propagated = {propagated}, chunk_txids = \"{txids}\", finalize_txid = \"{finalize_txid}\", error = \"{error}\", started_at = {started_at}, updated_at = {updated_at}",
+        hash = escape_sql(&chunks.optimistic_hash),
+        status = escape_sql(&chunks.status),
+        count = chunks.chunks_count,
+        propagated = chunks.chunks_propagated,
+        txids = escape_sql(&chunks.chunk_txids),
+        finalize_txid = escape_sql(&chunks.finalize_txid),
+        error = escape_sql(&chunks.error),
+        started_at = chunks.started_at,
+        updated_at = chunks.updated_at,
+    );
+    let res = query(&query_str).execute(&conn).await?;
+    println!("Upsert bundle chunks operation successful: {:?}", res);
+    Ok(())
+}
+
+pub async fn get_bundle_chunks(optimistic_hash: &str) -> Result<Option<BundleChunks>, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "SELECT * FROM bundle_chunks WHERE optimistic_hash = \"{}\" LIMIT 1",
+        escape_sql(optimistic_hash)
+    );
+    let results: Vec<BundleChunks> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results.into_iter().next())
+}
+
+pub async fn upsert_settlement_cost(cost: &SettlementCost) -> Result<(), Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "INSERT INTO settlement_costs(optimistic_hash, owner, data_size, chunks_count, estimated_gas, estimated_cost_wei, gas_used, cost_wei, status, created_at, updated_at) VALUES(\"{hash}\", \"{owner}\", {data_size}, {chunks_count}, {estimated_gas}, {estimated_cost_wei}, {gas_used}, {cost_wei}, \"{status}\", {created_at}, {updated_at}) ON DUPLICATE KEY UPDATE data_size = {data_size}, chunks_count = {chunks_count}, estimated_gas = {estimated_gas}, estimated_cost_wei = {estimated_cost_wei}, gas_used = {gas_used}, cost_wei = {cost_wei}, status = \"{status}\", updated_at = {updated_at}",
+        hash = escape_sql(&cost.optimistic_hash),
+        owner = escape_sql(&cost.owner),
+        data_size = cost.data_size,
+        chunks_count = cost.chunks_count,
+        estimated_gas = cost.estimated_gas,
+        estimated_cost_wei = cost.estimated_cost_wei,
+        gas_used = cost.gas_used,
+        cost_wei = cost.cost_wei,
+        status = escape_sql(&cost.status),
+        created_at = cost.created_at,
+        updated_at = cost.updated_at,
+    );
+    let res = query(&query_str).execute(&conn).await?;
+    println!("Upsert settlement cost operation successful: {:?}", res);
+    Ok(())
+}
+
+pub async fn get_settlement_cost(optimistic_hash: &str) -> Result<Option<SettlementCost>, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "SELECT * FROM settlement_costs WHERE optimistic_hash = \"{}\" LIMIT 1",
+        escape_sql(optimistic_hash)
+    );
+    let results: Vec<SettlementCost> = query(&query_str).fetch_all(&conn).await?;
+    Ok(results.into_iter().next())
+}
+
+pub async fn get_owner_settlement_costs(owner: &str) -> Result<OwnerSettlementCosts, Error> {
+    let conn = ps_client().await?;
+    let query_str = format!(
+        "
+        SELECT
+            COUNT(*) as bundles,
+            CAST(COALESCE(SUM(CASE WHEN status = \"{recorded}\" THEN 1 ELSE 0 END), 0) AS UNSIGNED) as recorded_bundles,
+            CAST(COALESCE(SUM(data_size), 0) AS UNSIGNED) as bytes,
+            CAST(COALESCE(SUM(estimated_gas), 0) AS UNSIGNED) as estimated_gas,
+            COALESCE(SUM(estimated_cost_wei), 0) as estimated_cost_wei,
+            CAST(COALESCE(SUM(CASE WHEN status = \"{recorded}\" THEN gas_used ELSE 0 END), 0) AS UNSIGNED) as gas_used,
+            COALESCE(SUM(CASE WHEN status = \"{recorded}\" THEN cost_wei ELSE 0 END), 0) as cost_wei
+        FROM settlement_costs WHERE owner = \"{owner}\"",
+        recorded = COST_STATUS_RECORDED,
+        owner = escape_sql(owner),
+    );
+    let result: OwnerSettlementCosts = query(&query_str).fetch_one(&conn).await?;
+    Ok(result)
+}
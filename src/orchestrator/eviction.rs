@@ -0,0 +1,88 @@
+use crate::auth::provider::unix_now;
+use crate::orchestrator::cron::upload_key_id;
+use crate::orchestrator::db::{Bundle, get_expired_bundles, mark_bundle_evicted};
+use crate::server::events::{BundleEvent, publish};
+use crate::server::webhooks::{EVENT_EVICTED, WebhookBundle, notify};
+use crate::utils::get_env::get_env_var;
+use anyhow::{Error, anyhow};
+use reqwest::StatusCode;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+const EVICTION_BATCH: u32 = 100;
+
+static STORAGE_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .unwrap()
+});
+
+// deletes the objects of expired ephemeral bundles, the rows are kept (marked
+// evicted) so lookups can tell expired objects from unknown ones
+pub async fn evict_expired() -> Result<usize, Error> {
+    let expired = get_expired_bundles(unix_now(), EVICTION_BATCH).await?;
+    let mut evicted = 0;
+    for bundle in &expired {
+        match evict_bundle(bundle).await {
+            Ok(()) => evicted += 1,
+            Err(e) => println!("Error evicting bundle {}: {:?}", bundle.optimistic_hash, e),
+        }
+    }
+    Ok(evicted)
+}
+
+async fn evict_bundle(bundle: &Bundle) -> Result<(), Error> {
+    delete_object(bundle).await?;
+    mark_bundle_evicted(&bundle.optimistic_hash, unix_now()).await?;
+    println!("Evicted expired bundle {}", bundle.optimistic_hash);
+
+    publish(BundleEvent::new(
+        EVENT_EVICTED,
+        &bundle.optimistic_hash,
+        &bundle.owner,
+    ));
+    let key_id = upload_key_id(&bundle.optimistic_hash).await;
+    tokio::spawn(notify(
+        EVENT_EVICTED,
+        WebhookBundle::from(bundle),
+        key_id,
+        None,
+    ));
+    Ok(())
+}
+
+// an object that is already gone counts as deleted
+async fn delete_object(bundle: &Bundle) -> Result<(), Error> {
+    let supabase_url = get_env_var("SUPABASE_URL")?;
+    let api_key = get_env_var("SUPABASE_API_KEY")?;
+    let bucket_name = if bundle.is_private {
+        get_env_var("S3_PRIVATE_BUCKET_NAME")?
+    } else {
+        get_env_var("S3_BUCKET_NAME")?
+    };
+
+    let url = format!(
+        "{}/{}/{}",
+        supabase_url.replace("/v1/s3", "/v1/object"),
+        bucket_name,
+        bundle.optimistic_hash
+    );
+    let response = STORAGE_CLIENT
+        .delete(&url)
+        .header("apikey", &api_key)
+        .header("Authorization", format!("Bearer {}", api_key))
+        .send()
+        .await?;
+
+    let status = response.status();
+    if status.is_success() || status == StatusCode::NOT_FOUND {
+        return Ok(());
+    }
+    let error_text = response.text().await.unwrap_or_default();
+    Err(anyhow!(
+        "Failed to delete object: HTTP {}: {}",
+        status,
+        error_text
+    ))
+}
@@ -1,2 +1,9 @@
+pub mod cost;
 pub mod cron;
 pub mod db;
+pub mod eviction;
+pub mod progress;
+pub mod queue;
+pub mod search;
+pub mod stats;
+pub mod storage_class;
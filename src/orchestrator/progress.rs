@@ -0,0 +1,159 @@
+use crate::orchestrator::db::{Bundle, BundleChunks};
+use crate::orchestrator::queue::SettlementLane;
+use crate::utils::constants::FOUR_MB;
+use serde::Serialize;
+
+// bundles without a bundle_chunks row are queued, or were settled before
+// progress was recorded
+pub const STATUS_QUEUED: &str = "queued";
+pub const STATUS_PROPAGATING: &str = "propagating";
+pub const STATUS_PROPAGATED: &str = "propagated";
+pub const STATUS_FINALIZED: &str = "finalized";
+pub const STATUS_FAILED: &str = "failed";
+
+pub fn chunks_count(data_size: u64) -> u32 {
+    data_size.div_ceil(FOUR_MB as u64) as u32
+}
+
+impl BundleChunks {
+    pub fn started(optimistic_hash: &str, chunks_count: u32, now: u64) -> Self {
+        Self {
+            optimistic_hash: optimistic_hash.to_string(),
+            status: STATUS_PROPAGATING.to_string(),
+            chunks_count,
+            chunks_propagated: 0,
+            chunk_txids: "[]".to_string(),
+            finalize_txid: String::new(),
+            error: String::new(),
+            started_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn propagated(&mut self, chunk_txids: &[String], now: u64) {
+        self.status = STATUS_PROPAGATED.to_string();
+        self.chunks_propagated = chunk_txids.len() as u32;
+        self.chunk_txids = serde_json::to_string(chunk_txids).unwrap_or_else(|_| "[]".to_string());
+        self.updated_at = now;
+    }
+
+    pub fn finalized(&mut self, finalize_txid: &str, now: u64) {
+        self.status = STATUS_FINALIZED.to_string();
+        self.finalize_txid = finalize_txid.to_string();
+        self.updated_at = now;
+    }
+
+    // the chunks propagated so far are kept, they show how far it got
+    pub fn failed(&mut self, error: &str, now: u64) {
+        self.status = STATUS_FAILED.to_string();
+        self.error = error.chars().take(1024).collect();
+        self.updated_at = now;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SettlementProgress {
+    pub optimistic_hash: String,
+    pub status: String,
+    pub lane: String,
+    pub is_settled: bool,
+    pub chunks_count: u32,
+    pub chunks_propagated: u32,
+    pub chunk_txids: Vec<String>,
+    pub finalize_txid: String,
+    pub error: String,
+    pub started_at: u64,
+    pub updated_at: u64,
+}
+
+impl SettlementProgress {
+    pub fn new(bundle: &Bundle, chunks: Option<BundleChunks>) -> Self {
+        let lane = SettlementLane::of(bundle.priority, bundle.data_size)
+            .as_str()
+            .to_string();
+        let Some(chunks) = chunks else {
+            let (status, finalize_txid) = if bundle.is_settled {
+                (STATUS_FINALIZED, bundle.bundle_txid.clone())
+            } else {
+                (STATUS_QUEUED, String::new())
+            };
+            return Self {
+                optimistic_hash: bundle.optimistic_hash.clone(),
+                status: status.to_string(),
+                lane,
+                is_settled: bundle.is_settled,
+                chunks_count: chunks_count(bundle.data_size),
+                chunks_propagated: 0,
+                chunk_txids: Vec::new(),
+                finalize_txid,
+                error: String::new(),
+                started_at: 0,
+                updated_at: bundle.settled_at,
+            };
+        };
+
+        Self {
+            optimistic_hash: chunks.optimistic_hash,
+            status: chunks.status,
+            lane,
+            is_settled: bundle.is_settled,
+            chunks_count: chunks.chunks_count,
+            chunks_propagated: chunks.chunks_propagated,
+            chunk_txids: serde_json::from_str(&chunks.chunk_txids).unwrap_or_default(),
+            finalize_txid: chunks.finalize_txid,
+            error: chunks.error,
+            started_at: chunks.started_at,
+            updated_at: chunks.updated_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::orchestrator::db::{Bundle, BundleChunks};
+    use crate::orchestrator::progress::{SettlementProgress, chunks_count};
+
+    #[test]
+    pub fn test_settlement_progress() {
+        assert_eq!(chunks_count(0), 0);
+        assert_eq!(chunks_count(1), 1);
+        assert_eq!(chunks_count(4 * 1024 * 1024), 1);
+        assert_eq!(chunks_count(4 * 1024 * 1024 + 1), 2);
+
+        let mut chunks = BundleChunks::started("0xabc", 2, 10);
+        assert_eq!(chunks.status, "propagating");
+        chunks.propagated(&["0xc1".to_string(), "0xc2".to_string()], 20);
+        assert_eq!(chunks.chunk_txids, r#"["0xc1","0xc2"]"#);
+        chunks.finalized("0xfinal", 30);
+
+        let bundle = Bundle {
+            optimistic_hash: "0xabc".to_string(),
+            data_size: 5 * 1024 * 1024,
+            ..Default::default()
+        };
+        let progress = SettlementProgress::new(&bundle, Some(chunks.clone()));
+        assert_eq!(progress.status, "finalized");
+        assert_eq!(progress.lane, "standard");
+        assert_eq!(progress.chunks_propagated, 2);
+        assert_eq!(progress.chunk_txids, vec!["0xc1", "0xc2"]);
+        assert_eq!(progress.finalize_txid, "0xfinal");
+        assert_eq!(progress.started_at, 10);
+
+        chunks.failed("boom", 40);
+        assert_eq!(chunks.status, "failed");
+        assert_eq!(chunks.chunks_propagated, 2);
+
+        let queued = SettlementProgress::new(&bundle, None);
+        assert_eq!(queued.status, "queued");
+        assert_eq!(queued.chunks_count, 2);
+
+        let settled = Bundle {
+            is_settled: true,
+            bundle_txid: "0xold".to_string(),
+            ..bundle
+        };
+        let settled = SettlementProgress::new(&settled, None);
+        assert_eq!(settled.status, "finalized");
+        assert_eq!(settled.finalize_txid, "0xold");
+    }
+}
@@ -0,0 +1,226 @@
+use crate::orchestrator::db::Bundle;
+use crate::utils::constants::{
+    FAST_LANE_MAX_BYTES, HUGE_LANE_MIN_BYTES, SETTLE_RETRY_BASE_SECS, SETTLE_RETRY_MAX_SECS,
+};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+// huge objects take minutes to propagate, they get their own lane so they
+// don't hold everything else back, and paid uploads go first in it too
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettlementLane {
+    Priority,
+    Fast,
+    Standard,
+    Huge,
+}
+
+pub const SETTLEMENT_LANES: [SettlementLane; 4] = [
+    SettlementLane::Priority,
+    SettlementLane::Fast,
+    SettlementLane::Standard,
+    SettlementLane::Huge,
+];
+
+impl SettlementLane {
+    pub fn of(priority: u8, data_size: u64) -> Self {
+        if data_size >= HUGE_LANE_MIN_BYTES {
+            SettlementLane::Huge
+        } else if priority > 0 {
+            SettlementLane::Priority
+        } else if data_size <= FAST_LANE_MAX_BYTES {
+            SettlementLane::Fast
+        } else {
+            SettlementLane::Standard
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SettlementLane::Priority => "priority",
+            SettlementLane::Fast => "fast",
+            SettlementLane::Standard => "standard",
+            SettlementLane::Huge => "huge",
+        }
+    }
+
+    // settlements per round while every lane has work queued
+    pub fn weight(&self) -> i64 {
+        match self {
+            SettlementLane::Priority => 8,
+            SettlementLane::Fast => 4,
+            SettlementLane::Standard => 2,
+            SettlementLane::Huge => 1,
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+// the same lanes as SettlementLane::of, as a SQL expression
+pub fn settlement_lane_sql() -> String {
+    format!(
+        "CASE WHEN data_size >= {} THEN 'huge' WHEN priority > 0 THEN 'priority' WHEN data_size <= {} THEN 'fast' ELSE 'standard' END",
+        HUGE_LANE_MIN_BYTES, FAST_LANE_MAX_BYTES
+    )
+}
+
+// a bundle that failed, or a manifest waiting for its files, is retried after
+// a delay doubling with each attempt, and the next bundle of its owner goes
+// first meanwhile
+pub fn retry_delay(attempts: u32) -> u64 {
+    SETTLE_RETRY_BASE_SECS
+        .saturating_mul(1 << attempts.min(16))
+        .min(SETTLE_RETRY_MAX_SECS)
+}
+
+// picks the next bundle to settle: lanes take turns by weight (smooth
+// weighted round robin), and within a lane the owner served the longest ago
+// goes first, so one owner's backlog doesn't starve the others
+#[derive(Debug, Default)]
+pub struct SettlementQueue {
+    credits: [i64; 4],
+    turn: u64,
+    last_served: HashMap<String, u64>,
+}
+
+impl SettlementQueue {
+    // `candidates` holds the oldest unsettled bundle of every owner and lane
+    // that is due, the ones waiting for a retry are skipped
+    pub fn pick<'a>(&mut self, candidates: &'a [Bundle], now: u64) -> Option<&'a Bundle> {
+        let candidates: Vec<&Bundle> = candidates
+            .iter()
+            .filter(|bundle| bundle.next_attempt_at <= now)
+            .collect();
+        let lane_of = |bundle: &Bundle| SettlementLane::of(bundle.priority, bundle.data_size);
+        let active: Vec<SettlementLane> = SETTLEMENT_LANES
+            .into_iter()
+            .filter(|lane| candidates.iter().any(|bundle| lane_of(bundle) == *lane))
+            .collect();
+
+        for lane in SETTLEMENT_LANES {
+            if active.contains(&lane) {
+                self.credits[lane.index()] += lane.weight();
+            } else {
+                self.credits[lane.index()] = 0;
+            }
+        }
+        // ties go to the higher lane
+        let lane = *active
+            .iter()
+            .max_by_key(|lane| (self.credits[lane.index()], Reverse(lane.index())))?;
+        self.credits[lane.index()] -= active.iter().map(|lane| lane.weight()).sum::<i64>();
+
+        let bundle = candidates
+            .iter()
+            .copied()
+            .filter(|bundle| lane_of(bundle) == lane)
+            .min_by_key(|bundle| {
+                (
+                    Reverse(bundle.priority),
+                    self.last_served.get(&bundle.owner).copied().unwrap_or(0),
+                    bundle.id,
+                )
+            })?;
+
+        self.turn += 1;
+        // owners with nothing queued start over when they come back
+        self.last_served
+            .retain(|owner, _| candidates.iter().any(|bundle| bundle.owner == *owner));
+        self.last_served.insert(bundle.owner.clone(), self.turn);
+        Some(bundle)
+    }
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::orchestrator::db::Bundle;
+    use crate::orchestrator::queue::{SettlementLane, SettlementQueue, retry_delay};
+
+    fn bundle(id: u32, owner: &str, priority: u8, data_size: u64) -> Bundle {
+        Bundle {
+            id,
+            owner: owner.to_string(),
+            priority,
+            data_size,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_settlement_queue_lanes() {
+        assert_eq!(SettlementLane::of(0, 1024), SettlementLane::Fast);
+        assert_eq!(SettlementLane::of(1, 1024), SettlementLane::Priority);
+        assert_eq!(
+            SettlementLane::of(0, 10 * 1024 * 1024),
+            SettlementLane::Standard
+        );
+        assert_eq!(
+            SettlementLane::of(1, 1024 * 1024 * 1024),
+            SettlementLane::Huge
+        );
+
+        // every lane gets its weight out of 15 picks, huge ones included
+        let candidates = vec![
+            bundle(1, "a", 1, 1024),
+            bundle(2, "a", 0, 1024),
+            bundle(3, "a", 0, 10 * 1024 * 1024),
+            bundle(4, "a", 0, 1024 * 1024 * 1024),
+        ];
+        let mut queue = SettlementQueue::default();
+        let mut picks = [0; 5];
+        for _ in 0..15 {
+            picks[queue.pick(&candidates, 0).unwrap().id as usize] += 1;
+        }
+        assert_eq!(picks, [0, 8, 4, 2, 1]);
+        assert_eq!(queue.pick(&candidates, 0).unwrap().id, 1);
+
+        // owners take turns within a lane, whatever their backlog
+        let candidates = vec![
+            bundle(1, "bulk", 0, 1024),
+            bundle(7, "small", 0, 1024),
+            bundle(9, "other", 0, 1024),
+        ];
+        let mut queue = SettlementQueue::default();
+        let order: Vec<u32> = (0..6)
+            .map(|_| queue.pick(&candidates, 0).unwrap().id)
+            .collect();
+        assert_eq!(order, vec![1, 7, 9, 1, 7, 9]);
+
+        assert!(queue.pick(&[], 0).is_none());
+    }
+
+    #[test]
+    pub fn test_settlement_queue_retries() {
+        assert_eq!(retry_delay(0), 60);
+        assert_eq!(retry_delay(1), 120);
+        assert_eq!(retry_delay(5), 32 * 60);
+        assert_eq!(retry_delay(6), 60 * 60);
+        assert_eq!(retry_delay(u32::MAX), 60 * 60);
+
+        // a bundle waiting for its retry doesn't hold its owner's next one
+        // back, nor another owner's
+        let failing = Bundle {
+            next_attempt_at: 1_060,
+            settle_attempts: 1,
+            ..bundle(1, "a", 0, 1024)
+        };
+        let candidates = vec![failing, bundle(2, "a", 0, 1024), bundle(3, "b", 0, 1024)];
+        let mut queue = SettlementQueue::default();
+        let order: Vec<u32> = (0..4)
+            .map(|_| queue.pick(&candidates, 1_000).unwrap().id)
+            .collect();
+        assert_eq!(order, vec![2, 3, 2, 3]);
+
+        // it gets its turn back once due
+        assert_eq!(queue.pick(&candidates, 1_060).unwrap().id, 1);
+
+        let waiting = vec![Bundle {
+            next_attempt_at: 1_060,
+            ..bundle(1, "a", 0, 1024)
+        }];
+        assert!(queue.pick(&waiting, 1_000).is_none());
+    }
+}
@@ -0,0 +1,213 @@
+use crate::orchestrator::db::escape_sql;
+use anyhow::{Error, anyhow};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BundleSort {
+    #[default]
+    Id,
+    Size,
+    CreatedAt,
+}
+
+impl BundleSort {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "id" => Some(BundleSort::Id),
+            "size" => Some(BundleSort::Size),
+            "created_at" => Some(BundleSort::CreatedAt),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BundleSort::Id => "id",
+            BundleSort::Size => "size",
+            BundleSort::CreatedAt => "created_at",
+        }
+    }
+
+    fn column(&self) -> &'static str {
+        match self {
+            BundleSort::Id => "id",
+            BundleSort::Size => "data_size",
+            BundleSort::CreatedAt => "created_at",
+        }
+    }
+}
+
+// position after the last returned row, (sort value, id) is unique so pages
+// never skip or repeat rows even when the sort value has ties
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BundleCursor {
+    pub sort: BundleSort,
+    pub value: u64,
+    pub id: u32,
+}
+
+impl BundleCursor {
+    pub fn encode(&self) -> String {
+        hex::encode(format!("{}:{}:{}", self.sort.name(), self.value, self.id))
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, Error> {
+        let raw = String::from_utf8(hex::decode(cursor)?)?;
+        let mut parts = raw.split(':');
+        let (Some(sort), Some(value), Some(id), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(anyhow!("invalid cursor"));
+        };
+
+        Ok(Self {
+            sort: BundleSort::parse(sort).ok_or_else(|| anyhow!("invalid cursor"))?,
+            value: value.parse()?,
+            id: id.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BundleQuery {
+    // None lists every owner, only allowed for the internal key
+    pub owner: Option<String>,
+    pub content_type_prefix: Option<String>,
+    pub is_settled: Option<bool>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub created_after: Option<u64>,
+    pub created_before: Option<u64>,
+    pub tag: Option<String>,
+    pub meta: Option<(String, String)>,
+    pub sort: BundleSort,
+    pub descending: bool,
+    pub cursor: Option<BundleCursor>,
+    pub limit: u32,
+}
+
+pub fn is_valid_content_type_prefix(prefix: &str) -> bool {
+    !prefix.is_empty()
+        && prefix.len() <= 255
+        && prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/.+-".contains(c))
+}
+
+impl BundleQuery {
+    pub fn to_sql(&self) -> String {
+        let mut conditions = Vec::new();
+
+        if let Some(owner) = &self.owner {
+            conditions.push(format!("owner = \"{}\"", escape_sql(owner)));
+        }
+        // the prefix is validated by is_valid_content_type_prefix, no LIKE
+        // wildcards can get in
+        if let Some(prefix) = &self.content_type_prefix {
+            conditions.push(format!("content_type LIKE \"{}%\"", escape_sql(prefix)));
+        }
+        if let Some(is_settled) = self.is_settled {
+            conditions.push(format!("is_settled = {}", is_settled as u8));
+        }
+        if let Some(min_size) = self.min_size {
+            conditions.push(format!("data_size >= {}", min_size));
+        }
+        if let Some(max_size) = self.max_size {
+            conditions.push(format!("data_size <= {}", max_size));
+        }
+        if let Some(created_after) = self.created_after {
+            conditions.push(format!("created_at >= {}", created_after));
+        }
+        if let Some(created_before) = self.created_before {
+            conditions.push(format!("created_at < {}", created_before));
+        }
+        if let Some(tag) = &self.tag {
+            conditions.push(format!(
+                "optimistic_hash IN (SELECT optimistic_hash FROM bundle_tags WHERE kind = \"tag\" AND name = \"{}\")",
+                escape_sql(tag)
+            ));
+        }
+        if let Some((name, value)) = &self.meta {
+            conditions.push(format!(
+                "optimistic_hash IN (SELECT optimistic_hash FROM bundle_tags WHERE kind = \"meta\" AND name = \"{}\" AND value = \"{}\")",
+                escape_sql(name),
+                escape_sql(value)
+            ));
+        }
+
+        let column = self.sort.column();
+        let (direction, comparison) = if self.descending {
+            ("DESC", "<")
+        } else {
+            ("ASC", ">")
+        };
+        if let Some(cursor) = &self.cursor {
+            conditions.push(match self.sort {
+                BundleSort::Id => format!("id {} {}", comparison, cursor.id),
+                _ => format!(
+                    "({}, id) {} ({}, {})",
+                    column, comparison, cursor.value, cursor.id
+                ),
+            });
+        }
+
+        let where_clause = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", conditions.join(" AND "))
+        };
+        let order_by = match self.sort {
+            BundleSort::Id => format!("id {}", direction),
+            _ => format!("{} {}, id {}", column, direction, direction),
+        };
+
+        // one extra row tells whether there is a next page
+        format!(
+            "SELECT * FROM bundles{} ORDER BY {} LIMIT {}",
+            where_clause,
+            order_by,
+            self.limit + 1
+        )
+    }
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::orchestrator::search::{BundleCursor, BundleQuery, BundleSort};
+
+    #[test]
+    pub fn test_bundle_query_to_sql() {
+        let cursor = BundleCursor {
+            sort: BundleSort::Size,
+            value: 1024,
+            id: 42,
+        };
+        assert_eq!(BundleCursor::decode(&cursor.encode()).unwrap(), cursor);
+        assert!(BundleCursor::decode("zz").is_err());
+
+        let query = BundleQuery {
+            owner: Some("team\"a".to_string()),
+            content_type_prefix: Some("image/".to_string()),
+            is_settled: Some(true),
+            min_size: Some(10),
+            tag: Some("docs".to_string()),
+            sort: BundleSort::Size,
+            descending: true,
+            cursor: Some(cursor),
+            limit: 50,
+            ..Default::default()
+        };
+        assert_eq!(
+            query.to_sql(),
+            "SELECT * FROM bundles WHERE owner = \"team\\\"a\" AND content_type LIKE \"image/%\" AND is_settled = 1 AND data_size >= 10 AND optimistic_hash IN (SELECT optimistic_hash FROM bundle_tags WHERE kind = \"tag\" AND name = \"docs\") AND (data_size, id) < (1024, 42) ORDER BY data_size DESC, id DESC LIMIT 51"
+        );
+
+        let query = BundleQuery {
+            limit: 10,
+            ..Default::default()
+        };
+        assert_eq!(
+            query.to_sql(),
+            "SELECT * FROM bundles ORDER BY id ASC LIMIT 11"
+        );
+    }
+}
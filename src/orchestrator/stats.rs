@@ -0,0 +1,265 @@
+use crate::orchestrator::db::{
+    BundleStats, StatsGroup, StatsSeriesRow, get_bundle_groups, get_bundle_series,
+    get_bundle_stats, get_settlement_latencies,
+};
+use crate::utils::constants::{FOUR_MB, SETTLEMENT_LATENCY_WINDOW_SECS};
+use anyhow::Error;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const HOURLY_POINTS: u64 = 48;
+const DAILY_POINTS: u64 = 30;
+const MAX_GROUPS: u32 = 50;
+
+// (label, exclusive upper bound in bytes), larger objects fall in LARGEST_BUCKET
+pub const SIZE_BUCKETS: &[(&str, u64)] = &[
+    ("lt_1kb", 1024),
+    ("lt_1mb", 1024 * 1024),
+    ("lt_4mb", FOUR_MB as u64),
+    ("lt_100mb", 100 * 1024 * 1024),
+    ("lt_1gb", 1024 * 1024 * 1024),
+];
+pub const LARGEST_BUCKET: &str = "gte_1gb";
+
+const SETTLEMENT_STATUS_SQL: &str =
+    "CASE WHEN is_settled = 1 THEN 'settled' WHEN is_private = 1 THEN 'private' ELSE 'pending' END";
+
+// settlement latency (seconds from upload to settlement) percentiles
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LatencyPercentiles {
+    pub count: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl LatencyPercentiles {
+    pub fn from_latencies(mut latencies: Vec<u64>) -> Self {
+        if latencies.is_empty() {
+            return Self::default();
+        }
+        latencies.sort_unstable();
+
+        Self {
+            count: latencies.len() as u64,
+            p50: percentile(&latencies, 50),
+            p90: percentile(&latencies, 90),
+            p99: percentile(&latencies, 99),
+            max: latencies[latencies.len() - 1],
+        }
+    }
+}
+
+// nearest-rank percentile of a sorted, non empty slice
+fn percentile(sorted: &[u64], p: usize) -> u64 {
+    let rank = (p * sorted.len()).div_ceil(100);
+    sorted[rank.saturating_sub(1)]
+}
+
+pub fn size_bucket_sql() -> String {
+    let cases: Vec<String> = SIZE_BUCKETS
+        .iter()
+        .map(|(label, bound)| format!("WHEN data_size < {} THEN '{}'", bound, label))
+        .collect();
+    format!("CASE {} ELSE '{}' END", cases.join(" "), LARGEST_BUCKET)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TimeSeriesPoint {
+    pub start: u64,
+    pub uploads: u64,
+    pub upload_bytes: u64,
+    pub settlements: u64,
+    pub settled_bytes: u64,
+}
+
+// `points` buckets of `step` seconds ending with the one containing `now`,
+// buckets without rows are zero filled
+pub fn time_series(
+    now: u64,
+    step: u64,
+    points: u64,
+    uploads: &[StatsSeriesRow],
+    settlements: &[StatsSeriesRow],
+) -> Vec<TimeSeriesPoint> {
+    let first = (now / step).saturating_sub(points - 1) * step;
+    let mut series: Vec<TimeSeriesPoint> = (0..points)
+        .map(|i| TimeSeriesPoint {
+            start: first + i * step,
+            ..Default::default()
+        })
+        .collect();
+
+    let index = |bucket: u64| {
+        (bucket >= first && bucket < first + points * step)
+            .then(|| ((bucket - first) / step) as usize)
+    };
+    for row in uploads {
+        if let Some(i) = index(row.bucket) {
+            series[i].uploads += row.count;
+            series[i].upload_bytes += row.bytes;
+        }
+    }
+    for row in settlements {
+        if let Some(i) = index(row.bucket) {
+            series[i].settlements += row.count;
+            series[i].settled_bytes += row.bytes;
+        }
+    }
+    series
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsSnapshot {
+    #[serde(flatten)]
+    pub totals: BundleStats,
+    pub settlement_latency: LatencyPercentiles,
+    pub by_content_type: Vec<StatsGroup>,
+    pub by_owner: Vec<StatsGroup>,
+    pub by_settlement_status: Vec<StatsGroup>,
+    pub by_size: Vec<StatsGroup>,
+    pub hourly: Vec<TimeSeriesPoint>,
+    pub daily: Vec<TimeSeriesPoint>,
+    pub generated_at: u64,
+}
+
+pub async fn load_stats_snapshot(now: u64) -> Result<StatsSnapshot, Error> {
+    let hourly_since = (now / HOUR).saturating_sub(HOURLY_POINTS - 1) * HOUR;
+    let daily_since = (now / DAY).saturating_sub(DAILY_POINTS - 1) * DAY;
+
+    let hourly = time_series(
+        now,
+        HOUR,
+        HOURLY_POINTS,
+        &get_bundle_series("created_at", HOUR, hourly_since).await?,
+        &get_bundle_series("settled_at", HOUR, hourly_since).await?,
+    );
+    let daily = time_series(
+        now,
+        DAY,
+        DAILY_POINTS,
+        &get_bundle_series("created_at", DAY, daily_since).await?,
+        &get_bundle_series("settled_at", DAY, daily_since).await?,
+    );
+
+    let latencies =
+        get_settlement_latencies(now.saturating_sub(SETTLEMENT_LATENCY_WINDOW_SECS)).await?;
+
+    Ok(StatsSnapshot {
+        totals: get_bundle_stats().await?,
+        settlement_latency: LatencyPercentiles::from_latencies(latencies),
+        by_content_type: get_bundle_groups("content_type", MAX_GROUPS).await?,
+        by_owner: get_bundle_groups("owner", MAX_GROUPS).await?,
+        by_settlement_status: get_bundle_groups(SETTLEMENT_STATUS_SQL, MAX_GROUPS).await?,
+        by_size: get_bundle_groups(&size_bucket_sql(), MAX_GROUPS).await?,
+        hourly,
+        daily,
+        generated_at: now,
+    })
+}
+
+// keeps the last aggregate for `ttl`, concurrent requests on expiry wait for
+// a single refresh instead of each scanning the table
+pub struct StatsCache<T> {
+    ttl: Duration,
+    entry: Mutex<Option<(Instant, Arc<T>)>>,
+}
+
+impl<T> StatsCache<T> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entry: Mutex::new(None),
+        }
+    }
+
+    pub async fn get_or_refresh<F, Fut>(&self, refresh: F) -> Result<Arc<T>, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut entry = self.entry.lock().await;
+        if let Some((loaded_at, value)) = entry.as_ref()
+            && loaded_at.elapsed() < self.ttl
+        {
+            return Ok(value.clone());
+        }
+
+        let value = Arc::new(refresh().await?);
+        *entry = Some((Instant::now(), value.clone()));
+        Ok(value)
+    }
+}
+
+pub fn get_stats_cache_ttl() -> Duration {
+    let secs = std::env::var("STATS_CACHE_TTL_SECS")
+        .ok()
+        .and_then(|val| val.parse::<u64>().ok())
+        .unwrap_or(60);
+    Duration::from_secs(secs)
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::orchestrator::db::StatsSeriesRow;
+    use crate::orchestrator::stats::{
+        LatencyPercentiles, StatsCache, size_bucket_sql, time_series,
+    };
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_latency_percentiles() {
+        let latencies: Vec<u64> = (1..=100).rev().collect();
+        let percentiles = LatencyPercentiles::from_latencies(latencies);
+        assert_eq!(percentiles.count, 100);
+        assert_eq!(percentiles.p50, 50);
+        assert_eq!(percentiles.p90, 90);
+        assert_eq!(percentiles.p99, 99);
+        assert_eq!(percentiles.max, 100);
+
+        let single = LatencyPercentiles::from_latencies(vec![7]);
+        assert_eq!((single.p50, single.p99, single.max), (7, 7, 7));
+        assert_eq!(
+            LatencyPercentiles::from_latencies(Vec::new()),
+            LatencyPercentiles::default()
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_stats_aggregates() {
+        assert!(size_bucket_sql().starts_with("CASE WHEN data_size < 1024 THEN 'lt_1kb'"));
+        assert!(size_bucket_sql().ends_with("ELSE 'gte_1gb' END"));
+
+        let row = |bucket, count, bytes| StatsSeriesRow {
+            bucket,
+            count,
+            bytes,
+        };
+        // 3 hourly buckets ending with the one of `now` (10_800..14_400)
+        let series = time_series(
+            12_000,
+            3600,
+            3,
+            &[row(3600, 2, 20), row(10_800, 1, 5), row(0, 9, 9)],
+            &[row(7200, 1, 7)],
+        );
+        assert_eq!(
+            series.iter().map(|p| p.start).collect::<Vec<_>>(),
+            vec![3600, 7200, 10_800]
+        );
+        assert_eq!((series[0].uploads, series[0].upload_bytes), (2, 20));
+        assert_eq!((series[1].settlements, series[1].settled_bytes), (1, 7));
+        assert_eq!(series[2].uploads, 1);
+
+        let cache = StatsCache::new(Duration::from_secs(60));
+        let first = cache.get_or_refresh(|| async { Ok(1) }).await.unwrap();
+        let second = cache.get_or_refresh(|| async { Ok(2) }).await.unwrap();
+        assert_eq!((*first, *second), (1, 1));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::orchestrator::db::Bundle;
+use crate::r#static::{EPHEMERAL_DEFAULT_TTL_SECS, EPHEMERAL_MAX_TTL_SECS};
+
+// only kept in object storage until its TTL, never settled
+pub const STORAGE_CLASS_EPHEMERAL: &str = "ephemeral";
+// served optimistically and settled by the orchestrator, the default
+pub const STORAGE_CLASS_STANDARD: &str = "standard";
+// the upload only returns once the object is settled
+pub const STORAGE_CLASS_PERMANENT_NOW: &str = "permanent-now";
+
+pub const STORAGE_CLASSES: [&str; 3] = [
+    STORAGE_CLASS_EPHEMERAL,
+    STORAGE_CLASS_STANDARD,
+    STORAGE_CLASS_PERMANENT_NOW,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageClass {
+    pub class: &'static str,
+    // unix seconds, 0 unless ephemeral
+    pub expires_at: u64,
+}
+
+// `ttl` (seconds) is only accepted with the ephemeral class
+pub fn parse_storage_class(
+    class: Option<&str>,
+    ttl: Option<u64>,
+    now: u64,
+) -> Result<StorageClass, String> {
+    let class = match class.map(|class| class.to_ascii_lowercase()) {
+        None => STORAGE_CLASS_STANDARD,
+        Some(class) => STORAGE_CLASSES
+            .into_iter()
+            .find(|known| *known == class)
+            .ok_or_else(|| {
+                format!(
+                    "storage_class must be one of {}",
+                    STORAGE_CLASSES.join(", ")
+                )
+            })?,
+    };
+
+    if class != STORAGE_CLASS_EPHEMERAL {
+        if ttl.is_some() {
+            return Err("ttl is only supported with the ephemeral storage_class".to_string());
+        }
+        return Ok(StorageClass {
+            class,
+            expires_at: 0,
+        });
+    }
+
+    let ttl = ttl.unwrap_or(*EPHEMERAL_DEFAULT_TTL_SECS);
+    if ttl == 0 || ttl > *EPHEMERAL_MAX_TTL_SECS {
+        return Err(format!(
+            "ttl must be between 1 and {} seconds",
+            *EPHEMERAL_MAX_TTL_SECS
+        ));
+    }
+    Ok(StorageClass {
+        class,
+        expires_at: now + ttl,
+    })
+}
+
+// expired objects are gone, or about to be, before the eviction loop gets to
+// them
+pub fn is_expired(bundle: &Bundle, now: u64) -> bool {
+    bundle.evicted_at > 0
+        || (bundle.storage_class == STORAGE_CLASS_EPHEMERAL
+            && bundle.expires_at > 0
+            && bundle.expires_at <= now)
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::orchestrator::db::Bundle;
+    use crate::orchestrator::storage_class::{is_expired, parse_storage_class};
+
+    #[test]
+    pub fn test_storage_class() {
+        let standard = parse_storage_class(None, None, 100).unwrap();
+        assert_eq!(standard.class, "standard");
+        assert_eq!(standard.expires_at, 0);
+
+        let permanent = parse_storage_class(Some("Permanent-Now"), None, 100).unwrap();
+        assert_eq!(permanent.class, "permanent-now");
+        assert!(parse_storage_class(Some("permanent-now"), Some(60), 100).is_err());
+        assert!(parse_storage_class(Some("archive"), None, 100).is_err());
+
+        let ephemeral = parse_storage_class(Some("ephemeral"), Some(60), 100).unwrap();
+        assert_eq!(ephemeral.expires_at, 160);
+        let default_ttl = parse_storage_class(Some("ephemeral"), None, 100).unwrap();
+        assert_eq!(default_ttl.expires_at, 100 + 7 * 24 * 60 * 60);
+        assert!(parse_storage_class(Some("ephemeral"), Some(0), 100).is_err());
+        assert!(parse_storage_class(Some("ephemeral"), Some(365 * 24 * 60 * 60), 100).is_err());
+
+        let bundle = Bundle {
+            storage_class: "ephemeral".to_string(),
+            expires_at: 160,
+            ..Default::default()
+        };
+        assert!(!is_expired(&bundle, 159));
+        assert!(is_expired(&bundle, 160));
+
+        let standard = Bundle {
+            storage_class: "standard".to_string(),
+            ..Default::default()
+        };
+        assert!(!is_expired(&standard, u64::MAX));
+        let evicted = Bundle {
+            evicted_at: 1,
+            ..standard
+        };
+        assert!(is_expired(&evicted, 0));
+    }
+}
@@ -0,0 +1,159 @@
+use crate::server::manifest::normalize_path;
+use flate2::read::GzDecoder;
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+// by magic bytes, the declared content type is only a hint for plain tar
+// archives which have no magic at offset 0
+pub fn detect_archive(content_type: &str, data: &[u8]) -> Option<ArchiveKind> {
+    if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+        return Some(ArchiveKind::Zip);
+    }
+    if data.starts_with(&[0x1f, 0x8b]) {
+        return Some(ArchiveKind::TarGz);
+    }
+    let is_ustar = data.get(257..262) == Some(b"ustar".as_slice());
+    let is_tar_type = matches!(content_type, "application/x-tar" | "application/tar");
+    (is_ustar || is_tar_type).then_some(ArchiveKind::Tar)
+}
+
+// regular files of the archive by normalized path. `max_bytes` bounds the
+// extracted size so small archives can't expand without limit
+pub fn extract_archive(
+    kind: ArchiveKind,
+    data: &[u8],
+    max_files: usize,
+    max_bytes: u64,
+) -> Result<BTreeMap<String, Vec<u8>>, String> {
+    let mut files = ExtractedFiles {
+        files: BTreeMap::new(),
+        max_files,
+        remaining_bytes: max_bytes,
+    };
+
+    match kind {
+        ArchiveKind::Tar => extract_tar(data, &mut files)?,
+        ArchiveKind::TarGz => extract_tar(GzDecoder::new(data), &mut files)?,
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(Cursor::new(data))
+                .map_err(|e| format!("invalid zip archive: {}", e))?;
+            for i in 0..archive.len() {
+                let entry = archive
+                    .by_index(i)
+                    .map_err(|e| format!("invalid zip entry: {}", e))?;
+                if !entry.is_file() {
+                    continue;
+                }
+                let path = entry.name().to_string();
+                files.add(&path, entry)?;
+            }
+        }
+    }
+
+    Ok(files.files)
+}
+
+fn extract_tar<R: Read>(reader: R, files: &mut ExtractedFiles) -> Result<(), String> {
+    let mut archive = tar::Archive::new(reader);
+    let entries = archive
+        .entries()
+        .map_err(|e| format!("invalid tar archive: {}", e))?;
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("invalid tar entry: {}", e))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry
+            .path()
+            .map_err(|e| format!("invalid tar entry path: {}", e))?
+            .to_string_lossy()
+            .to_string();
+        files.add(&path, entry)?;
+    }
+    Ok(())
+}
+
+struct ExtractedFiles {
+    files: BTreeMap<String, Vec<u8>>,
+    max_files: usize,
+    remaining_bytes: u64,
+}
+
+impl ExtractedFiles {
+    fn add<R: Read>(&mut self, path: &str, reader: R) -> Result<(), String> {
+        let normalized = normalize_path(path).ok_or_else(|| format!("invalid path {}", path))?;
+        if self.files.contains_key(&normalized) {
+            return Err(format!("duplicate path {}", normalized));
+        }
+        if self.files.len() >= self.max_files {
+            return Err(format!("at most {} files per archive", self.max_files));
+        }
+
+        let mut data = Vec::new();
+        reader
+            .take(self.remaining_bytes + 1)
+            .read_to_end(&mut data)
+            .map_err(|e| format!("error reading {}: {}", normalized, e))?;
+        if data.len() as u64 > self.remaining_bytes {
+            return Err("the extracted archive is too large".to_string());
+        }
+
+        self.remaining_bytes -= data.len() as u64;
+        self.files.insert(normalized, data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::server::archive::{ArchiveKind, detect_archive, extract_archive};
+    use std::io::{Cursor, Write};
+
+    fn tar_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    pub fn test_extract_archive() {
+        let tar = tar_archive(&[
+            ("./site/index.html", b"<h1>hi</h1>"),
+            ("site/a.css", b"a{}"),
+        ]);
+        assert_eq!(detect_archive("", &tar), Some(ArchiveKind::Tar));
+        let files = extract_archive(ArchiveKind::Tar, &tar, 10, 1024).unwrap();
+        assert_eq!(files["site/index.html"], b"<h1>hi</h1>");
+        assert_eq!(files.len(), 2);
+
+        assert!(extract_archive(ArchiveKind::Tar, &tar, 1, 1024).is_err());
+        assert!(extract_archive(ArchiveKind::Tar, &tar, 10, 12).is_err());
+
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("docs/readme.md", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"# load0").unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+        assert_eq!(
+            detect_archive("application/zip", &zip),
+            Some(ArchiveKind::Zip)
+        );
+        let files = extract_archive(ArchiveKind::Zip, &zip, 10, 1024).unwrap();
+        assert_eq!(files["docs/readme.md"], b"# load0");
+
+        assert_eq!(detect_archive("text/plain", b"hello"), None);
+    }
+}
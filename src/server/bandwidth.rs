@@ -0,0 +1,163 @@
+use crate::server::rate_limiter::XLoadAuthHeaderExtractor;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use bytes::Bytes;
+use futures::StreamExt;
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use tower_governor::key_extractor::KeyExtractor;
+
+// bytes are accounted in KiB cells so large rates still fit governor's u32 quotas
+const CELL_SIZE: u64 = 1024;
+// chunks are re-split so a single large frame is paced instead of sent at once
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+pub struct BandwidthLimiter {
+    limiter: DefaultKeyedRateLimiter<String>,
+    burst_cells: u32,
+}
+
+impl BandwidthLimiter {
+    // None when the byte rate is 0, i.e. unlimited
+    pub fn new(bytes_per_sec: u64, burst_secs: u32) -> Option<Self> {
+        let cells_per_sec = bytes_per_sec.div_ceil(CELL_SIZE).min(u32::MAX as u64) as u32;
+        let cells_per_sec = NonZeroU32::new(cells_per_sec)?;
+        let burst_cells = cells_per_sec
+            .get()
+            .saturating_mul(burst_secs.max(1))
+            .max((MAX_FRAME_SIZE as u64 / CELL_SIZE) as u32);
+        let quota = Quota::per_second(cells_per_sec).allow_burst(NonZeroU32::new(burst_cells)?);
+
+        Some(Self {
+            limiter: RateLimiter::keyed(quota),
+            burst_cells,
+        })
+    }
+
+    pub async fn wait_for(&self, key: &String, bytes: usize) {
+        let mut cells = (bytes as u64).div_ceil(CELL_SIZE);
+        while cells > 0 {
+            let batch = cells.min(self.burst_cells as u64) as u32;
+            if let Some(batch) = NonZeroU32::new(batch) {
+                // batch never exceeds the burst so capacity errors can't happen
+                let _ = self.limiter.until_key_n_ready(key, batch).await;
+            }
+            cells -= batch as u64;
+        }
+    }
+
+    pub fn retain_recent(&self) {
+        self.limiter.retain_recent();
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct BandwidthLimits {
+    pub ingress: Option<Arc<BandwidthLimiter>>,
+    pub egress: Option<Arc<BandwidthLimiter>>,
+}
+
+impl BandwidthLimits {
+    pub fn new(ingress_bytes_per_sec: u64, egress_bytes_per_sec: u64, burst_secs: u32) -> Self {
+        Self {
+            ingress: BandwidthLimiter::new(ingress_bytes_per_sec, burst_secs).map(Arc::new),
+            egress: BandwidthLimiter::new(egress_bytes_per_sec, burst_secs).map(Arc::new),
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.ingress.is_none() && self.egress.is_none()
+    }
+
+    pub fn retain_recent(&self) {
+        self.ingress.iter().for_each(|l| l.retain_recent());
+        self.egress.iter().for_each(|l| l.retain_recent());
+    }
+}
+
+fn throttle_body(body: Body, limiter: Arc<BandwidthLimiter>, key: String) -> Body {
+    let stream = body
+        .into_data_stream()
+        .flat_map(|chunk| {
+            let frames: Vec<Result<Bytes, axum::Error>> = match chunk {
+                Ok(bytes) if bytes.len() > MAX_FRAME_SIZE => (0..bytes.len())
+                    .step_by(MAX_FRAME_SIZE)
+                    .map(|start| Ok(bytes.slice(start..(start + MAX_FRAME_SIZE).min(bytes.len()))))
+                    .collect(),
+                chunk => vec![chunk],
+            };
+            futures::stream::iter(frames)
+        })
+        .then(move |chunk| {
+            let limiter = limiter.clone();
+            let key = key.clone();
+            async move {
+                if let Ok(bytes) = &chunk {
+                    limiter.wait_for(&key, bytes.len()).await;
+                }
+                chunk
+            }
+        });
+
+    Body::from_stream(stream)
+}
+
+// paces request and response bodies per XLoadAuthHeaderExtractor key
+pub async fn bandwidth_limit(
+    State(limits): State<BandwidthLimits>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let key = match XLoadAuthHeaderExtractor.extract(&req) {
+        Ok(key) => key,
+        Err(_) => return next.run(req).await,
+    };
+
+    let req = match &limits.ingress {
+        Some(limiter) => {
+            let (parts, body) = req.into_parts();
+            Request::from_parts(parts, throttle_body(body, limiter.clone(), key.clone()))
+        }
+        None => req,
+    };
+
+    let response = next.run(req).await;
+
+    match &limits.egress {
+        Some(limiter) => {
+            let (parts, body) = response.into_parts();
+            Response::from_parts(parts, throttle_body(body, limiter.clone(), key))
+        }
+        None => response,
+    }
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::server::bandwidth::BandwidthLimiter;
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    pub async fn test_bandwidth_limiter_paces_bytes() {
+        assert!(BandwidthLimiter::new(0, 10).is_none());
+
+        // 64KiB/s with a one second burst: the first 64KiB pass, the next 32KiB wait ~0.5s
+        let limiter = BandwidthLimiter::new(64 * 1024, 1).unwrap();
+        let key = "127.0.0.1".to_string();
+
+        let start = Instant::now();
+        limiter.wait_for(&key, 64 * 1024).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        limiter.wait_for(&key, 32 * 1024).await;
+        assert!(start.elapsed() >= Duration::from_millis(400));
+
+        // other keys have their own bucket
+        let start = Instant::now();
+        limiter.wait_for(&"10.0.0.1".to_string(), 64 * 1024).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::auth::provider::unix_now;
+use crate::server::webhooks::{EVENT_SETTLEMENT_FAILED, EVENT_UPLOADED};
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+use tokio::sync::broadcast;
+
+pub const EVENT_SETTLEMENT_STARTED: &str = "settlement_started";
+pub const EVENT_CHUNKS_PROPAGATED: &str = "chunks_propagated";
+pub const EVENT_FINALIZED: &str = "finalized";
+
+// subscribers lagging further behind than this miss the oldest events
+const EVENT_BUS_CAPACITY: usize = 1024;
+
+// events are published by the server and the settlement cron of this process,
+// subscribers of another replica don't see them
+static EVENT_BUS: LazyLock<broadcast::Sender<BundleEvent>> =
+    LazyLock::new(|| broadcast::channel(EVENT_BUS_CAPACITY).0);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundleEvent {
+    pub event: String,
+    pub optimistic_hash: String,
+    pub owner: String,
+    pub created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks_total: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks_propagated: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle_txid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BundleEvent {
+    pub fn new(event: &str, optimistic_hash: &str, owner: &str) -> Self {
+        Self {
+            event: event.to_string(),
+            optimistic_hash: optimistic_hash.to_string(),
+            owner: owner.to_string(),
+            created_at: unix_now(),
+            chunks_total: None,
+            chunks_propagated: None,
+            bundle_txid: None,
+            error: None,
+        }
+    }
+
+    pub fn uploaded(optimistic_hash: &str, owner: &str) -> Self {
+        Self::new(EVENT_UPLOADED, optimistic_hash, owner)
+    }
+
+    pub fn progress(
+        event: &str,
+        optimistic_hash: &str,
+        owner: &str,
+        chunks_propagated: u32,
+        chunks_total: u32,
+    ) -> Self {
+        Self {
+            chunks_total: Some(chunks_total),
+            chunks_propagated: Some(chunks_propagated),
+            ..Self::new(event, optimistic_hash, owner)
+        }
+    }
+
+    pub fn finalized(optimistic_hash: &str, owner: &str, bundle_txid: &str) -> Self {
+        Self {
+            bundle_txid: Some(bundle_txid.to_string()),
+            ..Self::new(EVENT_FINALIZED, optimistic_hash, owner)
+        }
+    }
+
+    pub fn failed(optimistic_hash: &str, owner: &str, error: &str) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..Self::new(EVENT_SETTLEMENT_FAILED, optimistic_hash, owner)
+        }
+    }
+}
+
+// an empty field matches everything
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventFilter {
+    pub owner: Option<String>,
+    pub optimistic_hash: Option<String>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &BundleEvent) -> bool {
+        self.owner
+            .as_ref()
+            .is_none_or(|owner| *owner == event.owner)
+            && self
+                .optimistic_hash
+                .as_ref()
+                .is_none_or(|hash| *hash == event.optimistic_hash)
+    }
+}
+
+pub fn publish(event: BundleEvent) {
+    // only fails when nobody is subscribed
+    let _ = EVENT_BUS.send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<BundleEvent> {
+    EVENT_BUS.subscribe()
+}
+
+// the next event matching `filter`, None once the bus is closed. Events
+// missed by a lagging subscriber are skipped
+pub async fn next_event(
+    receiver: &mut broadcast::Receiver<BundleEvent>,
+    filter: &EventFilter,
+) -> Option<BundleEvent> {
+    loop {
+        match receiver.recv().await {
+            Ok(event) if filter.matches(&event) => return Some(event),
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                println!("Event subscriber lagged, skipped {} events", skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::server::events::{
+        BundleEvent, EVENT_CHUNKS_PROPAGATED, EventFilter, next_event, publish, subscribe,
+    };
+
+    #[tokio::test]
+    pub async fn test_event_stream_filters() {
+        let mut receiver = subscribe();
+        let filter = EventFilter {
+            owner: Some("team".to_string()),
+            optimistic_hash: None,
+        };
+
+        publish(BundleEvent::uploaded("0x1", "other"));
+        publish(BundleEvent::progress(
+            EVENT_CHUNKS_PROPAGATED,
+            "0x2",
+            "team",
+            3,
+            3,
+        ));
+        publish(BundleEvent::finalized("0x2", "team", "0xtx"));
+
+        let event = next_event(&mut receiver, &filter).await.unwrap();
+        assert_eq!(event.optimistic_hash, "0x2");
+        assert_eq!(event.chunks_propagated, Some(3));
+        let event = next_event(&mut receiver, &filter).await.unwrap();
+        assert_eq!(event.bundle_txid.as_deref(), Some("0xtx"));
+
+        let json = serde_json::to_string(&BundleEvent::failed("0x3", "team", "boom")).unwrap();
+        assert!(json.contains(r#""event":"settlement_failed""#));
+        assert!(!json.contains("chunks_total"));
+
+        let by_hash = EventFilter {
+            owner: None,
+            optimistic_hash: Some("0x3".to_string()),
+        };
+        assert!(by_hash.matches(&BundleEvent::uploaded("0x3", "")));
+        assert!(!by_hash.matches(&BundleEvent::uploaded("0x4", "")));
+    }
+}
@@ -1,12 +1,68 @@
+use crate::auth::provider::{
+    ApiKeyIdentity, KNOWN_SCOPES, SCOPE_PRIORITY, SCOPE_READ, SCOPE_UPLOAD, StorageQuota,
+    parse_scopes, unix_now,
+};
+use crate::auth::{identify, is_bundle_reader, is_internal_key, is_reserved_owner, is_valid_owner};
+use crate::orchestrator::cost::{current_gas_price, estimate_settlement};
+use crate::orchestrator::cron;
 use crate::orchestrator::db::{
-    get_bundle_by_optimistic_hash, get_bundle_by_txid, get_bundle_stats, insert_bundle,
+    Bundle, BundleEncryption, KeyUsage, NewBundle, OwnerSettlementCosts, USAGE_EVENT_UPLOAD,
+    Webhook, deactivate_webhook, get_bundle_by_optimistic_hash, get_bundle_by_txid,
+    get_bundle_chunks, get_bundle_tags, get_key_usage, get_owner_settlement_costs,
+    get_settlement_cost, get_webhook, get_webhook_deliveries, get_webhooks_by_owner,
+    insert_api_key, insert_bundle, insert_bundle_tags, insert_usage_entry, insert_webhook,
+    revoke_api_key, search_bundles,
+};
+use crate::orchestrator::progress::SettlementProgress;
+use crate::orchestrator::search::{
+    BundleCursor, BundleQuery, BundleSort, is_valid_content_type_prefix,
+};
+use crate::orchestrator::stats::load_stats_snapshot;
+use crate::orchestrator::storage_class::{
+    STORAGE_CLASS_PERMANENT_NOW, StorageClass, is_expired, parse_storage_class,
+};
+use crate::server::archive::{detect_archive, extract_archive};
+use crate::server::events::{self, BundleEvent, EventFilter};
+use crate::server::manifest::{DEFAULT_INDEX, MANIFEST_CONTENT_TYPE, PathManifest, normalize_path};
+use crate::server::metadata::ObjectMetadata;
+use crate::server::presign::{
+    DEFAULT_SIGNED_URL_TTL_SECS, MAX_SIGNED_URL_TTL_SECS, signed_download_path, verify_download,
+};
+use crate::server::quota::{
+    QuotaExceeded, check_quota, day_start, remaining_bytes, seconds_until_next_day,
+};
+use crate::server::rate_limiter::{LOAD_HEADER_NAME, resolve_client_ip};
+use crate::server::types::{
+    AppState, CreateApiKeyRequest, CreateApiKeyResponse, CreateOriginTokenRequest,
+    CreateOriginTokenResponse, CreateWebhookRequest, DirectoryUploadResponse, DownloadQuery,
+    EstimateQuery, EventsQuery, ListBundlesQuery, MultipartUploadReport, SignBundleRequest,
+    SignBundleResponse, UploadQuery, UploadResponse, UsageResponse, WebhookDeliveriesQuery,
+    WebhookDeliveriesResponse, WebhookSecretResponse, WebhooksResponse,
+};
+use crate::server::webhooks::{self, EVENT_UPLOADED, WEBHOOK_EVENTS, WebhookBundle};
+use crate::server::whitelist::sign_origin_token;
+use crate::r#static::{
+    DOWNLOAD_SIGNING_SECRET, MAX_UPLOAD_BYTES, PUBLIC_URL, TRUSTED_PROXIES, UPLOAD_AUTH_REQUIRED,
+    WEBHOOK_ALLOW_PRIVATE_URLS, WHITELIST_TOKEN_SECRET,
+};
+use crate::utils::compression::{
+    COMPRESSION_NONE, accepts_encoding, compress, decompress,
+    is_supported as is_supported_compression,
 };
-use crate::server::types::{AppState, UploadQuery, UploadResponse};
-use crate::utils::constants::ZERO_ADDRESS;
-use crate::utils::hash::generate_pseudorandom_keccak_hash;
+use crate::utils::constants::{
+    DEFAULT_LIST_LIMIT, MAX_DIRECTORY_FILES, MAX_LIST_LIMIT, MAX_MULTIPART_FILES, ZERO_ADDRESS,
+};
+use crate::utils::encryption::{
+    ENCRYPTION_ALGORITHM_HEADER, ENCRYPTION_CLIENT, ENCRYPTION_HEADER, ENCRYPTION_KEY_ID_HEADER,
+    ENCRYPTION_NONCE_HEADER, ENCRYPTION_NONE, ENCRYPTION_SERVER, SERVER_ALGORITHM,
+    WRAPPED_KEY_HEADER, is_valid_envelope_value,
+};
+use crate::utils::hash::{generate_api_key, generate_pseudorandom_keccak_hash, keccak_hex};
 use axum::body::Body;
-use axum::extract::Path;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, Path};
 use axum::response::IntoResponse;
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::{
     Json,
     extract::{Query, State},
@@ -15,7 +71,9 @@ use axum::{
 use futures::StreamExt;
 use futures::stream::{self};
 use serde_json::{Value, json};
+use std::collections::BTreeMap;
 use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use tokio::io::AsyncReadExt;
 use tokio_util::io::StreamReader;
@@ -25,6 +83,281 @@ pub async fn server_status_handler() -> Json<Value> {
     Json(json!({"status": "running"}))
 }
 
+// request level settings shared by every object of an upload
+struct UploadContext {
+    identity: Option<ApiKeyIdentity>,
+    owner: String,
+    is_private: bool,
+    compression: String,
+    encryption: BundleEncryption,
+    // usage is only loaded for keys that actually have a quota
+    quota_usage: Option<(StorageQuota, KeyUsage)>,
+    callback_url: Option<String>,
+    storage_class: StorageClass,
+}
+
+impl UploadContext {
+    fn check_quota(&self, incoming_bytes: u64) -> Result<(), (QuotaExceeded, StorageQuota)> {
+        match &self.quota_usage {
+            Some((quota, usage)) => {
+                check_quota(quota, usage, incoming_bytes).map_err(|exceeded| (exceeded, *quota))
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn remaining_bytes(&self) -> Option<u64> {
+        self.quota_usage
+            .as_ref()
+            .and_then(|(quota, usage)| remaining_bytes(quota, usage))
+    }
+
+    // whether all of `sizes` fit, each as one more object
+    fn check_batch(&self, sizes: &[u64]) -> Result<(), (QuotaExceeded, StorageQuota)> {
+        let Some((quota, usage)) = &self.quota_usage else {
+            return Ok(());
+        };
+        let mut usage = usage.clone();
+        for size in sizes {
+            check_quota(quota, &usage, *size).map_err(|exceeded| (exceeded, *quota))?;
+            usage.objects_count += 1;
+            usage.total_bytes += size;
+            usage.bytes_today += size;
+        }
+        Ok(())
+    }
+
+    // the internal key and paid keys settle in the priority lane
+    fn priority(&self) -> u8 {
+        self.identity
+            .as_ref()
+            .is_some_and(|identity| identity.is_internal || identity.has_scope(SCOPE_PRIORITY))
+            as u8
+    }
+
+    // later objects of a multipart upload count the earlier ones
+    fn record_stored(&mut self, bytes: u64) {
+        if let Some((_, usage)) = &mut self.quota_usage {
+            usage.objects_count += 1;
+            usage.total_bytes += bytes;
+            usage.bytes_today += bytes;
+        }
+    }
+}
+
+async fn upload_context(
+    state: &AppState,
+    params: &UploadQuery,
+    headers: &axum::http::HeaderMap,
+) -> Result<UploadContext, axum::response::Response> {
+    let identity = match identify(state.auth.as_ref(), headers).await {
+        Ok(Some(identity)) if identity.has_scope(SCOPE_UPLOAD) => Some(identity),
+        Ok(Some(_)) => {
+            return Err(upload_error_response(
+                StatusCode::FORBIDDEN,
+                "API key is missing the upload scope".to_string(),
+            ));
+        }
+        Ok(None) if !*UPLOAD_AUTH_REQUIRED => None,
+        Ok(None) => {
+            return Err(upload_error_response(
+                StatusCode::UNAUTHORIZED,
+                format!("A valid {} API key is required to upload", LOAD_HEADER_NAME),
+            ));
+        }
+        Err(e) => {
+            println!("Error verifying upload API key: {:?}", e);
+            return Err(upload_error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Unable to verify API key, try again later".to_string(),
+            ));
+        }
+    };
+    let owner = identity
+        .as_ref()
+        .map(|identity| identity.owner.clone())
+        .unwrap_or_default();
+
+    // private objects need an owner, only they (or the internal key) can sign
+    // download URLs for them
+    let is_private = params.private.unwrap_or(false);
+    if is_private && state.private_bucket_name.is_empty() {
+        return Err(upload_error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Private uploads are not enabled".to_string(),
+        ));
+    }
+    if is_private && identity.is_none() {
+        return Err(upload_error_response(
+            StatusCode::UNAUTHORIZED,
+            format!(
+                "A valid {} API key is required for private uploads",
+                LOAD_HEADER_NAME
+            ),
+        ));
+    }
+
+    let compression = params
+        .compression
+        .clone()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if !is_supported_compression(&compression) {
+        return Err(upload_error_response(
+            StatusCode::BAD_REQUEST,
+            "compression must be `zstd` or `gzip`".to_string(),
+        ));
+    }
+
+    // encrypted objects are stored, and settled, as opaque ciphertext
+    let encryption = match params.encryption.as_deref().unwrap_or(ENCRYPTION_NONE) {
+        ENCRYPTION_NONE => BundleEncryption::default(),
+        ENCRYPTION_SERVER if !state.key_ring.is_enabled() => {
+            return Err(upload_error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Server-side encryption is not enabled".to_string(),
+            ));
+        }
+        ENCRYPTION_SERVER if identity.is_none() => {
+            return Err(upload_error_response(
+                StatusCode::UNAUTHORIZED,
+                format!(
+                    "A valid {} API key is required for server-side encryption",
+                    LOAD_HEADER_NAME
+                ),
+            ));
+        }
+        ENCRYPTION_SERVER => BundleEncryption {
+            mode: ENCRYPTION_SERVER.to_string(),
+            algorithm: SERVER_ALGORITHM.to_string(),
+            ..Default::default()
+        },
+        ENCRYPTION_CLIENT if !compression.is_empty() => {
+            return Err(upload_error_response(
+                StatusCode::BAD_REQUEST,
+                "Client-side encrypted envelopes can't be compressed".to_string(),
+            ));
+        }
+        ENCRYPTION_CLIENT => match client_envelope(headers) {
+            Some(envelope) => envelope,
+            None => {
+                return Err(upload_error_response(
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "Client-side encryption requires a valid {} header",
+                        ENCRYPTION_ALGORITHM_HEADER
+                    ),
+                ));
+            }
+        },
+        _ => {
+            return Err(upload_error_response(
+                StatusCode::BAD_REQUEST,
+                "encryption must be `client` or `server`".to_string(),
+            ));
+        }
+    };
+
+    let storage_class =
+        match parse_storage_class(params.storage_class.as_deref(), params.ttl, unix_now()) {
+            Ok(storage_class) => storage_class,
+            Err(e) => return Err(upload_error_response(StatusCode::BAD_REQUEST, e)),
+        };
+    // private objects are never settled
+    if is_private && storage_class.class == STORAGE_CLASS_PERMANENT_NOW {
+        return Err(upload_error_response(
+            StatusCode::BAD_REQUEST,
+            "Private uploads can't use the permanent-now storage_class".to_string(),
+        ));
+    }
+
+    // callbacks are signed with the secret of the owner
+    let callback_url = match params.callback_url.as_deref() {
+        None | Some("") => None,
+        Some(_) if !webhooks::is_enabled() => {
+            return Err(upload_error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Webhooks are not enabled".to_string(),
+            ));
+        }
+        Some(_) if identity.is_none() => {
+            return Err(upload_error_response(
+                StatusCode::UNAUTHORIZED,
+                format!(
+                    "A valid {} API key is required for a callback_url",
+                    LOAD_HEADER_NAME
+                ),
+            ));
+        }
+        Some(url) => match webhooks::validate_url(url, *WEBHOOK_ALLOW_PRIVATE_URLS) {
+            Ok(()) => Some(url.to_string()),
+            Err(e) => {
+                return Err(upload_error_response(
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid callback_url: {}", e),
+                ));
+            }
+        },
+    };
+
+    // concurrent uploads of the same key can overshoot the quota by at most
+    // one request each
+    let quota_usage = match &identity {
+        Some(identity) if !identity.quota.is_unlimited() => {
+            match get_key_usage(&identity.key_id, day_start(unix_now())).await {
+                Ok(usage) => Some((identity.quota, usage)),
+                Err(e) => {
+                    println!("Error loading usage for key {}: {:?}", identity.key_id, e);
+                    return Err(upload_error_response(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "Unable to load usage, try again later".to_string(),
+                    ));
+                }
+            }
+        }
+        _ => None,
+    };
+
+    Ok(UploadContext {
+        identity,
+        owner,
+        is_private,
+        compression,
+        encryption,
+        quota_usage,
+        callback_url,
+        storage_class,
+    })
+}
+
+// registers the callback_url of the upload, then fires `uploaded` to it, to
+// the webhooks of the key and to the event stream
+async fn notify_uploaded(context: &UploadContext, bundle: WebhookBundle) {
+    if let (Some(identity), Some(url)) = (&context.identity, &context.callback_url)
+        && let Err(e) = insert_webhook(
+            &identity.owner,
+            &identity.key_id,
+            url,
+            &WEBHOOK_EVENTS.join(","),
+            &bundle.optimistic_hash,
+            unix_now(),
+        )
+        .await
+    {
+        println!("Error registering callback_url: {:?}", e);
+    }
+
+    let key_id = context
+        .identity
+        .as_ref()
+        .map(|identity| identity.key_id.clone());
+    events::publish(BundleEvent::uploaded(
+        &bundle.optimistic_hash,
+        &bundle.owner,
+    ));
+    tokio::spawn(webhooks::notify(EVENT_UPLOADED, bundle, key_id, None));
+}
+
 // uploads handler with improved chunked reading and detailed logging
 pub async fn upload_binary_handler(
     State(state): State<Arc<AppState>>,
@@ -34,7 +367,53 @@ pub async fn upload_binary_handler(
 ) -> impl IntoResponse {
     let start_time = std::time::Instant::now();
     println!("UPLOAD BINARY HANDLER CALLED");
-    let filename_hash = generate_pseudorandom_keccak_hash();
+
+    let context = match upload_context(&state, &params, &headers).await {
+        Ok(context) => context,
+        Err(response) => return response,
+    };
+
+    // a directory settles file by file before its manifest, permanent-now
+    // only waits on a single object
+    let is_permanent_now = context.storage_class.class == STORAGE_CLASS_PERMANENT_NOW;
+    if is_permanent_now
+        && (params.directory.unwrap_or(false) || multipart_boundary(&headers).is_some())
+    {
+        return upload_error_response(
+            StatusCode::BAD_REQUEST,
+            "The permanent-now storage_class only supports single object uploads".to_string(),
+        );
+    }
+
+    if params.directory.unwrap_or(false) {
+        return upload_directory(&state, context, &params, &headers, body, start_time).await;
+    }
+    if let Some(boundary) = multipart_boundary(&headers) {
+        return upload_multipart(
+            &state, context, &params, &headers, boundary, body, start_time,
+        )
+        .await;
+    }
+
+    let metadata = match ObjectMetadata::from_request(
+        params.filename.as_deref(),
+        params.tags.as_deref(),
+        &headers,
+    ) {
+        Ok(metadata) => metadata,
+        Err(e) => return upload_error_response(StatusCode::BAD_REQUEST, e),
+    };
+
+    let declared_size = headers
+        .get(axum::http::header::CONTENT_LENGTH)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<u64>().ok())
+        .unwrap_or(0);
+
+    if let Err((exceeded, quota)) = context.check_quota(declared_size) {
+        return quota_exceeded_response(exceeded, &quota);
+    }
+
     let content_type = params
         .content_type
         .or_else(|| {
@@ -52,6 +431,81 @@ pub async fn upload_binary_handler(
     //                     content_type.starts_with("application/octet-stream") ||
     //                     content_type.starts_with("image/");
 
+    let full_body = match read_upload_body(body, &context).await {
+        Ok(full_body) => full_body,
+        Err(response) => return response,
+    };
+
+    let (status, response) = store_object(
+        &state,
+        &context,
+        &content_type,
+        &metadata,
+        full_body,
+        start_time,
+    )
+    .await;
+    if is_permanent_now
+        && response.success
+        && let Some(optimistic_hash) = response.optimistic_hash.clone()
+    {
+        return settle_upload(&optimistic_hash, response).await;
+    }
+    (status, Json(response)).into_response()
+}
+
+// settles a permanent-now upload before responding, with its bundle_txid. If
+// that fails the object stays stored and the settlement queue retries it
+async fn settle_upload(
+    optimistic_hash: &str,
+    response: UploadResponse,
+) -> axum::response::Response {
+    let settle_start = std::time::Instant::now();
+    let settled = match get_bundle_by_optimistic_hash(optimistic_hash).await {
+        Ok(bundle) => cron::settle(&bundle).await,
+        Err(e) => Err(e),
+    };
+
+    match settled {
+        Ok(Some(bundle_txid)) => {
+            println!(
+                "Permanent-now upload settled in {:?}",
+                settle_start.elapsed()
+            );
+            let mut response = serde_json::to_value(&response).unwrap_or_default();
+            response["bundle_txid"] = json!(bundle_txid);
+            (StatusCode::OK, Json(response)).into_response()
+        }
+        // empty objects are never settled, and a bundle the queue is
+        // settling already is left to it
+        Ok(None) => (StatusCode::OK, Json(response)).into_response(),
+        Err(e) => {
+            println!("Error settling permanent-now upload: {:?}", e);
+            upload_settlement_failed(optimistic_hash, e.to_string())
+        }
+    }
+}
+
+fn upload_settlement_failed(optimistic_hash: &str, error: String) -> axum::response::Response {
+    (
+        StatusCode::BAD_GATEWAY,
+        Json(UploadResponse {
+            success: false,
+            message: format!(
+                "Upload stored but settlement failed, it will be retried: {}",
+                error
+            ),
+            optimistic_hash: Some(optimistic_hash.to_string()),
+        }),
+    )
+        .into_response()
+}
+
+// buffers a raw upload body, stopping early once it runs over the quota
+async fn read_upload_body(
+    body: axum::body::Body,
+    context: &UploadContext,
+) -> Result<Vec<u8>, axum::response::Response> {
     let stream = body.into_data_stream();
     let mut full_body = Vec::new();
     let stream =
@@ -59,6 +513,7 @@ pub async fn upload_binary_handler(
     let mut stream_reader = StreamReader::new(stream);
 
     // read the data in chunks
+    let byte_limit = context.remaining_bytes();
     let read_start = std::time::Instant::now();
     let mut buffer = vec![0u8; 64 * 1024]; // 64KB buffer
     let mut total_bytes = 0;
@@ -70,6 +525,13 @@ pub async fn upload_binary_handler(
                 full_body.extend_from_slice(&buffer[..n]);
                 total_bytes += n;
 
+                // stop buffering as soon as a chunked body runs over the quota
+                if byte_limit.is_some_and(|limit| total_bytes as u64 > limit)
+                    && let Err((exceeded, quota)) = context.check_quota(total_bytes as u64)
+                {
+                    return Err(quota_exceeded_response(exceeded, &quota));
+                }
+
                 if total_bytes % (5 * 1024 * 1024) < n {
                     // Log every ~5MB
                     println!(
@@ -81,7 +543,7 @@ pub async fn upload_binary_handler(
             }
             Err(e) => {
                 println!("Error reading request body: {}", e);
-                return (
+                return Err((
                     StatusCode::INTERNAL_SERVER_ERROR,
                     Json(UploadResponse {
                         success: false,
@@ -89,7 +551,7 @@ pub async fn upload_binary_handler(
                         optimistic_hash: None,
                     }),
                 )
-                    .into_response();
+                    .into_response());
             }
         }
     }
@@ -106,19 +568,98 @@ pub async fn upload_binary_handler(
         println!("Large file upload: {} MB", total_bytes / (1024 * 1024));
     }
 
+    Ok(full_body)
+}
+
+// compresses, encrypts and stores one object, then records its bundle
+async fn store_object(
+    state: &AppState,
+    context: &UploadContext,
+    content_type: &str,
+    metadata: &ObjectMetadata,
+    full_body: Vec<u8>,
+    start_time: std::time::Instant,
+) -> (StatusCode, UploadResponse) {
+    let failure = |status: StatusCode, message: String| {
+        (
+            status,
+            UploadResponse {
+                success: false,
+                message,
+                optimistic_hash: None,
+            },
+        )
+    };
+
+    let filename_hash = generate_pseudorandom_keccak_hash();
+    let bucket_name = if context.is_private {
+        &state.private_bucket_name
+    } else {
+        &state.bucket_name
+    };
+
+    // compression runs before encryption, ciphertext doesn't compress
+    let original_size = full_body.len() as u64;
+    let compression = context.compression.clone();
+    let (compression, full_body) = if compression.is_empty() {
+        (compression, full_body)
+    } else {
+        match compress(&compression, &full_body) {
+            // incompressible payloads are stored as uploaded
+            Ok(compressed) if compressed.len() < full_body.len() => (compression, compressed),
+            Ok(_) => (COMPRESSION_NONE.to_string(), full_body),
+            Err(e) => {
+                println!("Error compressing object: {:?}", e);
+                return failure(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Compression failed: {}", e),
+                );
+            }
+        }
+    };
+    let compressed_size = full_body.len() as u64;
+
+    let mut encryption = context.encryption.clone();
+    let full_body = if encryption.mode == ENCRYPTION_SERVER {
+        match state.key_ring.encrypt(&full_body) {
+            Ok(encrypted) => {
+                encryption.key_id = encrypted.key_id;
+                encryption.wrapped_key = encrypted.wrapped_key;
+                encryption.nonce = encrypted.nonce;
+                encrypted.ciphertext
+            }
+            Err(e) => {
+                println!("Error encrypting object: {:?}", e);
+                return failure(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Encryption failed: {}", e),
+                );
+            }
+        }
+    } else {
+        full_body
+    };
+    // the stored object (and the settled one) doesn't reveal the plaintext type
+    let storage_content_type = if encryption.mode.is_empty() {
+        content_type
+    } else {
+        "application/octet-stream"
+    };
+
     let rest_url = state.supabase_url.replace("/v1/s3", "/v1/object");
-    let url = format!("{}/{}/{}", rest_url, state.bucket_name, filename_hash);
+    let url = format!("{}/{}/{}", rest_url, bucket_name, filename_hash);
 
     println!("Uploading to URL: {}", url);
 
     let upload_start = std::time::Instant::now();
+    let data_size = full_body.len() as u64;
     match state
         .http_client
         .post(&url)
-        .header("Content-Type", &content_type)
+        .header("Content-Type", storage_content_type)
         .header("Authorization", format!("Bearer {}", state.api_key))
         .header("apikey", &state.api_key)
-        .body(full_body.clone())
+        .body(full_body)
         .send()
         .await
     {
@@ -127,48 +668,83 @@ pub async fn upload_binary_handler(
 
             if response.status().is_success() {
                 let db_start = std::time::Instant::now();
-                match insert_bundle(
-                    &filename_hash,
-                    ZERO_ADDRESS,
-                    full_body.len() as u32,
-                    false,
-                    &content_type,
-                )
+                match insert_bundle(&NewBundle {
+                    optimistic_hash: filename_hash.clone(),
+                    bundle_txid: ZERO_ADDRESS.to_string(),
+                    data_size,
+                    is_settled: false,
+                    content_type: content_type.to_string(),
+                    owner: context.owner.clone(),
+                    is_private: context.is_private,
+                    encryption,
+                    compression,
+                    original_size,
+                    compressed_size,
+                    filename: metadata.filename.clone(),
+                    priority: context.priority(),
+                    storage_class: context.storage_class.class.to_string(),
+                    expires_at: context.storage_class.expires_at,
+                })
                 .await
                 {
                     Ok(_) => {
+                        // only bundles that have a record count against the quota
+                        if let Some(identity) = &context.identity
+                            && let Err(e) = insert_usage_entry(
+                                &identity.key_id,
+                                &identity.owner,
+                                USAGE_EVENT_UPLOAD,
+                                &filename_hash,
+                                original_size,
+                                unix_now(),
+                            )
+                            .await
+                        {
+                            println!("Error recording upload usage: {:?}", e);
+                        }
+
+                        if let Err(e) =
+                            insert_bundle_tags(&filename_hash, &metadata.to_rows()).await
+                        {
+                            println!("Error inserting bundle tags: {:?}", e);
+                        }
+
                         println!("Database record created in {:?}", db_start.elapsed());
                         println!("Total upload handler time: {:?}", start_time.elapsed());
 
+                        notify_uploaded(
+                            context,
+                            WebhookBundle {
+                                optimistic_hash: filename_hash.clone(),
+                                bundle_txid: ZERO_ADDRESS.to_string(),
+                                owner: context.owner.clone(),
+                                content_type: content_type.to_string(),
+                                data_size,
+                                is_settled: false,
+                            },
+                        )
+                        .await;
+
                         (
                             StatusCode::OK,
-                            Json(UploadResponse {
+                            UploadResponse {
                                 success: true,
                                 message: format!(
                                     "Upload successful. Size: {} bytes, Time: {:?}",
-                                    full_body.len(),
+                                    data_size,
                                     start_time.elapsed()
                                 ),
                                 optimistic_hash: Some(filename_hash),
-                            }),
+                            },
                         )
-                            .into_response()
                     }
                     Err(e) => {
                         println!("Error inserting bundle record: {:?}", e);
 
-                        (
-                            StatusCode::OK,
-                            Json(UploadResponse {
-                                success: true,
-                                message: format!(
-                                    "Upload successful but failed to create database record: {}",
-                                    e
-                                ),
-                                optimistic_hash: Some(filename_hash),
-                            }),
+                        failure(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            format!("Upload failed to create database record: {}", e),
                         )
-                            .into_response()
                     }
                 }
             } else {
@@ -176,158 +752,1815 @@ pub async fn upload_binary_handler(
                 let error_text = response.text().await.unwrap_or_default();
                 println!("Error: {} - {}", status, error_text);
 
-                (
+                failure(
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(UploadResponse {
-                        success: false,
-                        message: format!("Upload failed: {} - {}", status, error_text),
-                        optimistic_hash: None,
-                    }),
+                    format!("Upload failed: {} - {}", status, error_text),
                 )
-                    .into_response()
             }
         }
         Err(err) => {
             println!("Request error: {:?}", err);
 
-            (
+            failure(
                 StatusCode::INTERNAL_SERVER_ERROR,
-                Json(UploadResponse {
-                    success: false,
-                    message: format!("Upload failed: {}", err),
-                    optimistic_hash: None,
-                }),
+                format!("Upload failed: {}", err),
             )
-                .into_response()
         }
     }
 }
 
-// server handler to stream objects
-pub async fn download_object_handler(
-    State(state): State<Arc<AppState>>,
-    Path(filename): Path<String>,
-) -> impl IntoResponse {
-    let start_time = std::time::Instant::now();
+fn multipart_boundary(headers: &axum::http::HeaderMap) -> Option<String> {
+    let content_type = headers
+        .get(axum::http::header::CONTENT_TYPE)?
+        .to_str()
+        .ok()?;
+    if !content_type
+        .to_ascii_lowercase()
+        .starts_with("multipart/form-data")
+    {
+        return None;
+    }
+    multer::parse_boundary(content_type).ok()
+}
 
-    let object_metadata = match get_bundle_by_optimistic_hash(&filename).await {
-        Ok(metadata) => {
-            println!("REQUESTED BUNDLE: {:?}", metadata);
-            metadata
-        }
-        Err(e) => {
-            println!("Error getting bundle metadata: {}", e);
-            return (
-                StatusCode::NOT_FOUND,
-                format!("Bundle not found: {}. Error: {}", filename, e),
-            )
-                .into_response();
-        }
-    };
+// every file part of a multipart/form-data body is stored as its own object,
+// with the part filename and content type. ?tags= and x-load-meta-* apply to
+// all of them
+async fn upload_multipart(
+    state: &AppState,
+    mut context: UploadContext,
+    params: &UploadQuery,
+    headers: &axum::http::HeaderMap,
+    boundary: String,
+    body: axum::body::Body,
+    start_time: std::time::Instant,
+) -> axum::response::Response {
+    // the envelope headers describe a single object
+    if context.encryption.mode == ENCRYPTION_CLIENT {
+        return upload_error_response(
+            StatusCode::BAD_REQUEST,
+            "Client-side encrypted envelopes can't be uploaded as multipart".to_string(),
+        );
+    }
 
-    let content_type = object_metadata.content_type;
-    println!("RENDERING MIME TYPE: {:?}", content_type);
+    let mut multipart = multer::Multipart::new(body.into_data_stream(), boundary);
+    let mut results: Vec<UploadResponse> = Vec::new();
 
-    let direct_url = format!(
-        "{}/object/public/{}/{}",
-        state.supabase_url.replace("/v1/s3", "/v1"),
-        state.bucket_name,
-        filename
-    );
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                println!("Error reading multipart body: {}", e);
+                results.push(UploadResponse {
+                    success: false,
+                    message: format!("Error reading multipart body: {}", e),
+                    optimistic_hash: None,
+                });
+                break;
+            }
+        };
 
-    let file_response = match state
-        .http_client
-        .get(&direct_url)
-        .header("apikey", &state.api_key)
-        .header("Authorization", format!("Bearer {}", state.api_key))
-        .send()
-        .await
-    {
-        Ok(response) => response,
-        Err(e) => {
-            println!("Error requesting file: {}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to request file: {}", e),
-            )
-                .into_response();
+        // plain form fields are not objects
+        let Some(filename) = field.file_name().map(String::from) else {
+            continue;
+        };
+        if results.len() >= MAX_MULTIPART_FILES {
+            results.push(UploadResponse {
+                success: false,
+                message: format!("At most {} files per upload", MAX_MULTIPART_FILES),
+                optimistic_hash: None,
+            });
+            break;
         }
-    };
 
-    if !file_response.status().is_success() {
-        let status = file_response.status();
-        let error_text = file_response.text().await.unwrap_or_default();
-        println!("Error accessing file: {} - {}", status, error_text);
+        let content_type = field
+            .content_type()
+            .map(|mime| mime.to_string())
+            .or_else(|| params.content_type.clone())
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        let metadata =
+            match ObjectMetadata::from_request(Some(&filename), params.tags.as_deref(), headers) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    results.push(UploadResponse {
+                        success: false,
+                        message: format!("{}: {}", filename, e),
+                        optimistic_hash: None,
+                    });
+                    continue;
+                }
+            };
 
-        return (
-            StatusCode::NOT_FOUND,
-            format!("File not found: {}. Error: {}", filename, error_text),
-        )
-            .into_response();
-    }
+        if let Err(exceeded) = context.check_quota(0) {
+            return multipart_response(results, Some(exceeded));
+        }
+        let byte_limit = context.remaining_bytes();
 
-    let bytes_start = std::time::Instant::now();
-    let bytes = match file_response.bytes().await {
-        Ok(b) => {
-            println!(
-                "Downloaded {} bytes in {:?}",
-                b.len(),
-                bytes_start.elapsed()
-            );
-            b
+        let mut data = Vec::new();
+        let read_error = loop {
+            match field.chunk().await {
+                Ok(Some(chunk)) => {
+                    data.extend_from_slice(&chunk);
+                    if byte_limit.is_some_and(|limit| data.len() as u64 > limit)
+                        && let Err(exceeded) = context.check_quota(data.len() as u64)
+                    {
+                        return multipart_response(results, Some(exceeded));
+                    }
+                }
+                Ok(None) => break None,
+                Err(e) => break Some(e),
+            }
+        };
+        if let Some(e) = read_error {
+            println!("Error reading multipart field {}: {}", filename, e);
+            results.push(UploadResponse {
+                success: false,
+                message: format!("{}: Error reading request body: {}", filename, e),
+                optimistic_hash: None,
+            });
+            break;
         }
-        Err(e) => {
-            println!("Error reading file bytes: {}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to read file content: {}", e),
-            )
-                .into_response();
+
+        let original_size = data.len() as u64;
+        let (_, response) =
+            store_object(state, &context, &content_type, &metadata, data, start_time).await;
+        if response.success {
+            context.record_stored(original_size);
         }
-    };
+        results.push(UploadResponse {
+            message: format!("{}: {}", filename, response.message),
+            ..response
+        });
+    }
 
-    let stream = stream::once(async move { Ok::<_, Infallible>(bytes) });
-    let body = Body::from_stream(stream);
+    if results.is_empty() {
+        return upload_error_response(
+            StatusCode::BAD_REQUEST,
+            "The multipart body has no file parts".to_string(),
+        );
+    }
+    multipart_response(results, None)
+}
 
-    println!(
-        "Download handler setup completed in {:?}",
-        start_time.elapsed()
-    );
+// 200 with every UploadResponse when all files were stored, otherwise a 207
+// report. A quota error stops the upload, it is the whole response when
+// nothing was stored yet
+fn multipart_response(
+    mut results: Vec<UploadResponse>,
+    stopped_by: Option<(QuotaExceeded, StorageQuota)>,
+) -> axum::response::Response {
+    if let Some((exceeded, quota)) = stopped_by {
+        if results.iter().all(|result| !result.success) {
+            return quota_exceeded_response(exceeded, &quota);
+        }
+        results.push(UploadResponse {
+            success: false,
+            message: exceeded.message(&quota),
+            optimistic_hash: None,
+        });
+    }
 
-    let is_video = content_type.starts_with("video/");
+    let uploaded = results.iter().filter(|result| result.success).count();
+    if uploaded == results.len() {
+        return (StatusCode::OK, Json(results)).into_response();
+    }
 
-    if is_video {
-        axum::response::Response::builder()
-            .status(StatusCode::OK)
-            .header("content-type", content_type)
-            .header("accept-ranges", "bytes")
-            .header("cache-control", "public, max-age=31536000")
-            .body(body)
-            .unwrap()
-            .into_response()
+    (
+        StatusCode::MULTI_STATUS,
+        Json(MultipartUploadReport {
+            success: false,
+            uploaded,
+            failed: results.len() - uploaded,
+            results,
+        }),
+    )
+        .into_response()
+}
+
+// stores every file of a tar/zip archive (or of a multipart body whose part
+// filenames are paths) as its own bundle, then a PathManifest of them
+async fn upload_directory(
+    state: &AppState,
+    mut context: UploadContext,
+    params: &UploadQuery,
+    headers: &axum::http::HeaderMap,
+    body: axum::body::Body,
+    start_time: std::time::Instant,
+) -> axum::response::Response {
+    // the manifest and its files are served by path through /resolve
+    if context.is_private || !context.encryption.mode.is_empty() {
+        return upload_error_response(
+            StatusCode::BAD_REQUEST,
+            "Directory uploads can't be private or encrypted".to_string(),
+        );
+    }
+
+    let files = if let Some(boundary) = multipart_boundary(headers) {
+        match read_multipart_files(body, boundary, &context).await {
+            Ok(files) => Ok(files),
+            Err(response) => return response,
+        }
     } else {
-        axum::response::Response::builder()
-            .status(StatusCode::OK)
-            .header("content-type", content_type)
-            .header("transfer-encoding", "chunked")
-            .header("cache-control", "public, max-age=3600") // 1 hour cache for non-video content
-            .body(body)
-            .unwrap()
-            .into_response()
+        let full_body = match read_upload_body(body, &context).await {
+            Ok(full_body) => full_body,
+            Err(response) => return response,
+        };
+        let content_type = headers
+            .get(axum::http::header::CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("");
+        match detect_archive(content_type, &full_body) {
+            Some(kind) => extract_archive(kind, &full_body, MAX_DIRECTORY_FILES, *MAX_UPLOAD_BYTES),
+            None => Err("the body must be a tar, tar.gz or zip archive".to_string()),
+        }
+    };
+    let files = match files {
+        Ok(files) if files.is_empty() => {
+            return upload_error_response(
+                StatusCode::BAD_REQUEST,
+                "The directory has no files".to_string(),
+            );
+        }
+        Ok(files) => files,
+        Err(e) => return upload_error_response(StatusCode::BAD_REQUEST, e),
+    };
+
+    // the whole directory, and its manifest, must fit in the quota
+    let mut sizes: Vec<u64> = files.values().map(|data| data.len() as u64).collect();
+    sizes.push(0);
+    if let Err((exceeded, quota)) = context.check_batch(&sizes) {
+        return quota_exceeded_response(exceeded, &quota);
+    }
+
+    let mut paths = BTreeMap::new();
+    for (path, data) in files {
+        let metadata =
+            match ObjectMetadata::from_request(Some(&path), params.tags.as_deref(), headers) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    return upload_error_response(
+                        StatusCode::BAD_REQUEST,
+                        format!("{}: {}", path, e),
+                    );
+                }
+            };
+        let content_type = mime_guess::from_path(&path)
+            .first_or_octet_stream()
+            .to_string();
+
+        let size = data.len() as u64;
+        let (status, response) =
+            store_object(state, &context, &content_type, &metadata, data, start_time).await;
+        match response.optimistic_hash {
+            Some(hash) if response.success => {
+                context.record_stored(size);
+                paths.insert(path, hash);
+            }
+            _ => {
+                return upload_error_response(status, format!("{}: {}", path, response.message));
+            }
+        }
+    }
+
+    let index = params.index.as_deref().unwrap_or(DEFAULT_INDEX);
+    let manifest = PathManifest::new(paths, index, params.fallback.as_deref());
+    let manifest_body = match serde_json::to_vec(&manifest) {
+        Ok(body) => body,
+        Err(e) => {
+            return upload_error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to build manifest: {}", e),
+            );
+        }
+    };
+    let metadata = match ObjectMetadata::from_request(
+        Some("manifest.json"),
+        params.tags.as_deref(),
+        headers,
+    ) {
+        Ok(metadata) => metadata,
+        Err(e) => return upload_error_response(StatusCode::BAD_REQUEST, e),
+    };
+
+    // the manifest is read back by /resolve, it's stored as is
+    let manifest_context = UploadContext {
+        compression: COMPRESSION_NONE.to_string(),
+        ..context
+    };
+    let (status, response) = store_object(
+        state,
+        &manifest_context,
+        MANIFEST_CONTENT_TYPE,
+        &metadata,
+        manifest_body,
+        start_time,
+    )
+    .await;
+
+    (
+        status,
+        Json(DirectoryUploadResponse {
+            success: response.success,
+            message: response.message,
+            optimistic_hash: response.optimistic_hash,
+            paths: manifest
+                .paths
+                .into_iter()
+                .map(|(path, entry)| (path, entry.id))
+                .collect(),
+        }),
+    )
+        .into_response()
+}
+
+// file parts of a multipart directory upload by normalized path, reading
+// stops as soon as the parts run over the byte quota of the key
+async fn read_multipart_files(
+    body: axum::body::Body,
+    boundary: String,
+    context: &UploadContext,
+) -> Result<BTreeMap<String, Vec<u8>>, axum::response::Response> {
+    let bad_request = |message: String| upload_error_response(StatusCode::BAD_REQUEST, message);
+    let mut multipart = multer::Multipart::new(body.into_data_stream(), boundary);
+    let mut files = BTreeMap::new();
+    let byte_limit = context.remaining_bytes();
+    let mut total_bytes: u64 = 0;
+
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| bad_request(format!("Error reading multipart body: {}", e)))?
+    {
+        let Some(filename) = field.file_name().map(String::from) else {
+            continue;
+        };
+        let path = normalize_path(&filename)
+            .ok_or_else(|| bad_request(format!("invalid path {}", filename)))?;
+        if files.contains_key(&path) {
+            return Err(bad_request(format!("duplicate path {}", path)));
+        }
+        if files.len() >= MAX_DIRECTORY_FILES {
+            return Err(bad_request(format!(
+                "at most {} files per directory",
+                MAX_DIRECTORY_FILES
+            )));
+        }
+
+        let mut data = Vec::new();
+        while let Some(chunk) = field
+            .chunk()
+            .await
+            .map_err(|e| bad_request(format!("{}: Error reading request body: {}", path, e)))?
+        {
+            data.extend_from_slice(&chunk);
+            total_bytes += chunk.len() as u64;
+            if byte_limit.is_some_and(|limit| total_bytes > limit)
+                && let Err((exceeded, quota)) = context.check_quota(total_bytes)
+            {
+                return Err(quota_exceeded_response(exceeded, &quota));
+            }
+        }
+        files.insert(path, data);
+    }
+
+    Ok(files)
+}
+
+fn client_envelope(headers: &axum::http::HeaderMap) -> Option<BundleEncryption> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .map(|h| h.trim().to_string())
+            .unwrap_or_default()
+    };
+
+    let envelope = BundleEncryption {
+        mode: ENCRYPTION_CLIENT.to_string(),
+        algorithm: header(ENCRYPTION_ALGORITHM_HEADER),
+        key_id: header(ENCRYPTION_KEY_ID_HEADER),
+        wrapped_key: header(WRAPPED_KEY_HEADER),
+        nonce: header(ENCRYPTION_NONCE_HEADER),
+    };
+
+    let is_valid = !envelope.algorithm.is_empty()
+        && is_valid_envelope_value(&envelope.algorithm, 64)
+        && is_valid_envelope_value(&envelope.key_id, 255)
+        && is_valid_envelope_value(&envelope.wrapped_key, 2048)
+        && is_valid_envelope_value(&envelope.nonce, 255);
+    is_valid.then_some(envelope)
+}
+
+fn upload_error_response(status: StatusCode, message: String) -> axum::response::Response {
+    (
+        status,
+        Json(UploadResponse {
+            success: false,
+            message,
+            optimistic_hash: None,
+        }),
+    )
+        .into_response()
+}
+
+fn quota_exceeded_response(
+    exceeded: QuotaExceeded,
+    quota: &StorageQuota,
+) -> axum::response::Response {
+    let mut response = upload_error_response(exceeded.status_code(), exceeded.message(quota));
+    if exceeded == QuotaExceeded::BytesPerDay {
+        response.headers_mut().insert(
+            axum::http::header::RETRY_AFTER,
+            axum::http::HeaderValue::from(seconds_until_next_day(unix_now())),
+        );
     }
+    response
+}
+
+// serves `/resolve/{manifest_hash}/` and `/resolve/{manifest_hash}/{path}`
+// from the PathManifest of a directory upload
+pub async fn resolve_manifest_index_handler(
+    State(state): State<Arc<AppState>>,
+    Path(manifest_hash): Path<String>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: axum::http::HeaderMap,
+) -> axum::response::Response {
+    resolve_manifest_path(state, manifest_hash, String::new(), peer, headers).await
 }
 
-pub async fn get_bundle_by_op_hash_handler(Path(op_hash): Path<String>) -> Json<Value> {
-    let bundle = get_bundle_by_optimistic_hash(&op_hash).await.unwrap();
-    Json(serde_json::to_value(bundle).unwrap())
+pub async fn resolve_manifest_path_handler(
+    State(state): State<Arc<AppState>>,
+    Path((manifest_hash, path)): Path<(String, String)>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: axum::http::HeaderMap,
+) -> axum::response::Response {
+    resolve_manifest_path(state, manifest_hash, path, peer, headers).await
 }
 
-pub async fn get_bundle_by_load_txid_handler(Path(bundle_txid): Path<String>) -> Json<Value> {
-    let bundle = get_bundle_by_txid(&bundle_txid).await.unwrap();
-    Json(serde_json::to_value(bundle).unwrap())
+async fn resolve_manifest_path(
+    state: Arc<AppState>,
+    manifest_hash: String,
+    path: String,
+    peer: SocketAddr,
+    headers: axum::http::HeaderMap,
+) -> axum::response::Response {
+    let bundle = match get_bundle_by_optimistic_hash(&manifest_hash).await {
+        Ok(bundle) => bundle,
+        Err(e) => {
+            return (
+                StatusCode::NOT_FOUND,
+                format!("Bundle not found: {}. Error: {}", manifest_hash, e),
+            )
+                .into_response();
+        }
+    };
+    if bundle.content_type != MANIFEST_CONTENT_TYPE || bundle.is_private {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("{} is not a path manifest", manifest_hash),
+        )
+            .into_response();
+    }
+    if is_expired(&bundle, unix_now()) {
+        return (
+            StatusCode::GONE,
+            format!("Ephemeral bundle {} has expired", manifest_hash),
+        )
+            .into_response();
+    }
+
+    let manifest_url = format!(
+        "{}/object/public/{}/{}",
+        state.supabase_url.replace("/v1/s3", "/v1"),
+        state.bucket_name,
+        manifest_hash
+    );
+    let manifest = match state
+        .http_client
+        .get(&manifest_url)
+        .header("apikey", &state.api_key)
+        .header("Authorization", format!("Bearer {}", state.api_key))
+        .send()
+        .await
+    {
+        Ok(response) if response.status().is_success() => response
+            .bytes()
+            .await
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<PathManifest>(&bytes).ok()),
+        Ok(response) => {
+            println!("Error accessing manifest: {}", response.status());
+            None
+        }
+        Err(e) => {
+            println!("Error requesting manifest: {}", e);
+            None
+        }
+    };
+    let Some(manifest) = manifest else {
+        return (
+            StatusCode::BAD_GATEWAY,
+            format!("Failed to load manifest {}", manifest_hash),
+        )
+            .into_response();
+    };
+
+    match manifest.resolve(&path) {
+        Some(id) => download_object_handler(
+            State(state),
+            Path(id.to_string()),
+            Query(DownloadQuery::default()),
+            ConnectInfo(peer),
+            headers,
+        )
+        .await
+        .into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            format!("Path not found in manifest: {}", path),
+        )
+            .into_response(),
+    }
 }
 
-pub async fn bundles_stats_handler() -> Json<Value> {
-    let stats = get_bundle_stats().await.unwrap();
-    Json(serde_json::to_value(stats).unwrap())
+// server handler to stream objects
+pub async fn download_object_handler(
+    State(state): State<Arc<AppState>>,
+    Path(filename): Path<String>,
+    Query(params): Query<DownloadQuery>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    let start_time = std::time::Instant::now();
+
+    let object_metadata = match get_bundle_by_optimistic_hash(&filename).await {
+        Ok(metadata) => {
+            println!("REQUESTED BUNDLE: {:?}", metadata);
+            metadata
+        }
+        Err(e) => {
+            println!("Error getting bundle metadata: {}", e);
+            return (
+                StatusCode::NOT_FOUND,
+                format!("Bundle not found: {}. Error: {}", filename, e),
+            )
+                .into_response();
+        }
+    };
+
+    if is_expired(&object_metadata, unix_now()) {
+        return (
+            StatusCode::GONE,
+            format!("Ephemeral bundle {} has expired", filename),
+        )
+            .into_response();
+    }
+
+    let is_private = object_metadata.is_private;
+    if is_private {
+        if DOWNLOAD_SIGNING_SECRET.is_empty() {
+            return (
+                StatusCode::FORBIDDEN,
+                "Private downloads are not enabled".to_string(),
+            )
+                .into_response();
+        }
+
+        let client_ip = resolve_client_ip(peer.ip(), &headers, &TRUSTED_PROXIES);
+        if let Err(e) = verify_download(
+            DOWNLOAD_SIGNING_SECRET.as_bytes(),
+            &filename,
+            &params,
+            client_ip,
+            unix_now(),
+        ) {
+            return (StatusCode::FORBIDDEN, e.message().to_string()).into_response();
+        }
+    }
+
+    // server-side encrypted objects are only decrypted for their owner, or
+    // through a signed URL the owner created
+    let is_server_encrypted = object_metadata.encryption == ENCRYPTION_SERVER;
+    if is_server_encrypted && !is_private {
+        match identify(state.auth.as_ref(), &headers).await {
+            Ok(Some(identity))
+                if identity.has_scope(SCOPE_READ)
+                    && (identity.is_internal || identity.owner == object_metadata.owner) => {}
+            Ok(Some(_)) => {
+                return (
+                    StatusCode::FORBIDDEN,
+                    "This object is encrypted, only its owner can download it".to_string(),
+                )
+                    .into_response();
+            }
+            Ok(None) => {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    format!(
+                        "This object is encrypted, a valid {} API key is required",
+                        LOAD_HEADER_NAME
+                    ),
+                )
+                    .into_response();
+            }
+            Err(e) => {
+                println!("Error verifying API key: {:?}", e);
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Unable to verify API key, try again later".to_string(),
+                )
+                    .into_response();
+            }
+        }
+    }
+
+    let content_type = object_metadata.content_type;
+    println!("RENDERING MIME TYPE: {:?}", content_type);
+
+    let direct_url = if is_private {
+        format!(
+            "{}/object/authenticated/{}/{}",
+            state.supabase_url.replace("/v1/s3", "/v1"),
+            state.private_bucket_name,
+            filename
+        )
+    } else {
+        format!(
+            "{}/object/public/{}/{}",
+            state.supabase_url.replace("/v1/s3", "/v1"),
+            state.bucket_name,
+            filename
+        )
+    };
+
+    let file_response = match state
+        .http_client
+        .get(&direct_url)
+        .header("apikey", &state.api_key)
+        .header("Authorization", format!("Bearer {}", state.api_key))
+        .send()
+        .await
+    {
+        Ok(response) => response,
+        Err(e) => {
+            println!("Error requesting file: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to request file: {}", e),
+            )
+                .into_response();
+        }
+    };
+
+    if !file_response.status().is_success() {
+        let status = file_response.status();
+        let error_text = file_response.text().await.unwrap_or_default();
+        println!("Error accessing file: {} - {}", status, error_text);
+
+        return (
+            StatusCode::NOT_FOUND,
+            format!("File not found: {}. Error: {}", filename, error_text),
+        )
+            .into_response();
+    }
+
+    let bytes_start = std::time::Instant::now();
+    let bytes = match file_response.bytes().await {
+        Ok(b) => {
+            println!(
+                "Downloaded {} bytes in {:?}",
+                b.len(),
+                bytes_start.elapsed()
+            );
+            b
+        }
+        Err(e) => {
+            println!("Error reading file bytes: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to read file content: {}", e),
+            )
+                .into_response();
+        }
+    };
+
+    let bytes = if is_server_encrypted {
+        match state.key_ring.decrypt(
+            &bytes,
+            &object_metadata.encryption_key_id,
+            &object_metadata.wrapped_key,
+            &object_metadata.encryption_nonce,
+        ) {
+            Ok(plaintext) => bytes::Bytes::from(plaintext),
+            Err(e) => {
+                println!("Error decrypting {}: {:?}", filename, e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to decrypt file: {}", e),
+                )
+                    .into_response();
+            }
+        }
+    } else {
+        bytes
+    };
+
+    // compressed objects are sent as stored to clients accepting the encoding
+    let compression = object_metadata.compression.clone();
+    let accept_encoding = headers
+        .get(axum::http::header::ACCEPT_ENCODING)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("");
+    let send_compressed =
+        !compression.is_empty() && accepts_encoding(accept_encoding, &compression);
+    let bytes = if compression.is_empty() || send_compressed {
+        bytes
+    } else {
+        match decompress(&compression, &bytes) {
+            Ok(decompressed) => bytes::Bytes::from(decompressed),
+            Err(e) => {
+                println!("Error decompressing {}: {:?}", filename, e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to decompress file: {}", e),
+                )
+                    .into_response();
+            }
+        }
+    };
+
+    let stream = stream::once(async move { Ok::<_, Infallible>(bytes) });
+    let body = Body::from_stream(stream);
+
+    println!(
+        "Download handler setup completed in {:?}",
+        start_time.elapsed()
+    );
+
+    let is_video = content_type.starts_with("video/");
+
+    let mut response = if object_metadata.encryption == ENCRYPTION_CLIENT {
+        // the envelope is served as is, clients decrypt it themselves
+        axum::response::Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", "application/octet-stream")
+            .header("x-load-content-type", content_type)
+            .header(ENCRYPTION_HEADER, ENCRYPTION_CLIENT)
+            .header(
+                ENCRYPTION_ALGORITHM_HEADER,
+                object_metadata.encryption_algorithm,
+            )
+            .header(ENCRYPTION_KEY_ID_HEADER, object_metadata.encryption_key_id)
+            .header(WRAPPED_KEY_HEADER, object_metadata.wrapped_key)
+            .header(ENCRYPTION_NONCE_HEADER, object_metadata.encryption_nonce)
+            .header(
+                "cache-control",
+                if is_private {
+                    "private, no-store"
+                } else {
+                    "public, max-age=3600"
+                },
+            )
+            .body(body)
+            .unwrap()
+            .into_response()
+    } else if is_private || is_server_encrypted {
+        axum::response::Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", content_type)
+            .header("cache-control", "private, no-store")
+            .body(body)
+            .unwrap()
+            .into_response()
+    } else if is_video {
+        axum::response::Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", content_type)
+            .header("accept-ranges", "bytes")
+            .header("cache-control", "public, max-age=31536000")
+            .body(body)
+            .unwrap()
+            .into_response()
+    } else {
+        axum::response::Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", content_type)
+            .header("transfer-encoding", "chunked")
+            .header("cache-control", "public, max-age=3600") // 1 hour cache for non-video content
+            .body(body)
+            .unwrap()
+            .into_response()
+    };
+
+    if !compression.is_empty() {
+        let headers = response.headers_mut();
+        headers.insert(
+            axum::http::header::VARY,
+            axum::http::HeaderValue::from_static("accept-encoding"),
+        );
+        if send_compressed && let Ok(value) = axum::http::HeaderValue::from_str(&compression) {
+            headers.insert(axum::http::header::CONTENT_ENCODING, value);
+        }
+    }
+
+    response
+}
+
+pub async fn get_bundle_by_op_hash_handler(
+    State(state): State<Arc<AppState>>,
+    Path(op_hash): Path<String>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    let bundle = get_bundle_by_optimistic_hash(&op_hash).await;
+    bundle_metadata_response(&state, &headers, &op_hash, bundle).await
+}
+
+pub async fn get_bundle_by_load_txid_handler(
+    State(state): State<Arc<AppState>>,
+    Path(bundle_txid): Path<String>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    let bundle = get_bundle_by_txid(&bundle_txid).await;
+    bundle_metadata_response(&state, &headers, &bundle_txid, bundle).await
+}
+
+// private bundles are only described to their owner (or the internal key),
+// for anyone else they don't exist. The filename and user metadata of
+// encrypted bundles are kept to their owner too, like on chain
+async fn bundle_metadata_response(
+    state: &AppState,
+    headers: &axum::http::HeaderMap,
+    id: &str,
+    bundle: Result<Bundle, anyhow::Error>,
+) -> axum::response::Response {
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": format!("Bundle not found: {}", id)})),
+        )
+            .into_response()
+    };
+    let bundle = match bundle {
+        Ok(bundle) => bundle,
+        Err(e) => {
+            println!("Error getting bundle metadata: {}", e);
+            return not_found();
+        }
+    };
+
+    let is_encrypted = !bundle.encryption.is_empty();
+    let is_owner = if bundle.is_private || is_encrypted {
+        match is_bundle_reader(state.auth.as_ref(), headers, &bundle.owner).await {
+            Ok(is_owner) => is_owner,
+            Err(e) => {
+                println!("Error verifying API key: {:?}", e);
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    Json(json!({"error": "Unable to verify API key, try again later"})),
+                )
+                    .into_response();
+            }
+        }
+    } else {
+        true
+    };
+
+    if bundle.is_private && !is_owner {
+        return not_found();
+    }
+    if is_encrypted && !is_owner {
+        let mut value = serde_json::to_value(Bundle {
+            filename: String::new(),
+            ..bundle
+        })
+        .unwrap();
+        value["metadata"] = json!({});
+        value["tags"] = json!([]);
+        return (StatusCode::OK, Json(value)).into_response();
+    }
+    (StatusCode::OK, Json(bundle_with_metadata(bundle).await)).into_response()
+}
+
+// private bundles are only visible to their owner, like their metadata
+pub async fn bundle_settlement_handler(
+    State(state): State<Arc<AppState>>,
+    Path(op_hash): Path<String>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": format!("Bundle not found: {}", op_hash)})),
+        )
+            .into_response()
+    };
+    let bundle = match get_bundle_by_optimistic_hash(&op_hash).await {
+        Ok(bundle) => bundle,
+        Err(e) => {
+            println!("Error getting bundle metadata: {}", e);
+            return not_found();
+        }
+    };
+    if bundle.is_private {
+        match is_bundle_reader(state.auth.as_ref(), &headers, &bundle.owner).await {
+            Ok(true) => {}
+            Ok(false) => return not_found(),
+            Err(e) => {
+                println!("Error verifying API key: {:?}", e);
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    Json(json!({"error": "Unable to verify API key, try again later"})),
+                )
+                    .into_response();
+            }
+        }
+    }
+
+    let cost = get_settlement_cost(&op_hash).await.unwrap_or_else(|e| {
+        println!("Error loading settlement cost: {:?}", e);
+        None
+    });
+    match get_bundle_chunks(&op_hash).await {
+        Ok(chunks) => {
+            let mut progress =
+                serde_json::to_value(SettlementProgress::new(&bundle, chunks)).unwrap();
+            progress["cost"] = json!(cost);
+            (StatusCode::OK, Json(progress)).into_response()
+        }
+        Err(e) => {
+            println!("Error loading settlement progress: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to load settlement progress: {}", e)})),
+            )
+                .into_response()
+        }
+    }
+}
+
+// the Bundle row plus its x-load-meta-* attributes and tags
+async fn bundle_with_metadata(bundle: Bundle) -> Value {
+    let tags = get_bundle_tags(&bundle.optimistic_hash)
+        .await
+        .unwrap_or_else(|e| {
+            println!("Error getting bundle tags: {:?}", e);
+            Vec::new()
+        });
+    let metadata = ObjectMetadata::from_bundle_tags(&bundle.filename, tags);
+
+    let mut value = serde_json::to_value(bundle).unwrap();
+    value["metadata"] = json!(metadata.attributes);
+    value["tags"] = json!(metadata.tags);
+    value
+}
+
+pub async fn sign_bundle_handler(
+    State(state): State<Arc<AppState>>,
+    Path(optimistic_hash): Path<String>,
+    headers: axum::http::HeaderMap,
+    Json(payload): Json<SignBundleRequest>,
+) -> impl IntoResponse {
+    if DOWNLOAD_SIGNING_SECRET.is_empty() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({"error": "signed URLs are not enabled"})),
+        )
+            .into_response();
+    }
+
+    let identity = match identify(state.auth.as_ref(), &headers).await {
+        Ok(Some(identity)) if identity.has_scope(SCOPE_READ) => identity,
+        Ok(_) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"error": format!("A valid {} API key is required", LOAD_HEADER_NAME)})),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            println!("Error verifying API key: {:?}", e);
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({"error": "Unable to verify API key, try again later"})),
+            )
+                .into_response();
+        }
+    };
+
+    let bundle = match get_bundle_by_optimistic_hash(&optimistic_hash).await {
+        Ok(bundle) => bundle,
+        Err(e) => {
+            println!("Error getting bundle metadata: {}", e);
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({"error": format!("Bundle not found: {}", optimistic_hash)})),
+            )
+                .into_response();
+        }
+    };
+
+    if !identity.is_internal && (bundle.owner.is_empty() || bundle.owner != identity.owner) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(json!({"error": "only the bundle owner can sign URLs for it"})),
+        )
+            .into_response();
+    }
+
+    let ip = match payload.ip.as_deref().map(str::parse::<IpAddr>) {
+        None => None,
+        Some(Ok(ip)) => Some(ip.to_canonical().to_string()),
+        Some(Err(_)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "invalid ip"})),
+            )
+                .into_response();
+        }
+    };
+
+    let expires_in = payload
+        .expires_in
+        .unwrap_or(DEFAULT_SIGNED_URL_TTL_SECS)
+        .clamp(1, MAX_SIGNED_URL_TTL_SECS);
+    let expires_at = unix_now() + expires_in;
+    let path = signed_download_path(
+        DOWNLOAD_SIGNING_SECRET.as_bytes(),
+        &bundle.optimistic_hash,
+        expires_at,
+        ip.as_deref(),
+    );
+
+    (
+        StatusCode::OK,
+        Json(SignBundleResponse {
+            optimistic_hash: bundle.optimistic_hash,
+            url: format!("{}{}", *PUBLIC_URL, path),
+            expires_at,
+            ip,
+        }),
+    )
+        .into_response()
+}
+
+pub async fn list_bundles_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ListBundlesQuery>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    let identity = match identify(state.auth.as_ref(), &headers).await {
+        Ok(Some(identity)) if identity.has_scope(SCOPE_READ) => identity,
+        Ok(_) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"error": format!("A valid {} API key is required", LOAD_HEADER_NAME)})),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            println!("Error verifying API key: {:?}", e);
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({"error": "Unable to verify API key, try again later"})),
+            )
+                .into_response();
+        }
+    };
+
+    let query = match bundle_query(params, &identity) {
+        Ok(query) => query,
+        Err((status, message)) => {
+            return (status, Json(json!({"error": message}))).into_response();
+        }
+    };
+
+    match search_bundles(&query).await {
+        Ok(mut bundles) => {
+            let next_cursor = if bundles.len() > query.limit as usize {
+                bundles.truncate(query.limit as usize);
+                bundles.last().map(|last| {
+                    BundleCursor {
+                        sort: query.sort,
+                        value: match query.sort {
+                            BundleSort::Id => last.id as u64,
+                            BundleSort::Size => last.data_size,
+                            BundleSort::CreatedAt => last.created_at,
+                        },
+                        id: last.id,
+                    }
+                    .encode()
+                })
+            } else {
+                None
+            };
+
+            (
+                StatusCode::OK,
+                Json(json!({
+                    "owner": query.owner.unwrap_or_else(|| "*".to_string()),
+                    "bundles": bundles,
+                    "next_cursor": next_cursor,
+                })),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            println!("Error listing bundles: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to list bundles: {}", e)})),
+            )
+                .into_response()
+        }
+    }
+}
+
+fn bundle_query(
+    params: ListBundlesQuery,
+    identity: &ApiKeyIdentity,
+) -> Result<BundleQuery, (StatusCode, String)> {
+    let bad_request = |message: &str| (StatusCode::BAD_REQUEST, message.to_string());
+    let is_internal = identity.is_internal;
+
+    // only the internal key can list another owner's bundles, or all of them
+    let owner = match params.owner.as_deref() {
+        None | Some("me") => Some(identity.owner.clone()),
+        Some("*") if is_internal => None,
+        Some(owner) if is_internal && is_valid_owner(owner) => Some(owner.to_string()),
+        Some(_) => {
+            return Err((StatusCode::FORBIDDEN, "owner must be `me`".to_string()));
+        }
+    };
+
+    let meta = match params.meta.as_deref().map(|meta| meta.split_once(':')) {
+        None => None,
+        Some(Some((name, value))) => Some((name.to_string(), value.to_string())),
+        Some(None) => return Err(bad_request("meta must be `key:value`")),
+    };
+
+    if let Some(prefix) = &params.content_type
+        && !is_valid_content_type_prefix(prefix)
+    {
+        return Err(bad_request("invalid content_type prefix"));
+    }
+
+    let sort = match params.sort.as_deref() {
+        None => BundleSort::default(),
+        Some(sort) => BundleSort::parse(sort)
+            .ok_or_else(|| bad_request("sort must be `id`, `size` or `created_at`"))?,
+    };
+    let descending = match params.order.as_deref() {
+        None | Some("desc") => true,
+        Some("asc") => false,
+        Some(_) => return Err(bad_request("order must be `asc` or `desc`")),
+    };
+
+    let cursor = match params.cursor.as_deref() {
+        None => None,
+        Some(cursor) => match BundleCursor::decode(cursor) {
+            Ok(cursor) if cursor.sort == sort => Some(cursor),
+            _ => return Err(bad_request("invalid cursor for this sort")),
+        },
+    };
+
+    Ok(BundleQuery {
+        owner,
+        content_type_prefix: params.content_type,
+        is_settled: params.settled,
+        min_size: params.min_size,
+        max_size: params.max_size,
+        created_after: params.created_after,
+        created_before: params.created_before,
+        tag: params.tag,
+        meta,
+        sort,
+        descending,
+        cursor,
+        limit: params
+            .limit
+            .unwrap_or(DEFAULT_LIST_LIMIT)
+            .clamp(1, MAX_LIST_LIMIT),
+    })
+}
+
+pub async fn estimate_handler(Query(params): Query<EstimateQuery>) -> impl IntoResponse {
+    let size = match params.size {
+        Some(size) if size > 0 && size <= *MAX_UPLOAD_BYTES => size,
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": format!("size must be between 1 and {} bytes", *MAX_UPLOAD_BYTES)})),
+            )
+                .into_response();
+        }
+    };
+
+    match current_gas_price().await {
+        Ok(gas_price) => {
+            (StatusCode::OK, Json(estimate_settlement(size, gas_price))).into_response()
+        }
+        Err(e) => {
+            println!("Error loading the gas price: {:?}", e);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({"error": "Unable to load the gas price, try again later"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+pub async fn usage_handler(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    let identity = match identify(state.auth.as_ref(), &headers).await {
+        Ok(Some(identity)) => identity,
+        Ok(None) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"error": format!("A valid {} API key is required", LOAD_HEADER_NAME)})),
+            )
+                .into_response();
+        }
+        Err(e) => {
+            println!("Error verifying API key: {:?}", e);
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({"error": "Unable to verify API key, try again later"})),
+            )
+                .into_response();
+        }
+    };
+
+    // settlement costs are best effort, they don't fail the usage
+    let settlement_costs = get_owner_settlement_costs(&identity.owner)
+        .await
+        .unwrap_or_else(|e| {
+            println!("Error loading settlement costs: {:?}", e);
+            OwnerSettlementCosts::default()
+        });
+
+    match get_key_usage(&identity.key_id, day_start(unix_now())).await {
+        Ok(usage) => (
+            StatusCode::OK,
+            Json(UsageResponse {
+                key_id: identity.key_id,
+                owner: identity.owner,
+                quota: identity.quota,
+                usage,
+                settlement_costs,
+            }),
+        )
+            .into_response(),
+        Err(e) => {
+            println!("Error loading usage: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to load usage: {}", e)})),
+            )
+                .into_response()
+        }
+    }
+}
+
+pub async fn bundles_stats_handler(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    let snapshot = match state
+        .stats_cache
+        .get_or_refresh(|| load_stats_snapshot(unix_now()))
+        .await
+    {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            println!("Error loading stats: {:?}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to load stats: {}", e)})),
+            )
+                .into_response();
+        }
+    };
+
+    let mut stats = serde_json::to_value(&*snapshot).unwrap();
+    // owners are only listed to the internal key
+    if !is_internal_request(&headers)
+        && let Some(stats) = stats.as_object_mut()
+    {
+        stats.remove("by_owner");
+    }
+    (StatusCode::OK, Json(stats)).into_response()
+}
+
+// the internal key sees every event, API keys only the events of their owner
+// and anonymous subscribers must follow the optimistic hash of a public bundle
+async fn events_filter(
+    state: &AppState,
+    headers: &axum::http::HeaderMap,
+    params: EventsQuery,
+) -> Result<EventFilter, axum::response::Response> {
+    let mut filter = EventFilter {
+        owner: params.owner.filter(|owner| !owner.is_empty()),
+        optimistic_hash: params.optimistic_hash.filter(|hash| !hash.is_empty()),
+    };
+    if is_internal_request(headers) {
+        return Ok(filter);
+    }
+
+    match identify(state.auth.as_ref(), headers).await {
+        Ok(Some(identity)) if identity.has_scope(SCOPE_READ) => {
+            if filter
+                .owner
+                .as_ref()
+                .is_some_and(|owner| *owner != identity.owner)
+            {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    Json(json!({"error": "only the events of your own owner can be streamed"})),
+                )
+                    .into_response());
+            }
+            filter.owner = Some(identity.owner);
+            Ok(filter)
+        }
+        Ok(_) if filter.owner.is_none() && filter.optimistic_hash.is_some() => {
+            let hash = filter.optimistic_hash.clone().unwrap_or_default();
+            // the events of a private bundle are its owner's, like its metadata
+            match get_bundle_by_optimistic_hash(&hash).await {
+                Ok(bundle) if !bundle.is_private => Ok(filter),
+                _ => Err((
+                    StatusCode::NOT_FOUND,
+                    Json(json!({"error": format!("Bundle not found: {}", hash)})),
+                )
+                    .into_response()),
+            }
+        }
+        Ok(_) => Err((
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error": format!("A valid {} API key or an optimistic_hash is required", LOAD_HEADER_NAME)})),
+        )
+            .into_response()),
+        Err(e) => {
+            println!("Error verifying API key: {:?}", e);
+            Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({"error": "Unable to verify API key, try again later"})),
+            )
+                .into_response())
+        }
+    }
+}
+
+pub async fn events_sse_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<EventsQuery>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    let filter = match events_filter(&state, &headers, params).await {
+        Ok(filter) => filter,
+        Err(response) => return response,
+    };
+
+    let receiver = events::subscribe();
+    let stream = stream::unfold((receiver, filter), |(mut receiver, filter)| async move {
+        let event = events::next_event(&mut receiver, &filter).await?;
+        let sse_event = Event::default()
+            .event(event.event.clone())
+            .json_data(&event)
+            .unwrap_or_default();
+        Some((Ok::<_, Infallible>(sse_event), (receiver, filter)))
+    });
+
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+pub async fn events_ws_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<EventsQuery>,
+    headers: axum::http::HeaderMap,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let filter = match events_filter(&state, &headers, params).await {
+        Ok(filter) => filter,
+        Err(response) => return response,
+    };
+
+    ws.on_upgrade(move |socket| stream_events_ws(socket, filter))
+}
+
+// incoming messages are ignored, the stream ends when the client closes it
+async fn stream_events_ws(mut socket: WebSocket, filter: EventFilter) {
+    let mut receiver = events::subscribe();
+    loop {
+        tokio::select! {
+            event = events::next_event(&mut receiver, &filter) => {
+                let Some(event) = event else { break };
+                let json = serde_json::to_string(&event).unwrap_or_default();
+                if socket.send(Message::Text(json.into())).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        }
+    }
+}
+
+fn is_internal_request(headers: &axum::http::HeaderMap) -> bool {
+    headers
+        .get(LOAD_HEADER_NAME)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(is_internal_key)
+}
+
+pub async fn create_api_key_handler(
+    headers: axum::http::HeaderMap,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> impl IntoResponse {
+    if !is_internal_request(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error": "internal key required"})),
+        )
+            .into_response();
+    }
+
+    if !is_valid_owner(&payload.owner) || is_reserved_owner(&payload.owner) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "invalid owner"})),
+        )
+            .into_response();
+    }
+
+    let scopes = payload
+        .scopes
+        .unwrap_or_else(|| vec![SCOPE_READ.to_string(), SCOPE_UPLOAD.to_string()]);
+    let scopes = parse_scopes(&scopes.join(","));
+    if scopes.iter().any(|s| !KNOWN_SCOPES.contains(&s.as_str())) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "invalid scope"})),
+        )
+            .into_response();
+    }
+
+    let api_key = generate_api_key();
+    let expires_at = payload.expires_at.unwrap_or(0);
+    let quota = payload.quota.unwrap_or_default();
+
+    match insert_api_key(
+        &keccak_hex(api_key.as_bytes()),
+        &payload.owner,
+        &scopes.join(","),
+        expires_at,
+        unix_now(),
+        &quota,
+    )
+    .await
+    {
+        Ok(record) => (
+            StatusCode::OK,
+            Json(CreateApiKeyResponse {
+                key_id: record.id,
+                api_key,
+                owner: record.owner,
+                scopes,
+                expires_at,
+                quota,
+            }),
+        )
+            .into_response(),
+        Err(e) => {
+            println!("Error inserting api key: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to create api key: {}", e)})),
+            )
+                .into_response()
+        }
+    }
+}
+
+// issues a signed token for partners that should get the whitelisted tier,
+// e.g. a frontend that can't be identified by its source address
+pub async fn create_origin_token_handler(
+    headers: axum::http::HeaderMap,
+    Json(payload): Json<CreateOriginTokenRequest>,
+) -> impl IntoResponse {
+    if !is_internal_request(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error": "internal key required"})),
+        )
+            .into_response();
+    }
+
+    if WHITELIST_TOKEN_SECRET.is_empty() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({"error": "origin tokens are not enabled"})),
+        )
+            .into_response();
+    }
+
+    if !is_valid_owner(&payload.subject) || payload.subject.contains(':') || payload.ttl_secs == 0 {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "invalid subject or ttl_secs"})),
+        )
+            .into_response();
+    }
+
+    let expires_at = unix_now().saturating_add(payload.ttl_secs);
+    let token = sign_origin_token(
+        WHITELIST_TOKEN_SECRET.as_bytes(),
+        &payload.subject,
+        expires_at,
+    );
+
+    (
+        StatusCode::OK,
+        Json(CreateOriginTokenResponse {
+            token,
+            subject: payload.subject,
+            expires_at,
+        }),
+    )
+        .into_response()
+}
+
+pub async fn revoke_api_key_handler(
+    headers: axum::http::HeaderMap,
+    Path(key_id): Path<u32>,
+) -> impl IntoResponse {
+    if !is_internal_request(&headers) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error": "internal key required"})),
+        )
+            .into_response();
+    }
+
+    match revoke_api_key(key_id).await {
+        Ok(_) => (StatusCode::OK, Json(json!({"revoked": key_id}))).into_response(),
+        Err(e) => {
+            println!("Error revoking api key: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to revoke api key: {}", e)})),
+            )
+                .into_response()
+        }
+    }
+}
+
+// webhooks are managed with an upload key, they fire for that key's uploads
+async fn webhook_identity(
+    state: &AppState,
+    headers: &axum::http::HeaderMap,
+) -> Result<ApiKeyIdentity, axum::response::Response> {
+    if !webhooks::is_enabled() {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({"error": "webhooks are not enabled"})),
+        )
+            .into_response());
+    }
+
+    match identify(state.auth.as_ref(), headers).await {
+        Ok(Some(identity)) if identity.has_scope(SCOPE_UPLOAD) => Ok(identity),
+        Ok(_) => Err((
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error": format!("A valid {} API key with the upload scope is required", LOAD_HEADER_NAME)})),
+        )
+            .into_response()),
+        Err(e) => {
+            println!("Error verifying API key: {:?}", e);
+            Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({"error": "Unable to verify API key, try again later"})),
+            )
+                .into_response())
+        }
+    }
+}
+
+// the webhook when `identity` owns it, or is the internal key
+async fn owned_webhook(
+    identity: &ApiKeyIdentity,
+    id: u32,
+) -> Result<Webhook, axum::response::Response> {
+    match get_webhook(id).await {
+        Ok(Some(webhook)) if webhook.owner == identity.owner || identity.is_internal => Ok(webhook),
+        Ok(_) => Err((
+            StatusCode::NOT_FOUND,
+            Json(json!({"error": format!("Webhook not found: {}", id)})),
+        )
+            .into_response()),
+        Err(e) => {
+            println!("Error loading webhook: {:?}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to load webhook: {}", e)})),
+            )
+                .into_response())
+        }
+    }
+}
+
+pub async fn create_webhook_handler(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(payload): Json<CreateWebhookRequest>,
+) -> impl IntoResponse {
+    let identity = match webhook_identity(&state, &headers).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+
+    if let Err(e) = webhooks::validate_url(&payload.url, *WEBHOOK_ALLOW_PRIVATE_URLS) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": format!("Invalid url: {}", e)})),
+        )
+            .into_response();
+    }
+    let events = match webhooks::parse_events(payload.events.as_deref()) {
+        Ok(events) => events,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, Json(json!({"error": e}))).into_response();
+        }
+    };
+
+    match insert_webhook(
+        &identity.owner,
+        &identity.key_id,
+        &payload.url,
+        &events,
+        "",
+        unix_now(),
+    )
+    .await
+    {
+        Ok(webhook) => match webhooks::issue_owner_secret(&identity.owner).await {
+            Ok(signing_secret) => (
+                StatusCode::OK,
+                Json(WebhooksResponse {
+                    owner: identity.owner,
+                    signing_secret,
+                    webhooks: vec![webhook],
+                }),
+            )
+                .into_response(),
+            Err(e) => {
+                println!("Error issuing webhook secret: {:?}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({"error": format!("Webhook {} created but its signing secret could not be issued, rotate it with POST /webhooks/secret/rotate: {}", webhook.id, e)})),
+                )
+                    .into_response()
+            }
+        },
+        Err(e) => {
+            println!("Error creating webhook: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to create webhook: {}", e)})),
+            )
+                .into_response()
+        }
+    }
+}
+
+pub async fn list_webhooks_handler(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    let identity = match webhook_identity(&state, &headers).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+
+    match get_webhooks_by_owner(&identity.owner).await {
+        Ok(webhooks) => (
+            StatusCode::OK,
+            Json(WebhooksResponse {
+                owner: identity.owner,
+                signing_secret: None,
+                webhooks,
+            }),
+        )
+            .into_response(),
+        Err(e) => {
+            println!("Error listing webhooks: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to list webhooks: {}", e)})),
+            )
+                .into_response()
+        }
+    }
+}
+
+// replaces the signing secret of every webhook of the owner, the new one is
+// only returned here
+pub async fn rotate_webhook_secret_handler(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    let identity = match webhook_identity(&state, &headers).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+
+    match webhooks::rotate_owner_secret(&identity.owner).await {
+        Ok(signing_secret) => (
+            StatusCode::OK,
+            Json(WebhookSecretResponse {
+                owner: identity.owner,
+                signing_secret,
+            }),
+        )
+            .into_response(),
+        Err(e) => {
+            println!("Error rotating webhook secret: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to rotate webhook secret: {}", e)})),
+            )
+                .into_response()
+        }
+    }
+}
+
+pub async fn delete_webhook_handler(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Path(id): Path<u32>,
+) -> impl IntoResponse {
+    let identity = match webhook_identity(&state, &headers).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    if let Err(response) = owned_webhook(&identity, id).await {
+        return response;
+    }
+
+    match deactivate_webhook(id).await {
+        Ok(_) => (StatusCode::OK, Json(json!({"deleted": id}))).into_response(),
+        Err(e) => {
+            println!("Error deleting webhook: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to delete webhook: {}", e)})),
+            )
+                .into_response()
+        }
+    }
+}
+
+pub async fn webhook_deliveries_handler(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Path(id): Path<u32>,
+    Query(params): Query<WebhookDeliveriesQuery>,
+) -> impl IntoResponse {
+    let identity = match webhook_identity(&state, &headers).await {
+        Ok(identity) => identity,
+        Err(response) => return response,
+    };
+    if let Err(response) = owned_webhook(&identity, id).await {
+        return response;
+    }
+
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .clamp(1, MAX_LIST_LIMIT);
+    match get_webhook_deliveries(id, limit).await {
+        Ok(deliveries) => (
+            StatusCode::OK,
+            Json(WebhookDeliveriesResponse {
+                webhook_id: id,
+                deliveries,
+            }),
+        )
+            .into_response(),
+        Err(e) => {
+            println!("Error loading webhook deliveries: {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"error": format!("Failed to load deliveries: {}", e)})),
+            )
+                .into_response()
+        }
+    }
 }
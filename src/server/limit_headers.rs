@@ -0,0 +1,178 @@
+use axum::Json;
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+
+pub const TIER_HEADER_NAME: &str = "X-Load-Tier";
+
+// one token bucket of a tier, e.g. the anonymous tier uploads
+#[derive(Debug, Clone)]
+pub struct RateLimitPolicy {
+    pub tier: String,
+    pub burst_size: u32,
+    pub replenish_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RateLimitedResponse {
+    pub error: String,
+    pub message: String,
+    pub tier: String,
+    pub limit: u32,
+    pub replenish_secs: u64,
+    pub retry_after: u64,
+    pub upgrade: String,
+}
+
+fn upgrade_hint(tier: &str) -> String {
+    let hint = match tier {
+        "anonymous" => {
+            "Send an API key in the X-Load-Authorization header to get the authenticated tier limits"
+        }
+        _ => "Contact the Load Network team to raise the limits of your key",
+    };
+
+    match std::env::var("RATE_LIMIT_UPGRADE_URL") {
+        Ok(url) if !url.trim().is_empty() => format!("{}: {}", hint, url.trim()),
+        _ => hint.to_string(),
+    }
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers
+        .get(name)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<u64>().ok())
+}
+
+// maps the x-ratelimit-* headers of GovernorLayer / shared_rate_limit to the
+// standard RateLimit-* ones and turns a 429 into a JSON body
+pub fn apply_rate_limit_headers(policy: &RateLimitPolicy, response: Response) -> Response {
+    let burst_size = policy.burst_size as u64;
+
+    // handlers answer 429 too (e.g. the daily quota), only the limiter sets
+    // x-ratelimit-after
+    let limiter_wait = header_u64(response.headers(), "x-ratelimit-after");
+    if response.status() == StatusCode::TOO_MANY_REQUESTS
+        && let Some(retry_after) = limiter_wait
+    {
+        let retry_after = retry_after.max(1);
+
+        let mut limited = (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(RateLimitedResponse {
+                error: "rate_limited".to_string(),
+                message: format!(
+                    "Too many requests for the {} tier, retry in {}s",
+                    policy.tier, retry_after
+                ),
+                tier: policy.tier.clone(),
+                limit: policy.burst_size,
+                replenish_secs: policy.replenish_secs,
+                retry_after,
+                upgrade: upgrade_hint(&policy.tier),
+            }),
+        )
+            .into_response();
+        let headers = limited.headers_mut();
+        headers.insert("ratelimit-limit", HeaderValue::from(burst_size));
+        headers.insert("ratelimit-remaining", HeaderValue::from(0));
+        headers.insert("ratelimit-reset", HeaderValue::from(retry_after));
+        headers.insert("retry-after", HeaderValue::from(retry_after));
+        headers.insert("x-ratelimit-after", HeaderValue::from(retry_after));
+        return limited;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let remaining = header_u64(&parts.headers, "x-ratelimit-remaining")
+        .unwrap_or(burst_size)
+        .min(burst_size);
+    // a shared store knows its window reset, governor refills one request
+    // every replenish_secs
+    let reset = header_u64(&parts.headers, "ratelimit-reset")
+        .unwrap_or((burst_size - remaining) * policy.replenish_secs);
+
+    parts
+        .headers
+        .insert("ratelimit-limit", HeaderValue::from(burst_size));
+    parts
+        .headers
+        .insert("ratelimit-remaining", HeaderValue::from(remaining));
+    parts
+        .headers
+        .insert("ratelimit-reset", HeaderValue::from(reset));
+    Response::from_parts(parts, body)
+}
+
+pub async fn rate_limit_headers(
+    State(policy): State<RateLimitPolicy>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(req).await;
+    apply_rate_limit_headers(&policy, response)
+}
+
+// tags every response of a tier router, limited or not
+pub async fn tier_header(State(tier): State<String>, mut response: Response) -> Response {
+    if let Ok(value) = HeaderValue::from_str(&tier) {
+        response.headers_mut().insert(TIER_HEADER_NAME, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::server::limit_headers::{
+        RateLimitPolicy, RateLimitedResponse, apply_rate_limit_headers,
+    };
+    use axum::http::StatusCode;
+    use axum::response::{IntoResponse, Response};
+
+    fn header(response: &Response, name: &str) -> String {
+        response.headers()[name].to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    pub async fn test_apply_rate_limit_headers() {
+        let policy = RateLimitPolicy {
+            tier: "anonymous".to_string(),
+            burst_size: 6,
+            replenish_secs: 60,
+        };
+
+        let ok = (
+            StatusCode::OK,
+            [("x-ratelimit-limit", "6"), ("x-ratelimit-remaining", "4")],
+            "ok",
+        )
+            .into_response();
+        let ok = apply_rate_limit_headers(&policy, ok);
+        assert_eq!(ok.status(), StatusCode::OK);
+        assert_eq!(header(&ok, "ratelimit-limit"), "6");
+        assert_eq!(header(&ok, "ratelimit-remaining"), "4");
+        assert_eq!(header(&ok, "ratelimit-reset"), "120");
+
+        let limited = (
+            StatusCode::TOO_MANY_REQUESTS,
+            [("x-ratelimit-after", "42"), ("retry-after", "42")],
+            "Too Many Requests! Wait for 42s",
+        )
+            .into_response();
+        let limited = apply_rate_limit_headers(&policy, limited);
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(&limited, "ratelimit-remaining"), "0");
+        assert_eq!(header(&limited, "ratelimit-reset"), "42");
+        assert_eq!(header(&limited, "retry-after"), "42");
+
+        let body = axum::body::to_bytes(limited.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: RateLimitedResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.tier, "anonymous");
+        assert_eq!(body.retry_after, 42);
+        assert!(body.upgrade.contains("X-Load-Authorization"));
+    }
+}
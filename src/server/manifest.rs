@@ -0,0 +1,194 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// content type of the manifest objects stored by directory uploads
+pub const MANIFEST_CONTENT_TYPE: &str = "application/x.load0-manifest+json";
+pub const MANIFEST_FORMAT: &str = "load0/paths";
+pub const MANIFEST_VERSION: &str = "0.1.0";
+pub const DEFAULT_INDEX: &str = "index.html";
+
+// settled manifests follow the ANS-104 path manifest format, so gateways can
+// resolve them by path without load0
+pub const SETTLED_MANIFEST_CONTENT_TYPE: &str = "application/x.arweave-manifest+json";
+pub const SETTLED_MANIFEST_FORMAT: &str = "arweave/paths";
+pub const SETTLED_MANIFEST_VERSION: &str = "0.2.0";
+
+// paths end up in the bundles.filename column
+const MAX_PATH_LEN: usize = 255;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestIndex {
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestPath {
+    pub id: String,
+}
+
+// maps the paths of a directory upload to the optimistic hashes of its files
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PathManifest {
+    pub manifest: String,
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<ManifestIndex>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<ManifestPath>,
+    pub paths: BTreeMap<String, ManifestPath>,
+}
+
+// `a/./b.txt`, `/a/b.txt` and `a\b.txt` all become `a/b.txt`, paths that
+// escape the directory are rejected
+pub fn normalize_path(path: &str) -> Option<String> {
+    let path = path.replace('\\', "/");
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return None,
+            segment if segment.chars().any(|c| c.is_control()) => return None,
+            segment => segments.push(segment),
+        }
+    }
+
+    let normalized = segments.join("/");
+    (!normalized.is_empty() && normalized.len() <= MAX_PATH_LEN).then_some(normalized)
+}
+
+impl PathManifest {
+    // `index` and `fallback` are paths of the directory, ignored when missing
+    pub fn new(paths: BTreeMap<String, String>, index: &str, fallback: Option<&str>) -> Self {
+        let index = paths.contains_key(index).then(|| ManifestIndex {
+            path: index.to_string(),
+        });
+        let fallback = fallback
+            .and_then(|path| paths.get(path))
+            .map(|id| ManifestPath { id: id.clone() });
+
+        Self {
+            manifest: MANIFEST_FORMAT.to_string(),
+            version: MANIFEST_VERSION.to_string(),
+            index,
+            fallback,
+            paths: paths
+                .into_iter()
+                .map(|(path, id)| (path, ManifestPath { id }))
+                .collect(),
+        }
+    }
+
+    // exact path, then the index document of the path as a directory, then
+    // the fallback
+    pub fn resolve(&self, path: &str) -> Option<&str> {
+        let path = normalize_path(path).unwrap_or_default();
+        let index = self.index.as_ref().map(|index| index.path.as_str());
+
+        let candidate = match (path.is_empty(), index) {
+            (true, Some(index)) => self.paths.get(index),
+            (true, None) => None,
+            (false, index) => self.paths.get(&path).or_else(|| {
+                let name = index?.rsplit('/').next()?;
+                self.paths.get(&format!("{}/{}", path, name))
+            }),
+        };
+
+        candidate
+            .or(self.fallback.as_ref())
+            .map(|entry| entry.id.as_str())
+    }
+
+    // optimistic hashes of the files, the fallback is always one of them
+    pub fn ids(&self) -> Vec<&str> {
+        let mut ids: Vec<&str> = self.paths.values().map(|entry| entry.id.as_str()).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    // the manifest pointing at the settled txids of its files, `txids` maps
+    // optimistic hashes to txids and files missing from it are left out
+    pub fn to_settled(&self, txids: &BTreeMap<String, String>) -> PathManifest {
+        let paths: BTreeMap<String, ManifestPath> = self
+            .paths
+            .iter()
+            .filter_map(|(path, entry)| {
+                let id = txids.get(&entry.id)?.clone();
+                Some((path.clone(), ManifestPath { id }))
+            })
+            .collect();
+        let index = self
+            .index
+            .clone()
+            .filter(|index| paths.contains_key(&index.path));
+        let fallback = self
+            .fallback
+            .as_ref()
+            .and_then(|fallback| txids.get(&fallback.id))
+            .map(|id| ManifestPath { id: id.clone() });
+
+        PathManifest {
+            manifest: SETTLED_MANIFEST_FORMAT.to_string(),
+            version: SETTLED_MANIFEST_VERSION.to_string(),
+            index,
+            fallback,
+            paths,
+        }
+    }
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::server::manifest::{PathManifest, normalize_path};
+    use std::collections::BTreeMap;
+
+    #[test]
+    pub fn test_path_manifest_resolve() {
+        assert_eq!(
+            normalize_path("./site\\css//main.css").unwrap(),
+            "site/css/main.css"
+        );
+        assert_eq!(normalize_path("/index.html").unwrap(), "index.html");
+        assert!(normalize_path("../etc/passwd").is_none());
+        assert!(normalize_path("a/../../b").is_none());
+        assert!(normalize_path("./").is_none());
+
+        let paths = BTreeMap::from([
+            ("index.html".to_string(), "0xindex".to_string()),
+            ("docs/index.html".to_string(), "0xdocs".to_string()),
+            ("docs/a.md".to_string(), "0xa".to_string()),
+            ("404.html".to_string(), "0x404".to_string()),
+        ]);
+        let manifest = PathManifest::new(paths, "index.html", Some("404.html"));
+        assert_eq!(manifest.resolve(""), Some("0xindex"));
+        assert_eq!(manifest.resolve("docs/a.md"), Some("0xa"));
+        assert_eq!(manifest.resolve("docs/"), Some("0xdocs"));
+        assert_eq!(manifest.resolve("missing"), Some("0x404"));
+
+        let json = serde_json::to_string(&manifest).unwrap();
+        assert!(json.starts_with(r#"{"manifest":"load0/paths","version":"0.1.0","index":{"path":"index.html"},"fallback":{"id":"0x404"}"#));
+        assert_eq!(
+            serde_json::from_str::<PathManifest>(&json).unwrap(),
+            manifest
+        );
+
+        let txids = BTreeMap::from([
+            ("0xindex".to_string(), "0xtx1".to_string()),
+            ("0x404".to_string(), "0xtx2".to_string()),
+            ("0xa".to_string(), "0xtx3".to_string()),
+        ]);
+        assert_eq!(manifest.ids(), vec!["0x404", "0xa", "0xdocs", "0xindex"]);
+        let settled = manifest.to_settled(&txids);
+        assert_eq!(settled.manifest, "arweave/paths");
+        assert_eq!(settled.version, "0.2.0");
+        assert_eq!(settled.resolve(""), Some("0xtx1"));
+        assert_eq!(settled.resolve("docs/a.md"), Some("0xtx3"));
+        // docs/index.html has no txid, docs/ falls back
+        assert_eq!(settled.resolve("docs/"), Some("0xtx2"));
+        assert_eq!(settled.paths.len(), 3);
+
+        let bare = PathManifest::new(BTreeMap::new(), "index.html", Some("404.html"));
+        assert!(bare.index.is_none() && bare.fallback.is_none());
+        assert_eq!(bare.resolve(""), None);
+    }
+}
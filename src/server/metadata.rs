@@ -0,0 +1,171 @@
+use crate::orchestrator::db::BundleTag;
+use axum::http::HeaderMap;
+use std::collections::BTreeMap;
+
+pub const META_HEADER_PREFIX: &str = "x-load-meta-";
+pub const TAG_KIND_META: &str = "meta";
+pub const TAG_KIND_TAG: &str = "tag";
+
+const MAX_ATTRIBUTES: usize = 32;
+const MAX_TAGS: usize = 32;
+const MAX_NAME_LEN: usize = 128;
+const MAX_VALUE_LEN: usize = 1024;
+const MAX_FILENAME_LEN: usize = 255;
+
+// user supplied metadata of an upload: ?filename=, ?tags=a,b and
+// x-load-meta-{key} headers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectMetadata {
+    pub filename: String,
+    pub attributes: BTreeMap<String, String>,
+    pub tags: Vec<String>,
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.:/".contains(c))
+}
+
+impl ObjectMetadata {
+    pub fn from_request(
+        filename: Option<&str>,
+        tags: Option<&str>,
+        headers: &HeaderMap,
+    ) -> Result<Self, String> {
+        let filename = filename.unwrap_or("").trim().to_string();
+        if filename.len() > MAX_FILENAME_LEN || filename.chars().any(|c| c.is_control()) {
+            return Err("invalid filename".to_string());
+        }
+
+        let mut attributes = BTreeMap::new();
+        for (name, value) in headers {
+            let Some(key) = name.as_str().strip_prefix(META_HEADER_PREFIX) else {
+                continue;
+            };
+            let value = value
+                .to_str()
+                .map_err(|_| format!("invalid value for {}", name))?
+                .trim();
+            if !is_valid_name(key) || value.len() > MAX_VALUE_LEN {
+                return Err(format!("invalid metadata header {}", name));
+            }
+            attributes.insert(key.to_string(), value.to_string());
+        }
+        if attributes.len() > MAX_ATTRIBUTES {
+            return Err(format!("at most {} metadata headers", MAX_ATTRIBUTES));
+        }
+
+        let mut parsed_tags: Vec<String> = Vec::new();
+        for tag in tags.unwrap_or("").split(',').map(str::trim) {
+            if tag.is_empty() || parsed_tags.iter().any(|t| t == tag) {
+                continue;
+            }
+            if !is_valid_name(tag) {
+                return Err(format!("invalid tag {}", tag));
+            }
+            parsed_tags.push(tag.to_string());
+        }
+        if parsed_tags.len() > MAX_TAGS {
+            return Err(format!("at most {} tags", MAX_TAGS));
+        }
+
+        Ok(Self {
+            filename,
+            attributes,
+            tags: parsed_tags,
+        })
+    }
+
+    // (kind, name, value) rows of the bundle_tags table
+    pub fn to_rows(&self) -> Vec<(String, String, String)> {
+        let attributes = self
+            .attributes
+            .iter()
+            .map(|(k, v)| (TAG_KIND_META.to_string(), k.clone(), v.clone()));
+        let tags = self
+            .tags
+            .iter()
+            .map(|tag| (TAG_KIND_TAG.to_string(), tag.clone(), String::new()));
+        attributes.chain(tags).collect()
+    }
+
+    pub fn from_rows(filename: &str, rows: Vec<(String, String, String)>) -> Self {
+        let mut metadata = Self {
+            filename: filename.to_string(),
+            ..Default::default()
+        };
+        for (kind, name, value) in rows {
+            match kind.as_str() {
+                TAG_KIND_META => {
+                    metadata.attributes.insert(name, value);
+                }
+                TAG_KIND_TAG => metadata.tags.push(name),
+                _ => {}
+            }
+        }
+        metadata
+    }
+
+    pub fn from_bundle_tags(filename: &str, tags: Vec<BundleTag>) -> Self {
+        let rows = tags
+            .into_iter()
+            .map(|tag| (tag.kind, tag.name, tag.value))
+            .collect();
+        Self::from_rows(filename, rows)
+    }
+
+    // name/value tags for the settled 0xbabe2 bundle
+    pub fn bundle_tags(&self, compression: &str) -> Vec<(String, String)> {
+        let mut tags = Vec::new();
+        if !self.filename.is_empty() {
+            tags.push(("File-Name".to_string(), self.filename.clone()));
+        }
+        if !compression.is_empty() {
+            tags.push(("Content-Encoding".to_string(), compression.to_string()));
+        }
+        tags.extend(
+            self.attributes
+                .iter()
+                .map(|(k, v)| (format!("Meta-{}", k), v.clone())),
+        );
+        tags.extend(self.tags.iter().map(|t| ("Tag".to_string(), t.clone())));
+        tags
+    }
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::server::metadata::ObjectMetadata;
+    use axum::http::{HeaderMap, HeaderValue};
+
+    #[test]
+    pub fn test_object_metadata_from_request() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-load-meta-author", HeaderValue::from_static("load0"));
+        headers.insert("x-load-meta-app", HeaderValue::from_static(" relic "));
+        headers.insert("content-type", HeaderValue::from_static("text/plain"));
+
+        let metadata =
+            ObjectMetadata::from_request(Some("notes.txt"), Some("docs, v1,docs"), &headers)
+                .unwrap();
+        assert_eq!(metadata.filename, "notes.txt");
+        assert_eq!(metadata.attributes.len(), 2);
+        assert_eq!(metadata.attributes["app"], "relic");
+        assert_eq!(metadata.tags, vec!["docs", "v1"]);
+
+        let restored = ObjectMetadata::from_rows("notes.txt", metadata.to_rows());
+        assert_eq!(restored, metadata);
+
+        let tags = metadata.bundle_tags("zstd");
+        assert!(tags.contains(&("File-Name".to_string(), "notes.txt".to_string())));
+        assert!(tags.contains(&("Content-Encoding".to_string(), "zstd".to_string())));
+        assert!(tags.contains(&("Meta-author".to_string(), "load0".to_string())));
+        assert!(tags.contains(&("Tag".to_string(), "v1".to_string())));
+
+        assert!(ObjectMetadata::from_request(None, Some("bad tag"), &HeaderMap::new()).is_err());
+        assert!(ObjectMetadata::from_request(Some("a\nb"), None, &HeaderMap::new()).is_err());
+    }
+}
@@ -1,3 +1,14 @@
+pub mod archive;
+pub mod bandwidth;
+pub mod events;
 pub mod handlers;
+pub mod limit_headers;
+pub mod manifest;
+pub mod metadata;
+pub mod presign;
+pub mod quota;
 pub mod rate_limiter;
+pub mod shared_limiter;
 pub mod types;
+pub mod webhooks;
+pub mod whitelist;
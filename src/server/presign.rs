@@ -0,0 +1,147 @@
+use crate::server::types::DownloadQuery;
+use crate::utils::hash::{hmac_sha256_hex, verify_hmac_sha256_hex};
+use std::net::IpAddr;
+
+pub const DEFAULT_SIGNED_URL_TTL_SECS: u64 = 3600;
+pub const MAX_SIGNED_URL_TTL_SECS: u64 = 7 * 86_400;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignedUrlError {
+    Missing,
+    Expired,
+    IpMismatch,
+    InvalidSignature,
+}
+
+impl SignedUrlError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            SignedUrlError::Missing => "This object is private, a signed URL is required",
+            SignedUrlError::Expired => "Signed URL has expired",
+            SignedUrlError::IpMismatch => "Signed URL was issued for another IP address",
+            SignedUrlError::InvalidSignature => "Invalid signed URL",
+        }
+    }
+}
+
+// the ip is part of the payload even when empty so an unbound signature can't
+// be reused with an ip added, or the other way around
+fn signed_payload(optimistic_hash: &str, expires: u64, ip: Option<&str>) -> String {
+    format!("{}:{}:{}", optimistic_hash, expires, ip.unwrap_or(""))
+}
+
+pub fn sign_download(
+    secret: &[u8],
+    optimistic_hash: &str,
+    expires: u64,
+    ip: Option<&str>,
+) -> String {
+    hmac_sha256_hex(secret, &signed_payload(optimistic_hash, expires, ip))
+}
+
+pub fn signed_download_path(
+    secret: &[u8],
+    optimistic_hash: &str,
+    expires: u64,
+    ip: Option<&str>,
+) -> String {
+    let signature = sign_download(secret, optimistic_hash, expires, ip);
+    match ip {
+        Some(ip) => format!(
+            "/download/{}?expires={}&ip={}&signature={}",
+            optimistic_hash, expires, ip, signature
+        ),
+        None => format!(
+            "/download/{}?expires={}&signature={}",
+            optimistic_hash, expires, signature
+        ),
+    }
+}
+
+pub fn verify_download(
+    secret: &[u8],
+    optimistic_hash: &str,
+    query: &DownloadQuery,
+    client_ip: IpAddr,
+    now: u64,
+) -> Result<(), SignedUrlError> {
+    let (Some(expires), Some(signature)) = (query.expires, query.signature.as_deref()) else {
+        return Err(SignedUrlError::Missing);
+    };
+
+    let ip = query.ip.as_deref();
+    if !verify_hmac_sha256_hex(
+        secret,
+        &signed_payload(optimistic_hash, expires, ip),
+        signature,
+    ) {
+        return Err(SignedUrlError::InvalidSignature);
+    }
+
+    if expires <= now {
+        return Err(SignedUrlError::Expired);
+    }
+
+    if let Some(ip) = ip
+        && ip.parse::<IpAddr>().ok() != Some(client_ip)
+    {
+        return Err(SignedUrlError::IpMismatch);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::server::presign::{SignedUrlError, sign_download, verify_download};
+    use crate::server::types::DownloadQuery;
+    use std::net::IpAddr;
+
+    #[test]
+    pub fn test_verify_download() {
+        let secret = b"secret";
+        let hash = "0xabc";
+        let client: IpAddr = "203.0.113.9".parse().unwrap();
+        let now = 1_000;
+
+        let query = DownloadQuery {
+            expires: Some(now + 60),
+            ip: None,
+            signature: Some(sign_download(secret, hash, now + 60, None)),
+        };
+        assert_eq!(verify_download(secret, hash, &query, client, now), Ok(()));
+        assert_eq!(
+            verify_download(secret, "0xdef", &query, client, now),
+            Err(SignedUrlError::InvalidSignature)
+        );
+        assert_eq!(
+            verify_download(secret, hash, &query, client, now + 60),
+            Err(SignedUrlError::Expired)
+        );
+
+        // binding an ip can't be added to or stripped from a signature
+        let with_ip = DownloadQuery {
+            ip: Some("203.0.113.9".to_string()),
+            ..query.clone()
+        };
+        assert_eq!(
+            verify_download(secret, hash, &with_ip, client, now),
+            Err(SignedUrlError::InvalidSignature)
+        );
+
+        let bound = DownloadQuery {
+            signature: Some(sign_download(secret, hash, now + 60, Some("203.0.113.9"))),
+            ..with_ip
+        };
+        assert_eq!(verify_download(secret, hash, &bound, client, now), Ok(()));
+        assert_eq!(
+            verify_download(secret, hash, &bound, "198.51.100.1".parse().unwrap(), now),
+            Err(SignedUrlError::IpMismatch)
+        );
+
+        assert_eq!(
+            verify_download(secret, hash, &DownloadQuery::default(), client, now),
+            Err(SignedUrlError::Missing)
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use crate::auth::provider::StorageQuota;
+use crate::orchestrator::db::KeyUsage;
+use axum::http::StatusCode;
+
+pub const SECONDS_PER_DAY: u64 = 86_400;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuotaExceeded {
+    BytesPerDay,
+    TotalBytes,
+    Objects,
+}
+
+impl QuotaExceeded {
+    // the daily budget resets on its own, the lifetime limits do not
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            QuotaExceeded::BytesPerDay => StatusCode::TOO_MANY_REQUESTS,
+            QuotaExceeded::TotalBytes | QuotaExceeded::Objects => StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+
+    pub fn message(&self, quota: &StorageQuota) -> String {
+        match self {
+            QuotaExceeded::BytesPerDay => {
+                format!(
+                    "Daily upload quota of {} bytes exceeded",
+                    quota.bytes_per_day
+                )
+            }
+            QuotaExceeded::TotalBytes => {
+                format!(
+                    "Total storage quota of {} bytes exceeded",
+                    quota.total_bytes
+                )
+            }
+            QuotaExceeded::Objects => format!("Object quota of {} objects exceeded", quota.objects),
+        }
+    }
+}
+
+pub fn day_start(now: u64) -> u64 {
+    now - now % SECONDS_PER_DAY
+}
+
+pub fn seconds_until_next_day(now: u64) -> u64 {
+    day_start(now) + SECONDS_PER_DAY - now
+}
+
+// checks whether storing one more object of `incoming_bytes` fits in the quota
+pub fn check_quota(
+    quota: &StorageQuota,
+    usage: &KeyUsage,
+    incoming_bytes: u64,
+) -> Result<(), QuotaExceeded> {
+    if quota.objects != 0 && usage.objects_count + 1 > quota.objects {
+        return Err(QuotaExceeded::Objects);
+    }
+
+    if quota.total_bytes != 0 && usage.total_bytes + incoming_bytes > quota.total_bytes {
+        return Err(QuotaExceeded::TotalBytes);
+    }
+
+    if quota.bytes_per_day != 0 && usage.bytes_today + incoming_bytes > quota.bytes_per_day {
+        return Err(QuotaExceeded::BytesPerDay);
+    }
+
+    Ok(())
+}
+
+// bytes that can still be uploaded before a byte quota trips, None if unlimited
+pub fn remaining_bytes(quota: &StorageQuota, usage: &KeyUsage) -> Option<u64> {
+    let daily =
+        (quota.bytes_per_day != 0).then(|| quota.bytes_per_day.saturating_sub(usage.bytes_today));
+    let total =
+        (quota.total_bytes != 0).then(|| quota.total_bytes.saturating_sub(usage.total_bytes));
+
+    match (daily, total) {
+        (Some(daily), Some(total)) => Some(daily.min(total)),
+        (daily, total) => daily.or(total),
+    }
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::auth::provider::StorageQuota;
+    use crate::orchestrator::db::KeyUsage;
+    use crate::server::quota::{QuotaExceeded, check_quota, seconds_until_next_day};
+
+    #[test]
+    pub fn test_check_quota() {
+        let usage = KeyUsage {
+            total_bytes: 900,
+            bytes_today: 400,
+            objects_count: 9,
+            ..Default::default()
+        };
+
+        assert!(check_quota(&StorageQuota::default(), &usage, u64::MAX / 2).is_ok());
+
+        let quota = StorageQuota {
+            bytes_per_day: 500,
+            total_bytes: 2000,
+            objects: 10,
+        };
+        assert!(check_quota(&quota, &usage, 100).is_ok());
+        assert_eq!(
+            check_quota(&quota, &usage, 101),
+            Err(QuotaExceeded::BytesPerDay)
+        );
+
+        let quota = StorageQuota {
+            total_bytes: 950,
+            ..quota
+        };
+        assert_eq!(
+            check_quota(&quota, &usage, 100),
+            Err(QuotaExceeded::TotalBytes)
+        );
+
+        let quota = StorageQuota {
+            objects: 9,
+            ..quota
+        };
+        assert_eq!(check_quota(&quota, &usage, 1), Err(QuotaExceeded::Objects));
+
+        assert_eq!(seconds_until_next_day(86_400 * 3 + 100), 86_300);
+    }
+}
@@ -1,13 +1,145 @@
+use crate::r#static::{IPV6_BUCKET_PREFIX, TRUSTED_PROXIES};
 use crate::utils::get_env::env_var_to_vec;
 use crate::utils::urls::to_url;
 use axum::extract::ConnectInfo;
-use axum::http::Request;
-use std::net::SocketAddr;
+use axum::http::{HeaderMap, Request};
+use ipnet::{IpNet, Ipv6Net};
+use std::net::{IpAddr, SocketAddr};
 use tower_governor::GovernorError;
 use tower_governor::key_extractor::KeyExtractor;
 
 pub const LOAD_HEADER_NAME: &str = "X-Load-Authorization";
 
+pub fn trusted_proxies() -> Vec<IpNet> {
+    env_var_to_vec("TRUSTED_PROXIES")
+        .iter()
+        .map(|cidr| {
+            cidr.parse::<IpNet>()
+                .or_else(|_| cidr.parse::<IpAddr>().map(IpNet::from))
+                .unwrap_or_else(|e| panic!("Invalid TRUSTED_PROXIES entry {}: {}", cidr, e))
+        })
+        .collect()
+}
+
+fn is_trusted(ip: &IpAddr, trusted_proxies: &[IpNet]) -> bool {
+    trusted_proxies.iter().any(|net| net.contains(ip))
+}
+
+fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    }
+}
+
+// accepts `1.2.3.4`, `1.2.3.4:80`, `2001:db8::1`, `[2001:db8::1]:80` and quoted forms
+fn parse_forwarded_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Some(rest) = value.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok().map(canonical_ip);
+    }
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Some(canonical_ip(ip));
+    }
+    let (host, _port) = value.rsplit_once(':')?;
+    host.parse().ok().map(canonical_ip)
+}
+
+// hop addresses from the client towards us, None entries are unparsable hops
+// such as `unknown` or obfuscated identifiers
+fn forwarded_hops(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    let forwarded: Vec<&str> = headers
+        .get_all("forwarded")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .collect();
+    if !forwarded.is_empty() {
+        let hops = forwarded
+            .iter()
+            .flat_map(|h| h.split(','))
+            .map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (key, value) = pair.split_once('=')?;
+                    key.trim()
+                        .eq_ignore_ascii_case("for")
+                        .then(|| parse_forwarded_ip(value))?
+                })
+            })
+            .collect();
+        return Some(hops);
+    }
+
+    let x_forwarded_for: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .collect();
+    if !x_forwarded_for.is_empty() {
+        let hops = x_forwarded_for
+            .iter()
+            .flat_map(|h| h.split(','))
+            .map(parse_forwarded_ip)
+            .collect();
+        return Some(hops);
+    }
+
+    let x_real_ip = headers.get("x-real-ip")?.to_str().ok()?;
+    Some(vec![parse_forwarded_ip(x_real_ip)])
+}
+
+// forwarding headers are only honoured when the direct peer is a trusted
+// proxy, the chain is then walked from the right skipping trusted hops so a
+// client can't prepend a spoofed address
+pub fn resolve_client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let peer = canonical_ip(peer);
+    if !is_trusted(&peer, trusted_proxies) {
+        return peer;
+    }
+
+    let hops = match forwarded_hops(headers) {
+        Some(hops) => hops,
+        None => return peer,
+    };
+
+    let mut client = peer;
+    for hop in hops.into_iter().rev() {
+        match hop {
+            Some(ip) if is_trusted(&ip, trusted_proxies) => client = ip,
+            Some(ip) => return ip,
+            None => return client,
+        }
+    }
+    client
+}
+
+// IPv6 clients usually get a whole /64, so they are limited per prefix
+pub fn client_bucket(ip: IpAddr, ipv6_prefix: u8) -> String {
+    match ip {
+        IpAddr::V4(v4) => v4.to_string(),
+        IpAddr::V6(v6) => match Ipv6Net::new(v6, ipv6_prefix.min(128)) {
+            Ok(net) => net.trunc().to_string(),
+            Err(_) => v6.to_string(),
+        },
+    }
+}
+
+// set by the tier dispatch once the X-Load-Authorization key is verified,
+// requests without it are limited per client IP whatever header they carry,
+// so made up keys don't get fresh buckets
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitKey(pub String);
+
+impl RateLimitKey {
+    pub fn internal() -> Self {
+        Self("internal".to_string())
+    }
+
+    // key ids are never IPs, the prefix keeps them apart anyway
+    pub fn for_key(key_id: &str) -> Self {
+        Self(format!("key:{}", key_id))
+    }
+}
+
 #[derive(Clone)]
 pub struct XLoadAuthHeaderExtractor;
 
@@ -15,20 +147,14 @@ impl KeyExtractor for XLoadAuthHeaderExtractor {
     type Key = String;
 
     fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
+        if let Some(RateLimitKey(key)) = req.extensions().get::<RateLimitKey>() {
+            return Ok(key.clone());
+        }
+
         let ip = req.extensions().get::<ConnectInfo<SocketAddr>>();
         if let Some(ip) = ip {
-            let headers = req.headers();
-
-            match headers.get(LOAD_HEADER_NAME) {
-                Some(res) => {
-                    let res = res
-                        .to_str()
-                        .map_err(|_| GovernorError::UnableToExtractKey)?;
-
-                    Ok(res.to_owned())
-                }
-                None => Ok(ip.0.ip().to_string()),
-            }
+            let ip = resolve_client_ip(ip.0.ip(), req.headers(), &TRUSTED_PROXIES);
+            Ok(client_bucket(ip, *IPV6_BUCKET_PREFIX))
         } else {
             Err(GovernorError::UnableToExtractKey)
         }
@@ -39,6 +165,18 @@ pub fn whitelisted_urls() -> Vec<String> {
     env_var_to_vec("WHITELISTED_HOSTS")
 }
 
+// `*.load.network` matches any subdomain of load.network but not load.network itself
+pub fn host_matches(host: &str, pattern: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+        None => host == pattern,
+    }
+}
+
 pub fn is_whitelisted(host: Option<String>, whitelisted_domains: &Vec<String>) -> bool {
     match host {
         None => false,
@@ -46,8 +184,9 @@ pub fn is_whitelisted(host: Option<String>, whitelisted_domains: &Vec<String>) -
             let url = to_url(host);
             if let Ok(url) = url {
                 let host = url.host_str().unwrap_or("");
-                let host_string = String::from(host);
-                whitelisted_domains.contains(&host_string)
+                whitelisted_domains
+                    .iter()
+                    .any(|pattern| host_matches(host, pattern))
             } else {
                 false
             }
@@ -57,7 +196,128 @@ pub fn is_whitelisted(host: Option<String>, whitelisted_domains: &Vec<String>) -
 
 #[cfg(test)]
 mod cfg_tests {
-    use crate::server::rate_limiter::{is_whitelisted, whitelisted_urls};
+    use crate::server::rate_limiter::{
+        LOAD_HEADER_NAME, RateLimitKey, XLoadAuthHeaderExtractor, client_bucket, is_whitelisted,
+        resolve_client_ip, whitelisted_urls,
+    };
+    use axum::extract::ConnectInfo;
+    use axum::http::{HeaderMap, HeaderValue, Request};
+    use ipnet::IpNet;
+    use std::net::{IpAddr, SocketAddr};
+    use tower_governor::key_extractor::KeyExtractor;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    pub fn test_resolve_client_ip() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()];
+        let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+        let untrusted_peer: IpAddr = "203.0.113.9".parse().unwrap();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        // spoofed headers from an untrusted peer are ignored
+        let spoofed = headers(&[
+            ("x-forwarded-for", "1.1.1.1"),
+            ("x-real-ip", "1.1.1.1"),
+            ("forwarded", "for=1.1.1.1"),
+        ]);
+        assert_eq!(
+            resolve_client_ip(untrusted_peer, &spoofed, &trusted),
+            untrusted_peer
+        );
+        assert_eq!(
+            resolve_client_ip(untrusted_peer, &spoofed, &[]),
+            untrusted_peer
+        );
+
+        // a client prepending its own X-Forwarded-For entry can't pick its address
+        let chain = headers(&[("x-forwarded-for", "1.1.1.1, 198.51.100.7, 10.0.0.5")]);
+        assert_eq!(
+            resolve_client_ip(proxy, &chain, &trusted),
+            ip("198.51.100.7")
+        );
+
+        let multiple = headers(&[
+            ("x-forwarded-for", "1.1.1.1"),
+            ("x-forwarded-for", "198.51.100.7:5555"),
+        ]);
+        assert_eq!(
+            resolve_client_ip(proxy, &multiple, &trusted),
+            ip("198.51.100.7")
+        );
+
+        let forwarded = headers(&[(
+            "forwarded",
+            "for=1.1.1.1, for=\"[2001:db8:cafe::17]:4711\";proto=https, for=10.1.1.1",
+        )]);
+        assert_eq!(
+            resolve_client_ip(proxy, &forwarded, &trusted),
+            ip("2001:db8:cafe::17")
+        );
+
+        let obfuscated = headers(&[("forwarded", "for=198.51.100.7, for=_hidden, for=10.1.1.1")]);
+        assert_eq!(
+            resolve_client_ip(proxy, &obfuscated, &trusted),
+            ip("10.1.1.1")
+        );
+
+        let real_ip = headers(&[("x-real-ip", "198.51.100.7")]);
+        assert_eq!(
+            resolve_client_ip(proxy, &real_ip, &trusted),
+            ip("198.51.100.7")
+        );
+        assert_eq!(resolve_client_ip(proxy, &HeaderMap::new(), &trusted), proxy);
+
+        let mapped = headers(&[("x-forwarded-for", "::ffff:198.51.100.7")]);
+        assert_eq!(
+            resolve_client_ip(ip("fd00::1"), &mapped, &trusted),
+            ip("198.51.100.7")
+        );
+
+        assert_eq!(client_bucket(ip("198.51.100.7"), 64), "198.51.100.7");
+        assert_eq!(
+            client_bucket(ip("2001:db8:cafe:1:2:3:4:5"), 64),
+            "2001:db8:cafe:1::/64"
+        );
+        assert_eq!(
+            client_bucket(ip("2001:db8:cafe:1:2:3:4:5"), 64),
+            client_bucket(ip("2001:db8:cafe:1:ffff::1"), 64)
+        );
+    }
+
+    #[test]
+    pub fn test_rate_limit_key() {
+        let peer: SocketAddr = "198.51.100.7:4000".parse().unwrap();
+        let request = |key: Option<RateLimitKey>| {
+            let mut request = Request::builder()
+                .header(LOAD_HEADER_NAME, "made-up-key")
+                .extension(ConnectInfo(peer))
+                .body(())
+                .unwrap();
+            if let Some(key) = key {
+                request.extensions_mut().insert(key);
+            }
+            request
+        };
+
+        // an unverified header doesn't pick the bucket
+        assert_eq!(
+            XLoadAuthHeaderExtractor.extract(&request(None)).unwrap(),
+            "198.51.100.7"
+        );
+        assert_eq!(
+            XLoadAuthHeaderExtractor
+                .extract(&request(Some(RateLimitKey::for_key("42"))))
+                .unwrap(),
+            "key:42"
+        );
+    }
 
     #[test]
     pub fn test_whitelisted_function() {
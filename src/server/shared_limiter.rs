@@ -0,0 +1,372 @@
+use crate::server::rate_limiter::XLoadAuthHeaderExtractor;
+use crate::utils::hash::keccak_hex;
+use anyhow::Error;
+use async_trait::async_trait;
+use axum::extract::{Request, State};
+use axum::http::{HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use redis::aio::MultiplexedConnection;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tower_governor::key_extractor::KeyExtractor;
+
+const MAX_MEMORY_WINDOWS: usize = 100_000;
+
+// hits recorded in the current window of a key
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowCount {
+    pub count: u64,
+    pub reset_after: Duration,
+}
+
+// a counter store shared by every load0 replica. A tier with burst B that
+// replenishes one request every P seconds is enforced as B hits per B*P
+// window, which keeps both the burst and the sustained rate of the governor
+// tiers while only needing INCR-style atomic counters
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    async fn hit(&self, key: &str, window: Duration) -> Result<WindowCount, Error>;
+}
+
+// per process fixed windows, used when no shared store is reachable
+#[derive(Default)]
+pub struct MemoryRateLimitStore {
+    windows: Mutex<HashMap<String, (Instant, Duration, u64)>>,
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryRateLimitStore {
+    async fn hit(&self, key: &str, window: Duration) -> Result<WindowCount, Error> {
+        let mut windows = self.windows.lock().unwrap();
+        let now = Instant::now();
+
+        if windows.len() >= MAX_MEMORY_WINDOWS {
+            windows.retain(|_, (started_at, window, _)| now.duration_since(*started_at) < *window);
+        }
+
+        let entry = windows.entry(key.to_string()).or_insert((now, window, 0));
+        if now.duration_since(entry.0) >= entry.1 {
+            *entry = (now, window, 0);
+        }
+        entry.2 += 1;
+
+        Ok(WindowCount {
+            count: entry.2,
+            reset_after: entry.1.saturating_sub(now.duration_since(entry.0)),
+        })
+    }
+}
+
+// any server speaking the Redis protocol (Redis, Valkey, Dragonfly, ...)
+pub struct RedisRateLimitStore {
+    client: redis::Client,
+    conn: tokio::sync::Mutex<Option<MultiplexedConnection>>,
+}
+
+impl RedisRateLimitStore {
+    pub fn new(url: &str) -> Result<Self, Error> {
+        Ok(Self {
+            client: redis::Client::open(url)?,
+            conn: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    async fn connection(&self) -> Result<MultiplexedConnection, Error> {
+        let mut conn = self.conn.lock().await;
+        if let Some(conn) = conn.as_ref() {
+            return Ok(conn.clone());
+        }
+
+        let connected = self.client.get_multiplexed_async_connection().await?;
+        *conn = Some(connected.clone());
+        Ok(connected)
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for RedisRateLimitStore {
+    async fn hit(&self, key: &str, window: Duration) -> Result<WindowCount, Error> {
+        let mut conn = self.connection().await?;
+
+        let result: Result<(u64, i64), redis::RedisError> = redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(key)
+            .arg(0)
+            .arg("PX")
+            .arg(window.as_millis().max(1) as u64)
+            .arg("NX")
+            .ignore()
+            .cmd("INCR")
+            .arg(key)
+            .cmd("PTTL")
+            .arg(key)
+            .query_async(&mut conn)
+            .await;
+
+        match result {
+            Ok((count, pttl)) => Ok(WindowCount {
+                count,
+                reset_after: Duration::from_millis(pttl.max(0) as u64),
+            }),
+            Err(e) => {
+                // drop the connection so the next hit reconnects
+                *self.conn.lock().await = None;
+                Err(e.into())
+            }
+        }
+    }
+}
+
+// falls back to per process counters while the shared store is unavailable,
+// during an outage each replica enforces the full tier limit on its own
+pub struct FallbackRateLimitStore {
+    primary: Arc<dyn RateLimitStore>,
+    fallback: MemoryRateLimitStore,
+}
+
+impl FallbackRateLimitStore {
+    pub fn new(primary: Arc<dyn RateLimitStore>) -> Self {
+        Self {
+            primary,
+            fallback: MemoryRateLimitStore::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for FallbackRateLimitStore {
+    async fn hit(&self, key: &str, window: Duration) -> Result<WindowCount, Error> {
+        match self.primary.hit(key, window).await {
+            Ok(count) => Ok(count),
+            Err(e) => {
+                println!(
+                    "Shared rate limit store unavailable, using local counters: {}",
+                    e
+                );
+                self.fallback.hit(key, window).await
+            }
+        }
+    }
+}
+
+// RATE_LIMIT_REDIS_URL enables cluster wide limits, e.g. redis://10.0.0.5:6379/0
+pub fn init_rate_limit_store() -> Option<Arc<dyn RateLimitStore>> {
+    let url = match std::env::var("RATE_LIMIT_REDIS_URL") {
+        Ok(url) if !url.trim().is_empty() => url,
+        _ => return None,
+    };
+
+    let redis_store = RedisRateLimitStore::new(&url)
+        .unwrap_or_else(|e| panic!("Invalid RATE_LIMIT_REDIS_URL: {}", e));
+    Some(Arc::new(FallbackRateLimitStore::new(Arc::new(redis_store))))
+}
+
+#[derive(Clone)]
+pub struct SharedRateLimit {
+    pub store: Arc<dyn RateLimitStore>,
+    pub namespace: String,
+    pub burst_size: u32,
+    pub replenish_secs: u64,
+}
+
+impl SharedRateLimit {
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.replenish_secs.max(1)).saturating_mul(self.burst_size.max(1))
+    }
+}
+
+// mirrors the headers GovernorLayer sends with `use_headers()`
+pub async fn shared_rate_limit(
+    State(limit): State<SharedRateLimit>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let key = match XLoadAuthHeaderExtractor.extract(&req) {
+        Ok(key) => key,
+        Err(_) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Couldn't find the key").into_response();
+        }
+    };
+
+    // keys can be API keys, they never reach redis (or its logs) in plaintext
+    let store_key = format!(
+        "load0:rl:{}:{}",
+        limit.namespace,
+        keccak_hex(key.as_bytes())
+    );
+    let window = match limit.store.hit(&store_key, limit.window()).await {
+        Ok(window) => window,
+        Err(e) => {
+            // fail open, the fallback store only errors on programming mistakes
+            println!("Error checking shared rate limit: {:?}", e);
+            return next.run(req).await;
+        }
+    };
+
+    let burst_size = limit.burst_size as u64;
+    if window.count > burst_size {
+        let wait_secs = window.reset_after.as_secs().max(1);
+        let mut response = (
+            StatusCode::TOO_MANY_REQUESTS,
+            format!("Too Many Requests! Wait for {}s", wait_secs),
+        )
+            .into_response();
+        let headers = response.headers_mut();
+        headers.insert("x-ratelimit-after", HeaderValue::from(wait_secs));
+        headers.insert("retry-after", HeaderValue::from(wait_secs));
+        return response;
+    }
+
+    let mut response = next.run(req).await;
+    let headers = response.headers_mut();
+    headers.insert("x-ratelimit-limit", HeaderValue::from(burst_size));
+    headers.insert(
+        "x-ratelimit-remaining",
+        HeaderValue::from(burst_size - window.count),
+    );
+    headers.insert(
+        "ratelimit-reset",
+        HeaderValue::from(window.reset_after.as_secs()),
+    );
+    response
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::server::shared_limiter::{
+        FallbackRateLimitStore, RateLimitStore, RedisRateLimitStore,
+    };
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+
+    type Keys = Arc<Mutex<HashMap<String, (i64, Instant)>>>;
+
+    async fn read_command(reader: &mut BufReader<TcpStream>) -> Option<Vec<String>> {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await.ok()? == 0 {
+            return None;
+        }
+        let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            line.clear();
+            reader.read_line(&mut line).await.ok()?;
+            let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+            let mut buf = vec![0u8; len + 2];
+            reader.read_exact(&mut buf).await.ok()?;
+            buf.truncate(len);
+            args.push(String::from_utf8(buf).ok()?);
+        }
+        Some(args)
+    }
+
+    fn execute(keys: &Keys, args: &[String]) -> String {
+        let mut keys = keys.lock().unwrap();
+        let now = Instant::now();
+        keys.retain(|_, (_, expires_at)| *expires_at > now);
+
+        match args[0].to_uppercase().as_str() {
+            "SET" => {
+                let ttl = Duration::from_millis(args[4].parse().unwrap());
+                if keys.contains_key(&args[1]) {
+                    "$-1\r\n".to_string()
+                } else {
+                    keys.insert(args[1].clone(), (args[2].parse().unwrap(), now + ttl));
+                    "+OK\r\n".to_string()
+                }
+            }
+            "INCR" => {
+                let entry = keys
+                    .entry(args[1].clone())
+                    .or_insert((0, now + Duration::from_secs(3600)));
+                entry.0 += 1;
+                format!(":{}\r\n", entry.0)
+            }
+            "PTTL" => match keys.get(&args[1]) {
+                Some((_, expires_at)) => format!(":{}\r\n", (*expires_at - now).as_millis()),
+                None => ":-2\r\n".to_string(),
+            },
+            _ => "-ERR unknown command\r\n".to_string(),
+        }
+    }
+
+    // just enough of the Redis protocol for the commands the store sends
+    async fn spawn_redis_stand_in() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let keys: Keys = Arc::default();
+
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let keys = keys.clone();
+                tokio::spawn(async move {
+                    let mut reader = BufReader::new(socket);
+                    let mut queued: Option<Vec<Vec<String>>> = None;
+                    while let Some(args) = read_command(&mut reader).await {
+                        let reply = match (args[0].to_uppercase().as_str(), queued.as_mut()) {
+                            ("MULTI", _) => {
+                                queued = Some(Vec::new());
+                                "+OK\r\n".to_string()
+                            }
+                            ("EXEC", Some(_)) => {
+                                let commands = queued.take().unwrap();
+                                let replies: Vec<String> =
+                                    commands.iter().map(|c| execute(&keys, c)).collect();
+                                format!("*{}\r\n{}", replies.len(), replies.concat())
+                            }
+                            (_, Some(commands)) => {
+                                commands.push(args);
+                                "+QUEUED\r\n".to_string()
+                            }
+                            (_, None) => execute(&keys, &args),
+                        };
+                        if reader.get_mut().write_all(reply.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        format!("redis://{}", addr)
+    }
+
+    #[tokio::test]
+    pub async fn test_redis_store_is_shared_across_replicas() {
+        let url = spawn_redis_stand_in().await;
+        let replica_a = RedisRateLimitStore::new(&url).unwrap();
+        let replica_b = RedisRateLimitStore::new(&url).unwrap();
+        let window = Duration::from_secs(60);
+
+        assert_eq!(replica_a.hit("tier:ip", window).await.unwrap().count, 1);
+        assert_eq!(replica_b.hit("tier:ip", window).await.unwrap().count, 2);
+        let count = replica_a.hit("tier:ip", window).await.unwrap();
+        assert_eq!(count.count, 3);
+        assert!(count.reset_after <= window && count.reset_after > Duration::from_secs(50));
+
+        assert_eq!(replica_b.hit("tier:other", window).await.unwrap().count, 1);
+    }
+
+    #[tokio::test]
+    pub async fn test_fallback_store_when_redis_is_down() {
+        // nothing listens on this port once the listener is dropped
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let redis_store = RedisRateLimitStore::new(&format!("redis://{}", addr)).unwrap();
+        let store = FallbackRateLimitStore::new(Arc::new(redis_store));
+        let window = Duration::from_secs(60);
+
+        assert_eq!(store.hit("tier:ip", window).await.unwrap().count, 1);
+        assert_eq!(store.hit("tier:ip", window).await.unwrap().count, 2);
+    }
+}
@@ -1,17 +1,98 @@
+use crate::auth::provider::{AuthProvider, StorageQuota};
+use crate::orchestrator::db::{KeyUsage, OwnerSettlementCosts, Webhook, WebhookDelivery};
+use crate::orchestrator::stats::{StatsCache, StatsSnapshot};
+use crate::server::shared_limiter::RateLimitStore;
+use crate::utils::encryption::KeyRing;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct AppState {
     pub http_client: Client,
     pub supabase_url: String,
     pub bucket_name: String,
+    // a non-public bucket for private uploads, empty when they're disabled
+    pub private_bucket_name: String,
     pub api_key: String,
+    pub auth: Arc<dyn AuthProvider>,
+    pub rate_limit_store: Option<Arc<dyn RateLimitStore>>,
+    pub key_ring: Arc<KeyRing>,
+    pub stats_cache: Arc<StatsCache<StatsSnapshot>>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct UploadQuery {
     pub content_type: Option<String>,
+    pub private: Option<bool>,
+    pub encryption: Option<String>,
+    pub compression: Option<String>,
+    pub filename: Option<String>,
+    // comma separated
+    pub tags: Option<String>,
+    // a tar/zip archive or multipart body stored as a path manifest
+    pub directory: Option<bool>,
+    pub index: Option<String>,
+    pub fallback: Option<String>,
+    // receives the webhook events of this upload
+    pub callback_url: Option<String>,
+    // "ephemeral", "standard" (the default) or "permanent-now"
+    pub storage_class: Option<String>,
+    // seconds an ephemeral object is kept
+    pub ttl: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct DownloadQuery {
+    pub expires: Option<u64>,
+    pub ip: Option<String>,
+    pub signature: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SignBundleRequest {
+    pub expires_in: Option<u64>,
+    pub ip: Option<String>,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct SignBundleResponse {
+    pub optimistic_hash: String,
+    pub url: String,
+    pub expires_at: u64,
+    pub ip: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct EstimateQuery {
+    pub size: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct EventsQuery {
+    pub owner: Option<String>,
+    pub optimistic_hash: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ListBundlesQuery {
+    pub owner: Option<String>,
+    pub limit: Option<u32>,
+    pub tag: Option<String>,
+    // `{key}:{value}` of a x-load-meta-{key} header
+    pub meta: Option<String>,
+    // prefix, e.g. `image/`
+    pub content_type: Option<String>,
+    pub settled: Option<bool>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    // unix seconds, created_after inclusive and created_before exclusive
+    pub created_after: Option<u64>,
+    pub created_before: Option<u64>,
+    pub sort: Option<String>,
+    pub order: Option<String>,
+    pub cursor: Option<String>,
 }
 
 // Response structure
@@ -21,3 +102,99 @@ pub struct UploadResponse {
     pub message: String,
     pub optimistic_hash: Option<String>,
 }
+
+// `optimistic_hash` is the hash of the manifest, `paths` maps each path of the
+// directory to the optimistic hash of its file
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DirectoryUploadResponse {
+    pub success: bool,
+    pub message: String,
+    pub optimistic_hash: Option<String>,
+    pub paths: BTreeMap<String, String>,
+}
+
+// returned by a multipart upload when some of its files failed, `results`
+// follows the order of the file parts
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MultipartUploadReport {
+    pub success: bool,
+    pub uploaded: usize,
+    pub failed: usize,
+    pub results: Vec<UploadResponse>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CreateApiKeyRequest {
+    pub owner: String,
+    pub scopes: Option<Vec<String>>,
+    pub expires_at: Option<u64>,
+    pub quota: Option<StorageQuota>,
+}
+
+// the plaintext api_key is only returned once, at creation time
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct CreateApiKeyResponse {
+    pub key_id: u32,
+    pub api_key: String,
+    pub owner: String,
+    pub scopes: Vec<String>,
+    pub expires_at: u64,
+    pub quota: StorageQuota,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct CreateOriginTokenRequest {
+    pub subject: String,
+    pub ttl_secs: u64,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct CreateOriginTokenResponse {
+    pub token: String,
+    pub subject: String,
+    pub expires_at: u64,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct UsageResponse {
+    pub key_id: String,
+    pub owner: String,
+    pub quota: StorageQuota,
+    pub usage: KeyUsage,
+    // of every key of the owner
+    pub settlement_costs: OwnerSettlementCosts,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    // every event when omitted
+    pub events: Option<Vec<String>>,
+}
+
+// the signing_secret is shared by all the webhooks of the owner, it is only
+// returned when the first webhook of the owner creates it
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct WebhooksResponse {
+    pub owner: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signing_secret: Option<String>,
+    pub webhooks: Vec<Webhook>,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct WebhookSecretResponse {
+    pub owner: String,
+    pub signing_secret: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct WebhookDeliveriesQuery {
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct WebhookDeliveriesResponse {
+    pub webhook_id: u32,
+    pub deliveries: Vec<WebhookDelivery>,
+}
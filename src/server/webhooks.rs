@@ -0,0 +1,525 @@
+use crate::auth::provider::unix_now;
+use crate::orchestrator::db::{
+    Bundle, Webhook, WebhookDelivery, get_bundle_webhooks, get_webhook_secret,
+    insert_webhook_delivery, insert_webhook_secret, upsert_webhook_secret,
+};
+use crate::r#static::{WEBHOOK_ALLOW_PRIVATE_URLS, WEBHOOK_SIGNING_SECRET};
+use crate::utils::hash::{generate_pseudorandom_keccak_hash, hmac_sha256_hex};
+use hyper::client::connect::dns::Name;
+use reqwest::Client;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use url::{Host, Url};
+
+pub const EVENT_UPLOADED: &str = "uploaded";
+pub const EVENT_SETTLED: &str = "settled";
+pub const EVENT_SETTLEMENT_FAILED: &str = "settlement_failed";
+pub const EVENT_EVICTED: &str = "evicted";
+pub const WEBHOOK_EVENTS: [&str; 4] = [
+    EVENT_UPLOADED,
+    EVENT_SETTLED,
+    EVENT_SETTLEMENT_FAILED,
+    EVENT_EVICTED,
+];
+
+pub const EVENT_HEADER: &str = "X-Load-Webhook-Event";
+pub const DELIVERY_HEADER: &str = "X-Load-Webhook-Delivery";
+pub const TIMESTAMP_HEADER: &str = "X-Load-Webhook-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Load-Webhook-Signature";
+
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(2);
+const MAX_URL_LEN: usize = 2048;
+
+// no proxy, so host names are always resolved by PublicResolver
+static WEBHOOK_CLIENT: LazyLock<Client> = LazyLock::new(|| {
+    let builder = Client::builder()
+        .timeout(Duration::from_secs(10))
+        .redirect(reqwest::redirect::Policy::none())
+        .no_proxy();
+    let builder = if *WEBHOOK_ALLOW_PRIVATE_URLS {
+        builder
+    } else {
+        builder.dns_resolver(Arc::new(PublicResolver))
+    };
+    builder.build().unwrap()
+});
+
+// validate_url only sees the host of a url, the addresses a name resolves to
+// are checked on every delivery so a public name can't point at a private IP
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| !is_private_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} only resolves to private addresses", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookBundle {
+    pub optimistic_hash: String,
+    pub bundle_txid: String,
+    pub owner: String,
+    pub content_type: String,
+    pub data_size: u64,
+    pub is_settled: bool,
+}
+
+impl From<&Bundle> for WebhookBundle {
+    fn from(bundle: &Bundle) -> Self {
+        Self {
+            optimistic_hash: bundle.optimistic_hash.clone(),
+            bundle_txid: bundle.bundle_txid.clone(),
+            owner: bundle.owner.clone(),
+            content_type: bundle.content_type.clone(),
+            data_size: bundle.data_size,
+            is_settled: bundle.is_settled,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    pub delivery_id: String,
+    pub event: String,
+    pub created_at: u64,
+    pub bundle: WebhookBundle,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeliveryAttempt {
+    pub attempt: u32,
+    // 0 when the request itself failed
+    pub status_code: u16,
+    pub error: String,
+}
+
+impl DeliveryAttempt {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status_code)
+    }
+}
+
+pub fn is_enabled() -> bool {
+    !WEBHOOK_SIGNING_SECRET.is_empty()
+}
+
+// every webhook of an owner is signed with the same secret, derived from the
+// owner's nonce. an empty nonce is the secret of the owners that registered
+// webhooks before nonces were stored
+pub fn owner_secret(owner: &str, nonce: &str) -> String {
+    let message = if nonce.is_empty() {
+        format!("webhook:{}", owner)
+    } else {
+        format!("webhook:{}:{}", owner, nonce)
+    };
+    hmac_sha256_hex(WEBHOOK_SIGNING_SECRET.as_bytes(), &message)
+}
+
+fn new_nonce() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+pub async fn current_owner_secret(owner: &str) -> Result<String, anyhow::Error> {
+    let nonce = get_webhook_secret(owner)
+        .await?
+        .map(|secret| secret.nonce)
+        .unwrap_or_default();
+    Ok(owner_secret(owner, &nonce))
+}
+
+// the secret of an owner the first time it is issued, None once they already
+// have one. it is never returned again, only replaced by rotate_owner_secret
+pub async fn issue_owner_secret(owner: &str) -> Result<Option<String>, anyhow::Error> {
+    if get_webhook_secret(owner).await?.is_some() {
+        return Ok(None);
+    }
+    let nonce = new_nonce();
+    insert_webhook_secret(owner, &nonce, unix_now()).await?;
+    // a concurrent first call may have won the insert
+    match get_webhook_secret(owner).await? {
+        Some(stored) if stored.nonce == nonce => Ok(Some(owner_secret(owner, &nonce))),
+        _ => Ok(None),
+    }
+}
+
+pub async fn rotate_owner_secret(owner: &str) -> Result<String, anyhow::Error> {
+    let nonce = new_nonce();
+    upsert_webhook_secret(owner, &nonce, unix_now()).await?;
+    Ok(owner_secret(owner, &nonce))
+}
+
+// `sha256=` HMAC of `{timestamp}.{body}`, the timestamp lets receivers reject
+// replayed deliveries
+pub fn sign_payload(secret: &str, timestamp: u64, body: &str) -> String {
+    format!(
+        "sha256={}",
+        hmac_sha256_hex(secret.as_bytes(), &format!("{}.{}", timestamp, body))
+    )
+}
+
+// comma separated events, every event when none are given
+pub fn parse_events(events: Option<&[String]>) -> Result<String, String> {
+    let Some(events) = events.filter(|events| !events.is_empty()) else {
+        return Ok(WEBHOOK_EVENTS.join(","));
+    };
+
+    let mut parsed: Vec<&str> = Vec::new();
+    for event in events {
+        let event = WEBHOOK_EVENTS
+            .iter()
+            .find(|known| **known == event.trim())
+            .ok_or_else(|| format!("unknown event {}", event))?;
+        if !parsed.contains(event) {
+            parsed.push(event);
+        }
+    }
+    Ok(parsed.join(","))
+}
+
+// only http(s), and no loopback or private hosts unless allowed. Host names
+// are resolved when delivering, see PublicResolver
+pub fn validate_url(url: &str, allow_private: bool) -> Result<(), String> {
+    if url.len() > MAX_URL_LEN {
+        return Err("url is too long".to_string());
+    }
+    let parsed = Url::parse(url).map_err(|_| "invalid url".to_string())?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err("url must be http or https".to_string());
+    }
+
+    let is_private = match parsed.host() {
+        None => return Err("url has no host".to_string()),
+        Some(Host::Domain(domain)) => {
+            domain.eq_ignore_ascii_case("localhost") || domain.ends_with(".localhost")
+        }
+        Some(Host::Ipv4(ip)) => is_private_ip(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_private_ip(IpAddr::V6(ip)),
+    };
+    if is_private && !allow_private {
+        return Err("url must not point at a private network".to_string());
+    }
+    Ok(())
+}
+
+// anything that isn't a public unicast address
+fn is_private_ip(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => is_private_ipv4(ip),
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            // NAT64 (64:ff9b::/96) embeds the IPv4 address it reaches
+            let nat64 = segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+                // site-local fec0::/10, deprecated but still routed by some stacks
+                || segments[0] & 0xffc0 == 0xfec0
+                || (nat64 && is_private_ipv4(nat64_ipv4(ip)))
+        }
+    }
+}
+
+fn is_private_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        // "this network" 0.0.0.0/8, CGNAT 100.64.0.0/10, benchmarking
+        // 198.18.0.0/15 and reserved 240.0.0.0/4
+        || a == 0
+        || (a == 100 && b & 0xc0 == 64)
+        || (a == 198 && b & 0xfe == 18)
+        || a >= 240
+}
+
+fn nat64_ipv4(ip: Ipv6Addr) -> Ipv4Addr {
+    let [.., a, b, c, d] = ip.octets();
+    Ipv4Addr::new(a, b, c, d)
+}
+
+pub async fn send_once(
+    client: &Client,
+    url: &str,
+    secret: &str,
+    payload: &WebhookPayload,
+    body: &str,
+    attempt: u32,
+) -> DeliveryAttempt {
+    let timestamp = unix_now();
+    let result = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header(EVENT_HEADER, &payload.event)
+        .header(DELIVERY_HEADER, &payload.delivery_id)
+        .header(TIMESTAMP_HEADER, timestamp.to_string())
+        .header(SIGNATURE_HEADER, sign_payload(secret, timestamp, body))
+        .body(body.to_string())
+        .send()
+        .await;
+
+    match result {
+        Ok(response) if response.status().is_success() => DeliveryAttempt {
+            attempt,
+            status_code: response.status().as_u16(),
+            error: String::new(),
+        },
+        Ok(response) => DeliveryAttempt {
+            attempt,
+            status_code: response.status().as_u16(),
+            error: format!("receiver responded {}", response.status()),
+        },
+        Err(e) => DeliveryAttempt {
+            attempt,
+            status_code: 0,
+            error: e.to_string(),
+        },
+    }
+}
+
+// retries with exponential backoff until a 2xx or `max_attempts`, each
+// attempt is handed to `on_attempt`
+pub async fn send_with_retries<F: FnMut(&DeliveryAttempt)>(
+    client: &Client,
+    url: &str,
+    secret: &str,
+    payload: &WebhookPayload,
+    max_attempts: u32,
+    first_retry_delay: Duration,
+    mut on_attempt: F,
+) -> bool {
+    let body = serde_json::to_string(payload).unwrap_or_default();
+    let mut delay = first_retry_delay;
+
+    for attempt in 1..=max_attempts {
+        let result = send_once(client, url, secret, payload, &body, attempt).await;
+        on_attempt(&result);
+        if result.is_success() {
+            return true;
+        }
+        if attempt < max_attempts {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+    false
+}
+
+// fires `event` to the callbacks of the bundle and the webhooks of the key
+// that uploaded it, deliveries run in the background
+pub async fn notify(
+    event: &'static str,
+    bundle: WebhookBundle,
+    key_id: Option<String>,
+    error: Option<String>,
+) {
+    if !is_enabled() {
+        return;
+    }
+
+    let webhooks = match get_bundle_webhooks(&bundle.optimistic_hash, key_id.as_deref()).await {
+        Ok(webhooks) => webhooks,
+        Err(e) => {
+            println!("Error loading webhooks: {:?}", e);
+            return;
+        }
+    };
+
+    for webhook in webhooks {
+        if !webhook.events.split(',').any(|e| e == event) {
+            continue;
+        }
+        let payload = WebhookPayload {
+            delivery_id: generate_pseudorandom_keccak_hash(),
+            event: event.to_string(),
+            created_at: unix_now(),
+            bundle: bundle.clone(),
+            error: error.clone(),
+        };
+        tokio::spawn(deliver(webhook, payload));
+    }
+}
+
+async fn deliver(webhook: Webhook, payload: WebhookPayload) {
+    let secret = match current_owner_secret(&webhook.owner).await {
+        Ok(secret) => secret,
+        Err(e) => {
+            println!("Error loading webhook secret of {}: {:?}", webhook.owner, e);
+            return;
+        }
+    };
+    let delivered = send_with_retries(
+        &WEBHOOK_CLIENT,
+        &webhook.url,
+        &secret,
+        &payload,
+        MAX_ATTEMPTS,
+        FIRST_RETRY_DELAY,
+        |attempt| {
+            let delivery = WebhookDelivery {
+                id: 0,
+                webhook_id: webhook.id,
+                delivery_id: payload.delivery_id.clone(),
+                event: payload.event.clone(),
+                optimistic_hash: payload.bundle.optimistic_hash.clone(),
+                attempt: attempt.attempt,
+                status_code: attempt.status_code as u32,
+                success: attempt.is_success(),
+                error: attempt.error.chars().take(1024).collect(),
+                created_at: unix_now(),
+            };
+            tokio::spawn(async move {
+                if let Err(e) = insert_webhook_delivery(&delivery).await {
+                    println!("Error logging webhook delivery: {:?}", e);
+                }
+            });
+        },
+    )
+    .await;
+
+    if !delivered {
+        println!(
+            "Webhook {} delivery {} failed after {} attempts",
+            webhook.id, payload.delivery_id, MAX_ATTEMPTS
+        );
+    }
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::server::webhooks::{
+        PublicResolver, SIGNATURE_HEADER, TIMESTAMP_HEADER, WebhookBundle, WebhookPayload,
+        owner_secret, parse_events, send_with_retries, sign_payload, validate_url,
+    };
+    use axum::Router;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use reqwest::dns::Resolve;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_owner_secret() {
+        let legacy = owner_secret("team", "");
+        assert_eq!(legacy, owner_secret("team", ""));
+        assert_ne!(legacy, owner_secret("team", "nonce"));
+        assert_ne!(owner_secret("team", "nonce"), owner_secret("team", "other"));
+        assert_ne!(
+            owner_secret("team", "nonce"),
+            owner_secret("other", "nonce")
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_webhook_delivery() {
+        assert_eq!(
+            parse_events(None).unwrap(),
+            "uploaded,settled,settlement_failed,evicted"
+        );
+        let events = vec!["settled".to_string(), "settled".to_string()];
+        assert_eq!(parse_events(Some(&events)).unwrap(), "settled");
+        assert!(parse_events(Some(&["deleted".to_string()])).is_err());
+
+        assert!(validate_url("https://example.com/hook", false).is_ok());
+        assert!(validate_url("ftp://example.com/hook", false).is_err());
+        assert!(validate_url("http://127.0.0.1:8080/hook", false).is_err());
+        assert!(validate_url("http://[::ffff:10.0.0.1]/hook", false).is_err());
+        assert!(validate_url("http://localhost/hook", true).is_ok());
+        for url in [
+            "http://100.64.0.1/hook",
+            "http://0.1.2.3/hook",
+            "http://169.254.169.254/latest",
+            "http://[fec0::1]/hook",
+            "http://[64:ff9b::a9fe:a9fe]/hook",
+        ] {
+            assert!(validate_url(url, false).is_err(), "{}", url);
+        }
+        assert!(validate_url("http://100.128.0.1/hook", false).is_ok());
+        assert!(validate_url("http://[2606:4700::1111]/hook", false).is_ok());
+
+        // names are checked once resolved
+        let resolved = PublicResolver.resolve("localhost".parse().unwrap()).await;
+        assert!(resolved.is_err());
+
+        // a local receiver that fails the first delivery and verifies the
+        // signature of the retry
+        let calls = Arc::new(AtomicUsize::new(0));
+        let received: Arc<Mutex<Vec<bool>>> = Arc::new(Mutex::new(Vec::new()));
+        let app = Router::new().route(
+            "/hook",
+            post({
+                let calls = calls.clone();
+                let received = received.clone();
+                move |headers: HeaderMap, body: String| async move {
+                    let timestamp: u64 =
+                        headers[TIMESTAMP_HEADER].to_str().unwrap().parse().unwrap();
+                    let signature = headers[SIGNATURE_HEADER].to_str().unwrap();
+                    received
+                        .lock()
+                        .unwrap()
+                        .push(signature == sign_payload("secret", timestamp, &body));
+                    if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        StatusCode::OK
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let payload = WebhookPayload {
+            delivery_id: "0x1".to_string(),
+            event: "settled".to_string(),
+            created_at: 1,
+            bundle: WebhookBundle {
+                optimistic_hash: "0xabc".to_string(),
+                bundle_txid: "0xdef".to_string(),
+                owner: "team".to_string(),
+                content_type: "text/plain".to_string(),
+                data_size: 5,
+                is_settled: true,
+            },
+            error: None,
+        };
+        let mut attempts = Vec::new();
+        let delivered = send_with_retries(
+            &reqwest::Client::new(),
+            &format!("http://{}/hook", addr),
+            "secret",
+            &payload,
+            3,
+            Duration::from_millis(10),
+            |attempt| attempts.push(attempt.clone()),
+        )
+        .await;
+
+        assert!(delivered);
+        assert_eq!(attempts.len(), 2);
+        assert_eq!(attempts[0].status_code, 500);
+        assert!(attempts[1].is_success());
+        assert_eq!(*received.lock().unwrap(), vec![true, true]);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::server::rate_limiter::{is_whitelisted, resolve_client_ip, whitelisted_urls};
+use crate::r#static::WHITELIST_TOKEN_SECRET;
+use crate::utils::get_env::env_var_to_vec;
+use crate::utils::hash::{hmac_sha256_hex, verify_hmac_sha256_hex};
+use axum::http::HeaderMap;
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+pub const ORIGIN_TOKEN_HEADER_NAME: &str = "X-Load-Origin-Token";
+
+// Decides whether a request skips rate limiting, which only signals the client
+// can't forge do. Origin / Referer and the Host header (when enabled with
+// WHITELIST_TRUST_HOST_HEADER) only pick the limited `browser` tier
+#[derive(Debug, Clone, Default)]
+pub struct Whitelist {
+    pub trust_host_header: bool,
+    pub hosts: Vec<String>,
+    // Origin / Referer patterns, only meaningful for browser traffic since any
+    // other client can set these headers freely
+    pub origins: Vec<String>,
+    pub cidrs: Vec<IpNet>,
+    pub mtls_identity_header: Option<String>,
+    pub mtls_identities: Vec<String>,
+    pub origin_token_secret: Option<Vec<u8>>,
+}
+
+impl Whitelist {
+    pub fn from_env() -> Self {
+        Self {
+            trust_host_header: std::env::var("WHITELIST_TRUST_HOST_HEADER")
+                .is_ok_and(|val| val == "true" || val == "1"),
+            hosts: whitelisted_urls(),
+            origins: env_var_to_vec("WHITELISTED_ORIGINS"),
+            cidrs: env_var_to_vec("WHITELISTED_CIDRS")
+                .iter()
+                .map(|cidr| {
+                    cidr.parse::<IpNet>()
+                        .or_else(|_| cidr.parse::<IpAddr>().map(IpNet::from))
+                        .unwrap_or_else(|e| {
+                            panic!("Invalid WHITELISTED_CIDRS entry {}: {}", cidr, e)
+                        })
+                })
+                .collect(),
+            mtls_identity_header: std::env::var("MTLS_IDENTITY_HEADER")
+                .ok()
+                .filter(|val| !val.trim().is_empty()),
+            mtls_identities: env_var_to_vec("WHITELISTED_MTLS_IDENTITIES"),
+            origin_token_secret: (!WHITELIST_TOKEN_SECRET.is_empty())
+                .then(|| WHITELIST_TOKEN_SECRET.as_bytes().to_vec()),
+        }
+    }
+
+    pub fn is_request_whitelisted(
+        &self,
+        peer: Option<IpAddr>,
+        headers: &HeaderMap,
+        trusted_proxies: &[IpNet],
+        now: u64,
+    ) -> bool {
+        if self.has_valid_origin_token(headers, now) {
+            return true;
+        }
+
+        if let Some(peer) = peer {
+            let client_ip = resolve_client_ip(peer, headers, trusted_proxies);
+            if self.cidrs.iter().any(|net| net.contains(&client_ip)) {
+                return true;
+            }
+
+            // the identity header is set by the TLS terminating proxy, anyone
+            // else could send it
+            if self.has_mtls_identity(peer, headers, trusted_proxies) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    // any client can send these headers, they are only a hint of browser
+    // traffic from a known site
+    pub fn is_browser_request(&self, headers: &HeaderMap) -> bool {
+        if !self.origins.is_empty() {
+            let origin = headers
+                .get("origin")
+                .or_else(|| headers.get("referer"))
+                .and_then(|h| h.to_str().ok())
+                .map(String::from);
+            if is_whitelisted(origin, &self.origins) {
+                return true;
+            }
+        }
+
+        if self.trust_host_header {
+            let host_header = headers
+                .get("host")
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or("");
+            return is_whitelisted(Some(format!("http://{}", host_header)), &self.hosts);
+        }
+
+        false
+    }
+
+    fn has_mtls_identity(
+        &self,
+        peer: IpAddr,
+        headers: &HeaderMap,
+        trusted_proxies: &[IpNet],
+    ) -> bool {
+        let Some(header) = &self.mtls_identity_header else {
+            return false;
+        };
+        if !trusted_proxies.iter().any(|net| net.contains(&peer)) {
+            return false;
+        }
+
+        headers
+            .get(header)
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|identity| self.mtls_identities.iter().any(|i| i == identity.trim()))
+    }
+
+    fn has_valid_origin_token(&self, headers: &HeaderMap, now: u64) -> bool {
+        let Some(secret) = &self.origin_token_secret else {
+            return false;
+        };
+
+        headers
+            .get(ORIGIN_TOKEN_HEADER_NAME)
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|token| verify_origin_token(secret, token, now).is_some())
+    }
+}
+
+// `{subject}:{expires_at}:{hex hmac-sha256("{subject}:{expires_at}")}`
+pub fn sign_origin_token(secret: &[u8], subject: &str, expires_at: u64) -> String {
+    let payload = format!("{}:{}", subject, expires_at);
+    let signature = hmac_sha256_hex(secret, &payload);
+    format!("{}:{}", payload, signature)
+}
+
+// returns the token subject if the signature is valid and it hasn't expired
+pub fn verify_origin_token(secret: &[u8], token: &str, now: u64) -> Option<String> {
+    let mut parts = token.trim().rsplitn(3, ':');
+    let signature = parts.next()?;
+    let expires_at = parts.next()?;
+    let subject = parts.next()?;
+
+    if expires_at.parse::<u64>().ok()? <= now {
+        return None;
+    }
+
+    let payload = format!("{}:{}", subject, expires_at);
+    verify_hmac_sha256_hex(secret, &payload, signature).then(|| subject.to_string())
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::server::whitelist::{ORIGIN_TOKEN_HEADER_NAME, Whitelist, sign_origin_token};
+    use axum::http::{HeaderMap, HeaderValue};
+    use ipnet::IpNet;
+    use std::net::IpAddr;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                axum::http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    pub fn test_whitelist_signals() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let proxy: Option<IpAddr> = Some("10.0.0.2".parse().unwrap());
+        let client: Option<IpAddr> = Some("203.0.113.9".parse().unwrap());
+        let now = 1_000;
+
+        let whitelist = Whitelist {
+            hosts: vec!["cloud.load.network".to_string()],
+            origins: vec!["*.load.network".to_string(), "relic.bot".to_string()],
+            cidrs: vec!["198.51.100.0/24".parse().unwrap()],
+            mtls_identity_header: Some("x-client-cert-subject".to_string()),
+            mtls_identities: vec!["CN=indexer".to_string()],
+            origin_token_secret: Some(b"secret".to_vec()),
+            ..Default::default()
+        };
+
+        // the Host header alone is not trusted unless opted in, and then only
+        // for the browser tier
+        let host = headers(&[("host", "cloud.load.network")]);
+        assert!(!whitelist.is_browser_request(&host));
+        let opted_in = Whitelist {
+            trust_host_header: true,
+            ..whitelist.clone()
+        };
+        assert!(opted_in.is_browser_request(&host));
+        assert!(!opted_in.is_request_whitelisted(client, &host, &trusted, now));
+
+        // forgeable headers never skip the limits
+        let origin = headers(&[("origin", "https://app.load.network")]);
+        assert!(whitelist.is_browser_request(&origin));
+        assert!(!whitelist.is_request_whitelisted(client, &origin, &trusted, now));
+        let referer = headers(&[("referer", "https://relic.bot/some/page")]);
+        assert!(whitelist.is_browser_request(&referer));
+        let lookalike = headers(&[("origin", "https://evil-load.network")]);
+        assert!(!whitelist.is_browser_request(&lookalike));
+
+        assert!(whitelist.is_request_whitelisted(
+            Some("198.51.100.7".parse().unwrap()),
+            &HeaderMap::new(),
+            &trusted,
+            now
+        ));
+        let forwarded = headers(&[("x-forwarded-for", "198.51.100.7")]);
+        assert!(whitelist.is_request_whitelisted(proxy, &forwarded, &trusted, now));
+        assert!(!whitelist.is_request_whitelisted(client, &forwarded, &trusted, now));
+
+        let mtls = headers(&[("x-client-cert-subject", "CN=indexer")]);
+        assert!(whitelist.is_request_whitelisted(proxy, &mtls, &trusted, now));
+        assert!(!whitelist.is_request_whitelisted(client, &mtls, &trusted, now));
+
+        let token = sign_origin_token(b"secret", "partner", now + 60);
+        let signed = headers(&[(ORIGIN_TOKEN_HEADER_NAME, token.as_str())]);
+        assert!(whitelist.is_request_whitelisted(client, &signed, &trusted, now));
+        assert!(!whitelist.is_request_whitelisted(client, &signed, &trusted, now + 60));
+
+        let forged = sign_origin_token(b"other", "partner", now + 60);
+        let forged = headers(&[(ORIGIN_TOKEN_HEADER_NAME, forged.as_str())]);
+        assert!(!whitelist.is_request_whitelisted(client, &forged, &trusted, now));
+    }
+}
@@ -1,4 +1,90 @@
+use crate::server::rate_limiter::trusted_proxies;
+use crate::utils::constants::SERVER_REQUEST_BODY_LIMIT;
+use ipnet::IpNet;
 use std::sync::LazyLock;
 
 pub static INTERNAL_KEY: LazyLock<String> =
     LazyLock::new(|| std::env::var("BYPASS_INTERNAL_KEY").unwrap_or("".to_string()));
+
+// opt-in so existing anonymous uploaders keep working, when enabled uploads
+// need an API key with the `upload` scope
+pub static UPLOAD_AUTH_REQUIRED: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var("UPLOAD_AUTH_REQUIRED")
+        .map(|val| val == "true" || val == "1")
+        .unwrap_or(false)
+});
+
+// CIDRs of the load balancers / proxies whose forwarding headers are trusted
+pub static TRUSTED_PROXIES: LazyLock<Vec<IpNet>> = LazyLock::new(trusted_proxies);
+
+// request body cap of uploads, can be raised beyond the 2GB default for
+// multipart uploads
+pub static MAX_UPLOAD_BYTES: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MAX_UPLOAD_BYTES")
+        .ok()
+        .and_then(|val| val.parse::<u64>().ok())
+        .filter(|bytes| *bytes > 0)
+        .unwrap_or(SERVER_REQUEST_BODY_LIMIT)
+});
+
+pub static IPV6_BUCKET_PREFIX: LazyLock<u8> = LazyLock::new(|| {
+    std::env::var("IPV6_BUCKET_PREFIX")
+        .ok()
+        .and_then(|val| val.parse::<u8>().ok())
+        .unwrap_or(64)
+});
+
+// signs the X-Load-Origin-Token tokens that put a client in the whitelisted tier
+pub static WHITELIST_TOKEN_SECRET: LazyLock<String> =
+    LazyLock::new(|| std::env::var("WHITELIST_TOKEN_SECRET").unwrap_or("".to_string()));
+
+// signs the presigned /download URLs of private objects
+pub static DOWNLOAD_SIGNING_SECRET: LazyLock<String> =
+    LazyLock::new(|| std::env::var("DOWNLOAD_SIGNING_SECRET").unwrap_or("".to_string()));
+
+// derives the per owner secrets that sign webhook deliveries, webhooks are
+// disabled when unset
+pub static WEBHOOK_SIGNING_SECRET: LazyLock<String> =
+    LazyLock::new(|| std::env::var("WEBHOOK_SIGNING_SECRET").unwrap_or("".to_string()));
+
+// webhooks to localhost and private networks, e.g. for a local receiver
+pub static WEBHOOK_ALLOW_PRIVATE_URLS: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var("WEBHOOK_ALLOW_PRIVATE_URLS")
+        .map(|val| val == "true" || val == "1")
+        .unwrap_or(false)
+});
+
+// base of the URLs returned to clients, e.g. presigned download URLs
+pub static PUBLIC_URL: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("LOAD0_PUBLIC_URL")
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or("https://load0.network".to_string())
+});
+
+// Load Network JSON-RPC, for gas prices and the receipts of settlements
+pub static LOAD_RPC_URL: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("LOAD_RPC_URL").unwrap_or("https://alphanet.load.network".to_string())
+});
+
+// fixed gas price of settlement estimates, 0 reads it from LOAD_RPC_URL
+pub static SETTLEMENT_GAS_PRICE_WEI: LazyLock<u128> = LazyLock::new(|| {
+    std::env::var("SETTLEMENT_GAS_PRICE_WEI")
+        .ok()
+        .and_then(|val| val.parse::<u128>().ok())
+        .unwrap_or(0)
+});
+
+// TTL of ephemeral uploads without a `ttl`, and the longest one accepted
+pub static EPHEMERAL_DEFAULT_TTL_SECS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("EPHEMERAL_DEFAULT_TTL_SECS")
+        .ok()
+        .and_then(|val| val.parse::<u64>().ok())
+        .unwrap_or(7 * 24 * 60 * 60)
+});
+
+pub static EPHEMERAL_MAX_TTL_SECS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("EPHEMERAL_MAX_TTL_SECS")
+        .ok()
+        .and_then(|val| val.parse::<u64>().ok())
+        .unwrap_or(30 * 24 * 60 * 60)
+});
@@ -0,0 +1,79 @@
+use anyhow::{Error, anyhow};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::io::{Read, Write};
+
+pub const COMPRESSION_NONE: &str = "";
+pub const COMPRESSION_ZSTD: &str = "zstd";
+pub const COMPRESSION_GZIP: &str = "gzip";
+
+const ZSTD_LEVEL: i32 = 3;
+
+pub fn is_supported(algorithm: &str) -> bool {
+    matches!(
+        algorithm,
+        COMPRESSION_NONE | COMPRESSION_ZSTD | COMPRESSION_GZIP
+    )
+}
+
+pub fn compress(algorithm: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+    match algorithm {
+        COMPRESSION_NONE => Ok(data.to_vec()),
+        COMPRESSION_ZSTD => Ok(zstd::encode_all(data, ZSTD_LEVEL)?),
+        COMPRESSION_GZIP => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data)?;
+            Ok(encoder.finish()?)
+        }
+        _ => Err(anyhow!("Unsupported compression {}", algorithm)),
+    }
+}
+
+pub fn decompress(algorithm: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+    match algorithm {
+        COMPRESSION_NONE => Ok(data.to_vec()),
+        COMPRESSION_ZSTD => Ok(zstd::decode_all(data)?),
+        COMPRESSION_GZIP => {
+            let mut decoded = Vec::new();
+            GzDecoder::new(data).read_to_end(&mut decoded)?;
+            Ok(decoded)
+        }
+        _ => Err(anyhow!("Unsupported compression {}", algorithm)),
+    }
+}
+
+// whether an Accept-Encoding header allows `encoding`, e.g. `gzip, zstd;q=0.5`
+pub fn accepts_encoding(accept_encoding: &str, encoding: &str) -> bool {
+    accept_encoding.split(',').any(|entry| {
+        let mut params = entry.split(';').map(str::trim);
+        let name = params.next().unwrap_or("");
+        let q = params
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        (name.eq_ignore_ascii_case(encoding) || name == "*") && q > 0.0
+    })
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::utils::compression::{
+        COMPRESSION_GZIP, COMPRESSION_ZSTD, accepts_encoding, compress, decompress,
+    };
+
+    #[test]
+    pub fn test_compression_round_trip() {
+        let data = br#"{"hello": "load0"}"#.repeat(100);
+        for algorithm in [COMPRESSION_ZSTD, COMPRESSION_GZIP] {
+            let compressed = compress(algorithm, &data).unwrap();
+            assert!(compressed.len() < data.len());
+            assert_eq!(decompress(algorithm, &compressed).unwrap(), data);
+        }
+
+        assert!(accepts_encoding("gzip, deflate, br, zstd", "zstd"));
+        assert!(accepts_encoding("GZIP;q=0.8", "gzip"));
+        assert!(!accepts_encoding("gzip;q=0, br", "gzip"));
+        assert!(!accepts_encoding("identity", "zstd"));
+    }
+}
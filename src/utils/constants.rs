@@ -1,3 +1,26 @@
-pub const SERVER_REQUEST_BODY_LIMIT: usize = 2 * 1000 * 1024 * 1024; // 2GB
+pub const SERVER_REQUEST_BODY_LIMIT: u64 = 2 * 1000 * 1024 * 1024; // 2GB
 pub const FOUR_MB: u32 = 4 * 1024 * 1024;
 pub const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000000000000000000000000000";
+pub const DEFAULT_LIST_LIMIT: u32 = 100;
+pub const MAX_LIST_LIMIT: u32 = 1000;
+pub const MAX_MULTIPART_FILES: usize = 100;
+pub const MAX_DIRECTORY_FILES: usize = 1000;
+pub const SETTLEMENT_LATENCY_WINDOW_SECS: u64 = 24 * 60 * 60;
+pub const FAST_LANE_MAX_BYTES: u64 = FOUR_MB as u64;
+pub const HUGE_LANE_MIN_BYTES: u64 = 100 * 1024 * 1024;
+pub const MAX_SETTLEMENT_CANDIDATES: u32 = 1000;
+// the upload request settles permanent-now bundles, the queue only retries
+// them once it has timed out
+pub const PERMANENT_NOW_SETTLE_GRACE_SECS: u64 = 60 * 60;
+// a settlement refreshes its claim on the bundle while it runs, one not
+// refreshed for the timeout was left by a settlement that died
+pub const SETTLE_CLAIM_REFRESH_SECS: u64 = 60;
+pub const SETTLE_CLAIM_TIMEOUT_SECS: u64 = 10 * 60;
+// the first retry delay of a bundle that failed or has to wait, doubled on
+// every attempt up to the max
+pub const SETTLE_RETRY_BASE_SECS: u64 = 60;
+pub const SETTLE_RETRY_MAX_SECS: u64 = 60 * 60;
+// the settlement loop polls the queue again right away after a settlement,
+// and less often while it is empty
+pub const SETTLEMENT_BUSY_DELAY_SECS: u64 = 5;
+pub const SETTLEMENT_IDLE_DELAY_SECS: u64 = 60;
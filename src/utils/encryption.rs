@@ -0,0 +1,187 @@
+use crate::utils::get_env::env_var_to_vec;
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{Error, anyhow};
+
+pub const ENCRYPTION_NONE: &str = "";
+// the client uploads an already encrypted envelope, load0 only stores its metadata
+pub const ENCRYPTION_CLIENT: &str = "client";
+// load0 encrypts with a per-object data key wrapped by a KEK
+pub const ENCRYPTION_SERVER: &str = "server";
+pub const SERVER_ALGORITHM: &str = "AES-256-GCM";
+
+pub const ENCRYPTION_HEADER: &str = "X-Load-Encryption";
+pub const ENCRYPTION_ALGORITHM_HEADER: &str = "X-Load-Encryption-Algorithm";
+pub const ENCRYPTION_KEY_ID_HEADER: &str = "X-Load-Encryption-Key-Id";
+pub const WRAPPED_KEY_HEADER: &str = "X-Load-Wrapped-Key";
+pub const ENCRYPTION_NONCE_HEADER: &str = "X-Load-Encryption-Nonce";
+
+const NONCE_SIZE: usize = 12;
+
+pub struct Kek {
+    pub id: String,
+    cipher: Aes256Gcm,
+}
+
+// ENCRYPTION_KEKS is a comma separated `{key_id}:{hex 32 bytes}` list, the
+// first KEK wraps new objects and the others are kept to unwrap older ones
+pub struct KeyRing {
+    keks: Vec<Kek>,
+}
+
+pub struct EncryptedObject {
+    pub ciphertext: Vec<u8>,
+    pub key_id: String,
+    pub wrapped_key: String,
+    pub nonce: String,
+}
+
+impl KeyRing {
+    pub fn from_env() -> Result<Self, Error> {
+        let keks = env_var_to_vec("ENCRYPTION_KEKS")
+            .iter()
+            .map(|entry| {
+                let (id, key) = entry
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("ENCRYPTION_KEKS entries must be `key_id:hex_key`"))?;
+                Kek::new(id, &hex::decode(key)?)
+            })
+            .collect::<Result<Vec<Kek>, Error>>()?;
+
+        Ok(Self { keks })
+    }
+
+    pub fn new(keks: Vec<Kek>) -> Self {
+        Self { keks }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.keks.is_empty()
+    }
+
+    fn kek(&self, key_id: &str) -> Option<&Kek> {
+        self.keks.iter().find(|kek| kek.id == key_id)
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<EncryptedObject, Error> {
+        let kek = self
+            .keks
+            .first()
+            .ok_or_else(|| anyhow!("Server-side encryption is not configured"))?;
+
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        let nonce = Aes256Gcm::generate_nonce(OsRng);
+        let ciphertext = Aes256Gcm::new(&data_key)
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| anyhow!("Error encrypting object"))?;
+
+        // the wrapped key is stored as hex(nonce || wrapped data key)
+        let wrap_nonce = Aes256Gcm::generate_nonce(OsRng);
+        let wrapped = kek
+            .cipher
+            .encrypt(&wrap_nonce, &data_key[..])
+            .map_err(|_| anyhow!("Error wrapping data key"))?;
+
+        Ok(EncryptedObject {
+            ciphertext,
+            key_id: kek.id.clone(),
+            wrapped_key: hex::encode([&wrap_nonce[..], &wrapped].concat()),
+            nonce: hex::encode(nonce),
+        })
+    }
+
+    pub fn decrypt(
+        &self,
+        ciphertext: &[u8],
+        key_id: &str,
+        wrapped_key: &str,
+        nonce: &str,
+    ) -> Result<Vec<u8>, Error> {
+        let kek = self
+            .kek(key_id)
+            .ok_or_else(|| anyhow!("Unknown KEK {}", key_id))?;
+
+        let wrapped_key = hex::decode(wrapped_key)?;
+        if wrapped_key.len() <= NONCE_SIZE {
+            return Err(anyhow!("Invalid wrapped key"));
+        }
+        let (wrap_nonce, wrapped) = wrapped_key.split_at(NONCE_SIZE);
+        let data_key = kek
+            .cipher
+            .decrypt(&to_nonce(wrap_nonce)?, wrapped)
+            .map_err(|_| anyhow!("Error unwrapping data key"))?;
+        let cipher =
+            Aes256Gcm::new_from_slice(&data_key).map_err(|_| anyhow!("Invalid data key"))?;
+
+        cipher
+            .decrypt(&to_nonce(&hex::decode(nonce)?)?, ciphertext)
+            .map_err(|_| anyhow!("Error decrypting object"))
+    }
+}
+
+impl Kek {
+    pub fn new(id: &str, key: &[u8]) -> Result<Self, Error> {
+        if id.is_empty() || key.len() != 32 {
+            return Err(anyhow!("A KEK needs an id and a 32 bytes key"));
+        }
+
+        Ok(Self {
+            id: id.to_string(),
+            cipher: Aes256Gcm::new_from_slice(key).map_err(|_| anyhow!("Invalid KEK"))?,
+        })
+    }
+}
+
+fn to_nonce(bytes: &[u8]) -> Result<Nonce<U12>, Error> {
+    let bytes: [u8; NONCE_SIZE] = bytes.try_into().map_err(|_| anyhow!("Invalid nonce"))?;
+    Ok(Nonce::from(bytes))
+}
+
+// envelope metadata sent by clients ends up in SQL and response headers
+pub fn is_valid_envelope_value(value: &str, max_len: usize) -> bool {
+    value.len() <= max_len
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+/=_-.:".contains(c))
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use crate::utils::encryption::{Kek, KeyRing};
+
+    #[test]
+    pub fn test_key_ring_round_trip() {
+        let old = KeyRing::new(vec![Kek::new("old", &[1u8; 32]).unwrap()]);
+        let encrypted = old.encrypt(b"hello load0").unwrap();
+        assert_ne!(encrypted.ciphertext, b"hello load0");
+        assert_eq!(encrypted.key_id, "old");
+
+        // a rotated ring still unwraps keys of the previous KEK
+        let rotated = KeyRing::new(vec![
+            Kek::new("new", &[2u8; 32]).unwrap(),
+            Kek::new("old", &[1u8; 32]).unwrap(),
+        ]);
+        let plaintext = rotated
+            .decrypt(
+                &encrypted.ciphertext,
+                &encrypted.key_id,
+                &encrypted.wrapped_key,
+                &encrypted.nonce,
+            )
+            .unwrap();
+        assert_eq!(plaintext, b"hello load0");
+
+        let wrong = KeyRing::new(vec![Kek::new("old", &[3u8; 32]).unwrap()]);
+        assert!(
+            wrong
+                .decrypt(
+                    &encrypted.ciphertext,
+                    &encrypted.key_id,
+                    &encrypted.wrapped_key,
+                    &encrypted.nonce,
+                )
+                .is_err()
+        );
+    }
+}
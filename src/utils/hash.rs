@@ -1,3 +1,5 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 use sha3::{Digest, Keccak256};
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -14,3 +16,34 @@ pub fn generate_pseudorandom_keccak_hash() -> String {
 
     format!("0x{}", hash_hex)
 }
+
+pub fn keccak_hex(data: &[u8]) -> String {
+    let mut hasher = Keccak256::new();
+    hasher.update(data);
+    format!("0x{}", hex::encode(hasher.finalize()))
+}
+
+// 244 random bits from two v4 uuids
+pub fn generate_api_key() -> String {
+    format!(
+        "load_{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+pub fn hmac_sha256_hex(secret: &[u8], message: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(message.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// constant time comparison of a hex encoded signature
+pub fn verify_hmac_sha256_hex(secret: &[u8], message: &str, signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(message.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
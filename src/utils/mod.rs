@@ -1,5 +1,6 @@
-pub mod auth;
+pub mod compression;
 pub mod constants;
+pub mod encryption;
 pub mod get_env;
 pub mod hash;
 pub mod urls;